@@ -1,6 +1,6 @@
 #![allow(non_snake_case)]
 #![allow(dead_code)]
-use std::io::Write;
+use vaccination_model::Sink;
 
 extern crate covid19_vaccine_model;
 use covid19_vaccine_model::*;
@@ -263,14 +263,14 @@ fn main() {
 	let t0: f64 = 9.*7.;			// t0=0 corresponds to the beginning of the vaccination programs (last week of December for Europe)
 	let T:  f64 = 350.0;			// total integration time
 	
-	model.initialize();		// adds the subpopulations
-	model.prepare_vaccination_rates(((t0+T)/7.0).ceil() as usize +10, 0.8);	// prepares the vaccination rates for 50 weeks in advance
+	model.initialize().expect("Invalid model parameters");		// adds the subpopulations
+	model.prepare_vaccination_rates(((t0+T)/7.0).ceil() as usize +10, 0.8).expect("Preparing the vaccination rates failed");	// prepares the vaccination rates for 50 weeks in advance
 
 	let mut initials = Vec::<vm::AgeGroupStateVector>::with_capacity(6);
 
 	for i in 0..6 {
-		let (vaccinated, vaccinated2) = model.vaccinated_between(0.0, t0, i);
-		let (in_V1, in_V2) = model.vaccinated_between(t0-model.tau, t0, i);
+		let (vaccinated, vaccinated2) = model.vaccinated_between(0.0, t0, i).expect("Vaccination rates not prepared");
+		let (in_V1, in_V2) = model.vaccinated_between(t0-model.tau, t0, i).expect("Vaccination rates not prepared");
 		
 		initials.push(vm::AgeGroupStateVector::create_initial(model.age_groups[i].M, seroprevalence, vaccinated, (vaccinated2/vaccinated).max(0.0), model.eta0, in_V1, in_V2, in_EI*age_distribution_EI[i], in_ICU*age_distribution_ICU[i]));
 	}
@@ -282,13 +282,17 @@ fn main() {
 		model: model,
 		t0: t0,
 		dt: 1e-2,
+		integrator: vm::Integrator::RK4,
+		observation: vm::ObservationModel::default(),
 		initials: initials,
 		time: Vec::with_capacity(N),
 		Rt: Vec::with_capacity(N),
-		states: Vec::with_capacity(N),
+		states: vm::States::with_capacity(6, N),
 		N: Vec::with_capacity(N),
 		N_obs: Vec::with_capacity(N),
-		index: 0
+		index: 0,
+		events: Vec::new(),
+		event_log: Vec::new()
 	};
 	
 	//// Population Immunity
@@ -296,6 +300,12 @@ fn main() {
 
 	let T = 600.0;	// To ensure the simulation runs long enough
 
+	// Medium case numbers (late lift), then measure how long the ICUs are at the capacity limit
+	let schedule = vm::Schedule::new(vec![
+		vm::Phase::new(vm::Target::ObservedCases, mod_case_numbers).until_time(237.).Rt_range(0.8, R_max).max_slope(0.02),
+		vm::Phase::new(vm::Target::ICUOccupancy, ICU_capacity).Rt_range(0.7, R_max).max_slope(0.03).measure_ICU_duration()
+	]).expect("Invalid schedule");
+
 	let eta_array:[f64;9]   = [0.90, 0.75, 0.75, 0.60, 0.60, 0.45, 0.45, 0.45, 0.60];
 	let kappa_array:[f64;9] = [0.90, 0.90, 0.75, 0.90, 0.75, 0.75, 0.60, 0.90, 0.60];
 
@@ -303,47 +313,54 @@ fn main() {
 	let max_uptake:f64 = 0.95;
 	let N:usize = 100;
 
-	let mut uptake = vec!(0.0f64; N);
-	let mut ICU_durations = vec!(0.0f64; N);
+	let uptake: Vec<f64> = (0..N).map(|i| min_uptake + (i as f64)/(N as f64) * (max_uptake-min_uptake)).collect();
 
-	for i in 0..9 {
-		let eta = eta_array[i];
-		let kappa = kappa_array[i];
-		println!("({}/{})eta={} \t kappa={}", i+1, eta_array.len(), eta, kappa);
-		
+	// Models for the efficacy combinations (prepared one after the other on the same model)
+	let models: Vec<vm::Model> = eta_array.iter().zip(kappa_array.iter()).map(|(eta, kappa)| {
 		solver.model.eta0 = 1. - (1.-eta).sqrt();
 		solver.model.kappa0 = 1. - (1.-kappa)/(1.-eta);
-		solver.model.initialize();
+		solver.model.initialize().expect("Invalid model parameters");
+		solver.model.clone()
+	}).collect();
+
+	// Run all combinations of efficacies and uptakes in parallel, each with an own solver
+	let runs: Vec<(usize, usize)> = (0..eta_array.len()).flat_map(|c| (0..N).map(move |i| (c, i))).collect();
+	let sweep = vm::Sweep::new().print_progress("Runs");
+	let ICU_durations = sweep.run(&runs, |&(c, i)| {
+		let mut solver = solver.clone();
+		solver.model = models[c].clone();
+		solver.model.prepare_vaccination_rates(((t0+T)/7.0).ceil() as usize +10, uptake[i]).expect("Preparing the vaccination rates failed");	// prepares the vaccination rates for 50 weeks in advance
+
+		let mut initials = Vec::<vm::AgeGroupStateVector>::with_capacity(6);
+
+		for i in 0..6 {
+			let (vaccinated, vaccinated2) = solver.model.vaccinated_between(0.0, t0, i).expect("Vaccination rates not prepared");
+			let (in_V1, in_V2) = solver.model.vaccinated_between(t0-solver.model.tau, t0, i).expect("Vaccination rates not prepared");
+			
+			initials.push(vm::AgeGroupStateVector::create_initial(solver.model.age_groups[i].M, seroprevalence, vaccinated, (vaccinated2/vaccinated).max(0.0), solver.model.eta0, in_V1, in_V2, in_EI*age_distribution_EI[i], in_ICU*age_distribution_ICU[i]));
+		}
 
+		solver.initials = initials;
+		solver.initialize().expect("Initializing the solver failed");
+		solver.controlled_run(T, &schedule).expect("Running the scenario failed")
+	});
+
+	let mut sink = vm::Directory::new("data");
+	for c in 0..eta_array.len() {
+		let eta = eta_array[c];
+		let kappa = kappa_array[c];
+		let ICU_durations = &ICU_durations[c*N..(c+1)*N];
+		println!("({}/{})eta={} \t kappa={}", c+1, eta_array.len(), eta, kappa);
 		for i in 0..N {
-			uptake[i] = min_uptake + (i as f64)/(N as f64) * (max_uptake-min_uptake);
-
-
-			solver.model.prepare_vaccination_rates(((t0+T)/7.0).ceil() as usize +10, uptake[i]);	// prepares the vaccination rates for 50 weeks in advance
-
-			let mut initials = Vec::<vm::AgeGroupStateVector>::with_capacity(6);
-
-			for i in 0..6 {
-				let (vaccinated, vaccinated2) = solver.model.vaccinated_between(0.0, t0, i);
-				let (in_V1, in_V2) = solver.model.vaccinated_between(t0-solver.model.tau, t0, i);
-				
-				initials.push(vm::AgeGroupStateVector::create_initial(solver.model.age_groups[i].M, seroprevalence, vaccinated, (vaccinated2/vaccinated).max(0.0), solver.model.eta0, in_V1, in_V2, in_EI*age_distribution_EI[i], in_ICU*age_distribution_ICU[i]));
-			}
-
-			solver.initials = initials;
-			solver.initialize();
-			ICU_durations[i] = solver.controlled_run(T, &[(237., 0.8, R_max, 0.02, 0, mod_case_numbers), (t0+T+280., 0.7, R_max, 0.03, 2, ICU_capacity)]);
 			println!("\tuptake: {:.2} \t full ICUs for {:.2} days", uptake[i], ICU_durations[i]);
 		}
 
-
-
-		// Write the results
 		println!("Writing...");
 		let to_write = uptake.iter().zip(ICU_durations.iter()).map(|n| format!("{:.4} \t {:.4}", n.0, n.1)).collect::<Vec<String>>().join("\n");
-		let filename = format!("data/durations_{}_{}_eta{:02.0}_kappa{:02.0}_Rmax{:1.1}.data", country, contacts, eta*100., kappa*100., R_max);
-		let mut file = std::fs::File::create(filename).expect("create failed");
-		file.write_all("uptake \t ICU_duration\n".as_bytes()).expect("write failed");
-		writeln!(file, "{}", to_write).expect("write failed");
+		let filename = format!("durations_{}_{}_eta{:02.0}_kappa{:02.0}_Rmax{:1.1}.data", country, contacts, eta*100., kappa*100., R_max);
+		sink.write_file(&filename, &mut |file| {
+			file.write_all("uptake \t ICU_duration\n".as_bytes())?;
+			writeln!(file, "{}", to_write)
+		}).expect("Writing failed");
 	}
 }
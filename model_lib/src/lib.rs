@@ -7,8 +7,33 @@ pub mod vaccination_model{
 	//! # How it is organized:
 	//! 
 	//!	All the model parameters and equations are included in the submodule _model_.
-	//! The _solver_ submodule includes the Runge-Kutta 4 solver and the PD control system and can write the numerical solutions
-	//! to a folder. The submodule _age\_group\_state\_vector_ implements some data structures to store the system state.
+	//! The _solver_ submodule includes the Runge-Kutta 4 solver and the control system and can write the numerical solutions
+	//! to a folder. The solver is generic over the trait _DelaySystem_ from the submodule _delay\_system_, so model variants can reuse it.
+	//! The other submodules:
+	//! - _age\_group\_state\_vector_: data structures to store the system state
+	//! - _stochastic_: tau-leaping counterpart of the dif. eqs. for small populations
+	//! - _random_: random number generator
+	//! - _adaptive_: adaptive step size solver that can be selected instead of Runge-Kutta 4
+	//! - _checkpoint_: saves and restores the complete solver state
+	//! - _events_: conditions on the state (threshold crossings, extrema) detected during a run
+	//! - _controller_: control laws (PD, PID, bang-bang)
+	//! - _mpc_: model predictive control
+	//! - _schedule_: typed phases describing the scenarios for _controlled\_run_
+	//! - _target_: the quantities the phases can aim at
+	//! - _observation_: how these quantities are reported (delays, ascertainment, weekday effects)
+	//! - _surveillance_: noisy synthetic surveillance data
+	//! - _estimation_: maximum likelihood fits of model parameters to observed data
+	//! - _mcmc_: sampling of the posterior distribution of model parameters
+	//! - _filter_: sequential assimilation of new data with a particle filter
+	//! - _sensitivity_: global sensitivity analyses (Morris, Sobol) of scenario outputs
+	//! - _ensemble_: uncertainty ensembles with quantile bands
+	//! - _sweep_: runs independent solvers in parallel
+	//! - _gradient_: exact gradients of runs with respect to model parameters (dual numbers)
+	//! - _output_: tables written as tab separated text, CSV, JSON or Parquet files
+	//! - _error_: the _Result_ type of fallible functions (invalid parameters, exhausted vaccination schedules, I/O failures)
+	//! - _sink_: destinations of the files (e.g. a directory with file name templates or an in-memory buffer)
+	//! - _manifest_: self-describing JSON record of a run (with the library version) to repeat it
+	//! - _summary_: summary metrics of finished runs (deaths, ICU load, infections, freedom)
 	//!
 	//! # How to use it:
 	//! 1. create the model with the global parameters and add the individual age groups to it
@@ -17,10 +42,57 @@ pub mod vaccination_model{
 	//! 4. initialize the solver
 	//! 5. run the solver using a specified scenario
 	//! 6. write the data to a file
+	mod error;
+	pub use error::{Error, Result};
 	mod model;
 	pub use model::{Model, AgeGroup};
+	mod delay_system;
+	pub use delay_system::{DelaySystem, StateVector};
+	mod controller;
+	pub use controller::{Controller, PD, PID, BangBang};
+	mod mpc;
+	pub use mpc::MPC;
+	mod schedule;
+	pub use schedule::{Schedule, Phase, Until, ScheduleError};
+	mod target;
+	pub use target::Target;
+	mod observation;
+	pub use observation::{ObservationModel, Holiday, discretized_gamma, ADMISSION_KERNEL, DEATH_KERNEL};
+	mod surveillance;
+	pub use surveillance::{SurveillanceGenerator, SurveillanceData, Noise, Aggregation};
+	mod estimation;
+	pub use estimation::{Estimation, Estimate, Parameter, Likelihood, Observed};
+	mod mcmc;
+	pub use mcmc::{MCMC, Posterior, Prior, Uncertain};
+	mod filter;
+	pub use filter::{ParticleFilter, Filtered};
+	mod sensitivity;
+	pub use sensitivity::{Factor, Output, Morris, MorrisResult, Sobol, SobolResult};
+	mod ensemble;
+	pub use ensemble::{EnsembleDesign, ScenarioEnsemble, Sampling, QUANTILES};
+	mod summary;
+	pub use summary::Summary;
+	mod sweep;
+	pub use sweep::Sweep;
 	mod solver;
-	pub use solver::Solver;
+	pub use solver::{Solver, Integrator};
+	mod output;
+	pub use output::{Table, TableKind, Column, Values, Format, Tsv, Csv, Json, Parquet, write_tables};
+	mod sink;
+	pub use sink::{Sink, Directory, Memory, Overwrite};
+	mod adaptive;
+	mod gradient;
+	pub use gradient::{Dual, Scalar, DualModel, ModelParameter};
 	mod age_group_state_vector;
-	pub use age_group_state_vector::AgeGroupStateVector;
+	pub use age_group_state_vector::{AgeGroupStateVector, States};
+	mod random;
+	pub use random::Rng;
+	mod events;
+	pub use events::{Event, EventRecord, Condition, Action, Direction};
+	mod checkpoint;
+	pub use checkpoint::Checkpoint;
+	mod manifest;
+	pub use manifest::Manifest;
+	mod stochastic;
+	pub use stochastic::StochasticEnsemble;
 }
\ No newline at end of file
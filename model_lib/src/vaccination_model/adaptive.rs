@@ -0,0 +1,217 @@
+//! Adaptive step size solver for the delay dif. eqs.
+//!
+//! The fixed step RK4 solver needs a small dt for the whole run and looks up the delayed state at the closest grid point.
+//! Here the embedded Bogacki-Shampine 3(2) pair is used instead: every step is accepted or rejected depending on the estimated local error
+//! and the step size is adapted accordingly. The accepted steps are kept as cubic Hermite polynomials (dense output), which are used both to
+//! write the results on the usual output grid with spacing dt and to evaluate the delayed state at $t-\tau$ for arbitrary times. Delayed
+//! times before the start of the current run are interpolated from the solver history.
+//!
+//! The right hand side is only piecewise smooth: the vaccination rates change weekly and $R_t$ changes daily (also delayed by $\tau$). Steps
+//! therefore never cross whole days or whole days shifted by $\tau$.
+
+use crate::vaccination_model::age_group_state_vector::States;
+use crate::vaccination_model::delay_system::{DelaySystem, StateVector};
+use crate::vaccination_model::error::Result;
+use crate::vaccination_model::solver::Solver;
+
+/// Smallest step size the adaptive solver may use before accepting a step regardless of its error estimate
+const MIN_STEP: f64 = 1e-6;
+
+/// One accepted step of the adaptive solver, stored as cubic Hermite polynomial for the dense output
+struct Segment<S> {
+	/// Start time of the step
+	t: f64,
+	/// Step size
+	h: f64,
+	/// State and slope at the beginning of the step
+	y0: Vec<S>,
+	f0: Vec<S>,
+	/// State and slope at the end of the step
+	y1: Vec<S>,
+	f1: Vec<S>
+}
+
+impl<S: StateVector> Segment<S> {
+	/// Evaluates the Hermite polynomial at time t (clamped to the step)
+	fn evaluate(&self, t: f64) -> Vec<S> {
+		let theta = ((t - self.t)/self.h).clamp(0.0, 1.0);
+		let c_y0 = 2.0*theta.powi(3) - 3.0*theta.powi(2) + 1.0;
+		let c_y1 = 3.0*theta.powi(2) - 2.0*theta.powi(3);
+		let c_f0 = (theta.powi(3) - 2.0*theta.powi(2) + theta)*self.h;
+		let c_f1 = (theta.powi(3) - theta.powi(2))*self.h;
+		linear_combination(&[(c_y0, &self.y0), (c_y1, &self.y1), (c_f0, &self.f0), (c_f1, &self.f1)])
+	}
+}
+
+/// Returns $\sum_k c_k x_k$ for a list of factors and state vectors.
+fn linear_combination<S: StateVector>(terms: &[(f64, &[S])]) -> Vec<S> {
+	let mut result: Vec<S> = terms[0].1.to_vec();
+	for r in result.iter_mut() {
+		r.apply([], |x, []| x*terms[0].0);
+	}
+	for (factor, vector) in terms.iter().skip(1) {
+		for (r, ag) in result.iter_mut().zip(vector.iter()) {
+			r.apply([ag], |r, [x]| r + x*factor);
+		}
+	}
+	result
+}
+
+/// Access to the system state and $R_t$ at arbitrary past times during an adaptive run
+struct History<'a, S> {
+	time: &'a [f64],
+	Rt: &'a [f64],
+	states: &'a States<S>,
+	/// Start of the current run and its (constant) $R_t$
+	t_start: f64,
+	R: f64,
+	/// Accepted steps of the current run
+	segments: Vec<Segment<S>>
+}
+
+impl<'a, S: StateVector> History<'a, S> {
+	/// System state at time t: from the dense output if t lies in the current run, else interpolated (cubic Lagrange) from the solver history.
+	/// Before the beginning of the history the first state is returned.
+	fn state(&self, t: f64) -> Vec<S> {
+		if t >= self.t_start && !self.segments.is_empty() {
+			let k = self.segments.partition_point(|s| s.t + s.h < t).min(self.segments.len()-1);
+			return self.segments[k].evaluate(t);
+		}
+
+		let len = self.time.len();
+		if t <= self.time[0] || len < 4 {
+			let k = self.time.partition_point(|&x| x <= t).max(1) - 1;
+			return self.states[k].to_vec();
+		}
+		let k = self.time.partition_point(|&x| x <= t).max(1) - 1;
+		let first = k.max(1).min(len-3) - 1;	// stencil first..first+4 around t
+		let nodes = &self.time[first..first+4];
+		let weights: Vec<f64> = (0..4).map(|j| {
+			(0..4).filter(|&m| m != j).map(|m| (t - nodes[m])/(nodes[j] - nodes[m])).product()
+		}).collect();
+		linear_combination(&[(weights[0], &self.states[first]), (weights[1], &self.states[first+1]), (weights[2], &self.states[first+2]), (weights[3], &self.states[first+3])])
+	}
+
+	/// Raw $R_t$ at time t ($R_t$ is piecewise constant)
+	fn R(&self, t: f64) -> f64 {
+		if t >= self.t_start {
+			return self.R;
+		}
+		self.Rt[self.time.partition_point(|&x| x <= t).max(1) - 1]
+	}
+}
+
+impl<M: DelaySystem> Solver<M> {
+	/// Solves the system of delay diff. eqs. for a timespan T with the adaptive Bogacki-Shampine 3(2) solver and the given relative and absolute
+	/// tolerances. The results are written on the grid with spacing dt, exactly like _run\_rk4_ does, so both can be used interchangeably.
+	///
+	/// Returns the index in the result arrays in the end for easy access. Fails if the timespan reaches beyond the model inputs (see _check\_horizon_).
+	#[allow(clippy::too_many_arguments)]
+	pub fn run_adaptive(&self, T: f64, time: &mut Vec<f64>, Rt: &mut Vec<f64>, states: &mut States<M::State>, N: &mut Vec<f64>,
+									   time_history: &[f64], Rt_history: &[f64], states_history: &States<M::State>, R: f64, rtol: f64, atol: f64) -> Result<usize> {
+		let tau = self.model.delay();
+		let history_index = time_history.len()-1;
+		let mut t = time_history[history_index];
+		self.check_until(t + T)?;
+		let mut state = states_history[history_index].to_vec();
+
+		let mut history = History { time: time_history, Rt: Rt_history, states: states_history, t_start: t, R, segments: Vec::new() };
+
+		// Output grid (accumulated like in run_rk4 to get identical time values)
+		let N_out = (T/self.dt) as usize;
+		let mut grid: Vec<f64> = Vec::with_capacity(N_out);
+		let mut t_grid = t;
+		for _ in 0..N_out {
+			t_grid += self.dt;
+			grid.push(t_grid);
+		}
+		let t_end = match grid.last() { Some(&t_end) => t_end, None => return Ok(0) };
+
+		let mut h = self.dt.min(0.1);
+		let mut slope_start: Option<Vec<M::State>> = None;	// slope at t if it can be reused from the last step (FSAL)
+		let mut written = 0usize;
+
+		while written < N_out {
+			// Never step across a discontinuity of the right hand side or beyond the end
+			let breakpoint = (t.floor() + 1.0).min((t - tau).floor() + 1.0 + tau).min(t_end);
+			h = h.min(0.5*tau);
+			let at_breakpoint = t + h >= breakpoint - MIN_STEP;
+			if at_breakpoint {
+				h = breakpoint - t;
+			}
+			// evaluate the last stage just before a discontinuity, i.e. with the left limit of the right hand side
+			let t_last_stage = if at_breakpoint { t + h - 0.1*MIN_STEP } else { t + h };
+
+			let k1 = match slope_start.take() {
+				Some(k1) => k1,
+				None => self.slopes_at(t, R, &state, &history)
+			};
+			let y2 = linear_combination(&[(1.0, &state), (0.5*h, &k1)]);
+			let k2 = self.slopes_at(t + 0.5*h, R, &y2, &history);
+			let y3 = linear_combination(&[(1.0, &state), (0.75*h, &k2)]);
+			let k3 = self.slopes_at(t + 0.75*h, R, &y3, &history);
+			let y_new = linear_combination(&[(1.0, &state), (2.0/9.0*h, &k1), (1.0/3.0*h, &k2), (4.0/9.0*h, &k3)]);
+			let k4 = self.slopes_at(t_last_stage, R, &y_new, &history);
+
+			// Error estimate from the difference to the embedded second order solution
+			let error = linear_combination(&[(-5.0/72.0*h, &k1), (1.0/12.0*h, &k2), (1.0/9.0*h, &k3), (-1.0/8.0*h, &k4)]);
+			let error_norm = scaled_norm(&error, &state, &y_new, rtol, atol);
+
+			if error_norm <= 1.0 || h <= MIN_STEP {
+				let segment = Segment { t, h, y0: state, f0: k1, y1: y_new.clone(), f1: k4.clone() };
+
+				// Write all output grid points within this step
+				while written < N_out && grid[written] <= t + h + 0.5*MIN_STEP {
+					let output = segment.evaluate(grid[written]);
+					N.push(self.model.N(&output));
+					states.push(&output);
+					Rt.push(R);
+					time.push(grid[written]);
+					written += 1;
+				}
+
+				history.segments.push(segment);
+				t = if at_breakpoint { breakpoint } else { t + h };
+				state = y_new;
+				if !at_breakpoint {
+					slope_start = Some(k4);
+				}
+			} else {
+				slope_start = Some(k1);
+			}
+
+			// Standard step size control for a third order method
+			let factor = if error_norm > 0.0 { 0.9*error_norm.powf(-1.0/3.0) } else { 5.0 };
+			h = (h*factor.clamp(0.2, 5.0)).max(MIN_STEP);
+		}
+
+		// Return new end index
+		Ok(N_out - 1)
+	}
+
+	/// Slopes of the model at time t, taking the delayed arguments from the history
+	fn slopes_at(&self, t: f64, R: f64, state: &[M::State], history: &History<M::State>) -> Vec<M::State> {
+		let tau = self.model.delay();
+		let delayed_state = history.state(t - tau);
+		let mut delayed = M::Delayed::default();
+		self.model.delayed_terms(history.R(t - tau), &delayed_state, &mut delayed);
+		let mut slopes = vec![M::State::default(); state.len()];
+		self.model.slopes_into(t, R, state, &delayed_state, &delayed, &mut slopes);
+		slopes
+	}
+}
+
+/// Root mean square of the error relative to the tolerance $atol + rtol\max(|y|, |y_{new}|)$ over all compartments.
+fn scaled_norm<S: StateVector>(error: &[S], state: &[S], state_new: &[S], rtol: f64, atol: f64) -> f64 {
+	let mut sum = 0.0f64;
+	let mut count = 0usize;
+	for ((e, y), y_new) in error.iter().zip(state.iter()).zip(state_new.iter()) {
+		let mut scaled = *e;
+		scaled.apply([y, y_new], |e, [y, y_new]| e/(atol + rtol*y.abs().max(y_new.abs())));
+		scaled.for_each(|e| {
+			sum += e.powi(2);
+			count += 1;
+		});
+	}
+	(sum/count.max(1) as f64).sqrt()
+}
@@ -2,37 +2,40 @@
 //!
 //! The state of a single age group is stored in a struct AgeGroupStateVector.
 //! Two of which can be added, and one of them can be multiplied with a scalar factor. The whole state of the age stratified model at a given instant
-//! is then stored in a vector of them. Again, we include functions to add two of those vectors or multiply them with a factor
-//! as needed for the Runge Kutta algorithm.
+//! is then stored in a slice of them. For the Runge Kutta algorithm we include functions that combine those slices in place, so that no memory
+//! has to be allocated in the integration loop. Sequences of states (the results of a run) are stored contiguously in a struct States.
+//! Both work for any block state implementing _StateVector_, not only for AgeGroupStateVector.
+use crate::vaccination_model::delay_system::StateVector;
 use std::ops::Add;
 use std::ops::Mul;
 
-/// Used to store all the information on the state of one age group
-#[derive(Copy, Clone, Debug)]
-pub struct AgeGroupStateVector {
+/// Used to store all the information on the state of one age group. The compartments are f64 by default, with dual numbers
+/// (see _gradient_) they carry their derivatives with respect to model parameters as well.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct AgeGroupStateVector<T = f64> {
 	/// Array of the susceptible people (array entries: unvaccinated, immuized from one dose, immunized from two doses) 
-	pub S: [f64; 3],
+	pub S: [T; 3],
 
 	/// Array of the vaccinated, but not yet immunized people (array entries: first dose, second dose) 
-	pub V: [f64; 2],
+	pub V: [T; 2],
 
 	/// Array of the exposed people (array entries: unvaccinated, immuized from one dose, immunized from two doses) 
-	pub E: [f64; 3],
+	pub E: [T; 3],
 	
 	/// Array of the infectious people (array entries: unvaccinated, immuized from one dose, immunized from two doses)
-	pub I: [f64; 3],
+	pub I: [T; 3],
 
 	/// Array of the infected people in ICU (array entries: unvaccinated, immuized from one dose, immunized from two doses)
-	pub ICU: [f64; 3],
+	pub ICU: [T; 3],
 
 	/// Cumulative deaths 
-	pub D: f64,
+	pub D: T,
 	
 	/// Array of the susceptible people (array entries: unvaccinated, immuized from one dose, immunized from two doses) 
-	pub R: [f64; 3],
+	pub R: [T; 3],
 
 	/// 
-	pub h: f64
+	pub h: T
 }
 
 impl AgeGroupStateVector {
@@ -75,6 +78,43 @@ impl AgeGroupStateVector {
 			h: 0.0
 		}
 	}
+
+}
+
+impl<T: Copy> AgeGroupStateVector<T> {
+	/// Returns all compartments (in the order S, V, E, I, ICU, D, R, h) as one array, e.g. to iterate over all of them.
+	pub fn to_array(&self) -> [T; 19] {
+		[self.S[0], self.S[1], self.S[2], self.V[0], self.V[1], self.E[0], self.E[1], self.E[2], self.I[0], self.I[1], self.I[2],
+		 self.ICU[0], self.ICU[1], self.ICU[2], self.D, self.R[0], self.R[1], self.R[2], self.h]
+	}
+
+	/// Inverse of _to\_array_
+	pub fn from_array(x: [T; 19]) -> AgeGroupStateVector<T> {
+		AgeGroupStateVector { S: [x[0], x[1], x[2]], V: [x[3], x[4]], E: [x[5], x[6], x[7]], I: [x[8], x[9], x[10]],
+							  ICU: [x[11], x[12], x[13]], D: x[14], R: [x[15], x[16], x[17]], h: x[18] }
+	}
+}
+
+impl StateVector for AgeGroupStateVector {
+	#[inline(always)]
+	fn apply<const K: usize>(&mut self, others: [&AgeGroupStateVector; K], f: impl Fn(f64, [f64; K]) -> f64) {
+		for vacc in 0..3 {
+			self.S[vacc] = f(self.S[vacc], others.map(|o| o.S[vacc]));
+			self.E[vacc] = f(self.E[vacc], others.map(|o| o.E[vacc]));
+			self.I[vacc] = f(self.I[vacc], others.map(|o| o.I[vacc]));
+			self.ICU[vacc] = f(self.ICU[vacc], others.map(|o| o.ICU[vacc]));
+			self.R[vacc] = f(self.R[vacc], others.map(|o| o.R[vacc]));
+		}
+		for dose in 0..2 {
+			self.V[dose] = f(self.V[dose], others.map(|o| o.V[dose]));
+		}
+		self.D = f(self.D, others.map(|o| o.D));
+		self.h = f(self.h, others.map(|o| o.h));
+	}
+
+	fn for_each(&self, f: impl FnMut(f64)) {
+		self.to_array().iter().copied().for_each(f);
+	}
 }
 
 /// Implements the addition operation for two AgeGroupStateVector's
@@ -121,21 +161,94 @@ impl std::fmt::Display for AgeGroupStateVector {
     }
 }
 
-/// Adds two vectors of AgeGroupStateVector's.
+/// Sets _result_ to $x + a y$ for whole system states (all age groups), without allocating.
+pub fn axpy<S: StateVector>(result: &mut [S], x: &[S], a: f64, y: &[S]) {
+	for ((r, x), y) in result.iter_mut().zip(x.iter()).zip(y.iter()) {
+		*r = *x;
+		r.apply([y], |x, [y]| x + y*a);
+	}
+}
+
+/// Advances a whole system state in place by one Runge-Kutta 4 step, i.e. $x \to x + \frac{dt}{6}(k_1 + k_4 + 2(k_2 + k_3))$, given the four slopes.
+pub fn rk4_update<S: StateVector>(state: &mut [S], k1: &[S], k2: &[S], k3: &[S], k4: &[S], dt: f64) {
+	for ((((x, k1), k2), k3), k4) in state.iter_mut().zip(k1.iter()).zip(k2.iter()).zip(k3.iter()).zip(k4.iter()) {
+		x.apply([k1, k2, k3, k4], |x, [k1, k2, k3, k4]| x + ((k1 + k4) + (k2 + k3)*2.0)*(dt/6.0));
+	}
+}
+
+/// A sequence of system states (one AgeGroupStateVector per age group at each instant), stored in one contiguous buffer.
 ///
-/// Consumes the first vector in the process.
-pub fn add_vec(mut vec1: Vec<AgeGroupStateVector>, vec2: &Vec<AgeGroupStateVector>) -> Vec<AgeGroupStateVector> {
-	for i in 0..vec1.len() {
-		vec1[i] = vec1[i] + vec2[i].clone();
+/// Indexing with a time index returns the slice of all age groups at that instant, i.e. `states[j][i]` is age group i at time index j.
+#[derive(Clone, Debug, Default)]
+pub struct States<S = AgeGroupStateVector> {
+	/// All states, one instant after the other
+	data: Vec<S>,
+	/// Number of age groups per instant (set by the first push if zero)
+	N_age_groups: usize
+}
+
+impl<S: Copy> States<S> {
+	/// Creates an empty sequence with room for _capacity_ instants of _N\_age\_groups_ age groups each
+	pub fn with_capacity(N_age_groups: usize, capacity: usize) -> States<S> {
+		States { data: Vec::with_capacity(N_age_groups*capacity), N_age_groups }
+	}
+
+	/// Number of stored instants
+	pub fn len(&self) -> usize {
+		self.data.len().checked_div(self.N_age_groups).unwrap_or(0)
+	}
+
+	/// Whether no state is stored
+	pub fn is_empty(&self) -> bool {
+		self.data.is_empty()
+	}
+
+	/// Removes all states (keeping the allocated memory)
+	pub fn clear(&mut self) {
+		self.data.clear();
+	}
+
+	/// Shortens the sequence to the first _len_ instants
+	pub fn truncate(&mut self, len: usize) {
+		self.data.truncate(len*self.N_age_groups);
+	}
+
+	/// Removes the first _n_ instants
+	pub fn drain_front(&mut self, n: usize) {
+		self.data.drain(..n*self.N_age_groups);
+	}
+
+	/// Appends the state of all age groups at one instant
+	pub fn push(&mut self, state: &[S]) {
+		if self.N_age_groups == 0 {
+			self.N_age_groups = state.len();
+		}
+		assert_eq!(state.len(), self.N_age_groups, "State has the wrong number of age groups");
+		self.data.extend_from_slice(state);
+	}
+
+	/// Appends the instants in _range_ of another sequence
+	pub fn extend_from(&mut self, other: &States<S>, range: std::ops::Range<usize>) {
+		if self.N_age_groups == 0 {
+			self.N_age_groups = other.N_age_groups;
+		}
+		self.data.extend_from_slice(&other.data[range.start*other.N_age_groups..range.end*other.N_age_groups]);
+	}
+
+	/// The last stored state (if any)
+	pub fn last(&self) -> Option<&[S]> {
+		if self.is_empty() { None } else { Some(&self[self.len()-1]) }
+	}
+
+	/// Iterates over all stored instants
+	pub fn iter(&self) -> impl Iterator<Item = &[S]> {
+		self.data.chunks(self.N_age_groups.max(1))
 	}
-	vec1
 }
 
-/// Multiplies a vectors of AgeGroupStateVector's by a scalar factor.
-pub fn mul_vec(vec: &Vec<AgeGroupStateVector>, factor: f64) -> Vec<AgeGroupStateVector> {
-	let mut result = vec.clone();
-	for i in 0..vec.len() {
-		result[i] = result[i]*factor;
+impl<S> std::ops::Index<usize> for States<S> {
+	type Output = [S];
+	fn index(&self, index: usize) -> &[S] {
+		&self.data[index*self.N_age_groups..(index+1)*self.N_age_groups]
 	}
-	result
-}
\ No newline at end of file
+}
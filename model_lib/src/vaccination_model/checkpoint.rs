@@ -0,0 +1,465 @@
+//! Saving the complete solver and model state to a file and restoring it.
+//!
+//! A checkpoint contains everything a _Solver_ knows: the model parameters (after initialization, including the prepared vaccination rates),
+//! the solver settings, the event log and the full history of the results, which also serves as the delay history for the next run. Floating point numbers
+//! are stored as their exact bit patterns (little endian), so a restored solver continues _controlled\_run_ bit-identically once the same events
+//! are registered again. The events themselves are not saved (they contain closures); events that may only occur once and are already in the
+//! saved event log are not handled again (see _Event.once_).
+//!
+//! The format is a small binary format: the magic bytes _MAGIC_, a format version and then all fields in declaration order. Besides files on
+//! disk, checkpoints can be written to any _Sink_ (_save\_checkpoint\_to_) and read from any reader (_load\_checkpoint\_from_).
+
+use crate::vaccination_model::age_group_state_vector::{AgeGroupStateVector, States};
+use crate::vaccination_model::delay_system::DelaySystem;
+use crate::vaccination_model::error::{create_file, open_file, Result};
+use crate::vaccination_model::events::EventRecord;
+use crate::vaccination_model::model::{AgeGroup, Model};
+use crate::vaccination_model::observation::{Holiday, ObservationModel};
+use crate::vaccination_model::sink::Sink;
+use crate::vaccination_model::solver::{Integrator, Solver};
+use std::io::{Read, Write};
+
+/// Magic bytes at the beginning of every checkpoint file
+const MAGIC: &[u8; 8] = b"VMCHKPT\0";
+/// Version of the checkpoint format
+const VERSION: u64 = 3;
+
+/// Types that can be written to and read back from a checkpoint without any loss of information.
+pub trait Checkpoint: Sized {
+	/// Writes the value to _writer_
+	fn write_checkpoint<W: Write>(&self, writer: &mut W) -> std::io::Result<()>;
+	/// Reads a value written by _write\_checkpoint_ from _reader_
+	fn read_checkpoint<R: Read>(reader: &mut R) -> std::io::Result<Self>;
+}
+
+/// Error for files that are not (valid) checkpoints
+fn invalid_data(message: &str) -> std::io::Error {
+	std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string())
+}
+
+impl Checkpoint for u64 {
+	fn write_checkpoint<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+		writer.write_all(&self.to_le_bytes())
+	}
+	fn read_checkpoint<R: Read>(reader: &mut R) -> std::io::Result<u64> {
+		let mut bytes = [0u8; 8];
+		reader.read_exact(&mut bytes)?;
+		Ok(u64::from_le_bytes(bytes))
+	}
+}
+
+impl Checkpoint for usize {
+	fn write_checkpoint<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+		(*self as u64).write_checkpoint(writer)
+	}
+	fn read_checkpoint<R: Read>(reader: &mut R) -> std::io::Result<usize> {
+		let value = u64::read_checkpoint(reader)?;
+		std::convert::TryFrom::try_from(value).map_err(|_| invalid_data("Index too large for this platform"))
+	}
+}
+
+impl Checkpoint for i32 {
+	fn write_checkpoint<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+		writer.write_all(&self.to_le_bytes())
+	}
+	fn read_checkpoint<R: Read>(reader: &mut R) -> std::io::Result<i32> {
+		let mut bytes = [0u8; 4];
+		reader.read_exact(&mut bytes)?;
+		Ok(i32::from_le_bytes(bytes))
+	}
+}
+
+impl Checkpoint for f64 {
+	fn write_checkpoint<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+		self.to_bits().write_checkpoint(writer)
+	}
+	fn read_checkpoint<R: Read>(reader: &mut R) -> std::io::Result<f64> {
+		Ok(f64::from_bits(u64::read_checkpoint(reader)?))
+	}
+}
+
+impl<const K: usize> Checkpoint for [f64; K] {
+	fn write_checkpoint<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+		for value in self.iter() {
+			value.write_checkpoint(writer)?;
+		}
+		Ok(())
+	}
+	fn read_checkpoint<R: Read>(reader: &mut R) -> std::io::Result<[f64; K]> {
+		let mut values = [0.0f64; K];
+		for value in values.iter_mut() {
+			*value = f64::read_checkpoint(reader)?;
+		}
+		Ok(values)
+	}
+}
+
+impl Checkpoint for String {
+	fn write_checkpoint<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+		self.len().write_checkpoint(writer)?;
+		writer.write_all(self.as_bytes())
+	}
+	fn read_checkpoint<R: Read>(reader: &mut R) -> std::io::Result<String> {
+		let len = usize::read_checkpoint(reader)?;
+		let mut bytes = Vec::new();
+		reader.take(len as u64).read_to_end(&mut bytes)?;
+		if bytes.len() != len {
+			return Err(invalid_data("Unexpected end of checkpoint"));
+		}
+		String::from_utf8(bytes).map_err(|_| invalid_data("Invalid string in checkpoint"))
+	}
+}
+
+impl<T: Checkpoint> Checkpoint for Vec<T> {
+	fn write_checkpoint<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+		self.len().write_checkpoint(writer)?;
+		for value in self.iter() {
+			value.write_checkpoint(writer)?;
+		}
+		Ok(())
+	}
+	fn read_checkpoint<R: Read>(reader: &mut R) -> std::io::Result<Vec<T>> {
+		let len = usize::read_checkpoint(reader)?;
+		let mut values = Vec::new();	// no capacity from the file, a corrupted length should fail on reading, not on allocation
+		for _ in 0..len {
+			values.push(T::read_checkpoint(reader)?);
+		}
+		Ok(values)
+	}
+}
+
+impl<S: Checkpoint + Copy> Checkpoint for States<S> {
+	fn write_checkpoint<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+		let N_age_groups = self.last().map_or(0, |state| state.len());
+		N_age_groups.write_checkpoint(writer)?;
+		self.len().write_checkpoint(writer)?;
+		for state in self.iter() {
+			for age_group in state.iter() {
+				age_group.write_checkpoint(writer)?;
+			}
+		}
+		Ok(())
+	}
+	fn read_checkpoint<R: Read>(reader: &mut R) -> std::io::Result<States<S>> {
+		let N_age_groups = usize::read_checkpoint(reader)?;
+		let len = usize::read_checkpoint(reader)?;
+		let mut states = States::with_capacity(N_age_groups, 0);
+		let mut state = Vec::with_capacity(N_age_groups);
+		for _ in 0..len {
+			state.clear();
+			for _ in 0..N_age_groups {
+				state.push(S::read_checkpoint(reader)?);
+			}
+			states.push(&state);
+		}
+		Ok(states)
+	}
+}
+
+impl Checkpoint for AgeGroupStateVector {
+	fn write_checkpoint<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+		self.to_array().write_checkpoint(writer)
+	}
+	fn read_checkpoint<R: Read>(reader: &mut R) -> std::io::Result<AgeGroupStateVector> {
+		Ok(AgeGroupStateVector::from_array(<[f64; 19]>::read_checkpoint(reader)?))
+	}
+}
+
+impl Checkpoint for AgeGroup {
+	fn write_checkpoint<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+		self.name.write_checkpoint(writer)?;
+		self.M.write_checkpoint(writer)?;
+		self.influx.write_checkpoint(writer)?;
+		self.rho.write_checkpoint(writer)?;
+		self.gamma_I.write_checkpoint(writer)?;
+		self.gamma_ICU.write_checkpoint(writer)?;
+		self.alpha.write_checkpoint(writer)?;
+		self.delta_I.write_checkpoint(writer)?;
+		self.delta_ICU.write_checkpoint(writer)?;
+		self.eligible_fraction.write_checkpoint(writer)?;
+		self.min_uptake.write_checkpoint(writer)?;
+		self.max_uptake.write_checkpoint(writer)?;
+		self.phase.write_checkpoint(writer)
+	}
+	fn read_checkpoint<R: Read>(reader: &mut R) -> std::io::Result<AgeGroup> {
+		Ok(AgeGroup {
+			name: Checkpoint::read_checkpoint(reader)?,
+			M: Checkpoint::read_checkpoint(reader)?,
+			influx: Checkpoint::read_checkpoint(reader)?,
+			rho: Checkpoint::read_checkpoint(reader)?,
+			gamma_I: Checkpoint::read_checkpoint(reader)?,
+			gamma_ICU: Checkpoint::read_checkpoint(reader)?,
+			alpha: Checkpoint::read_checkpoint(reader)?,
+			delta_I: Checkpoint::read_checkpoint(reader)?,
+			delta_ICU: Checkpoint::read_checkpoint(reader)?,
+			eligible_fraction: Checkpoint::read_checkpoint(reader)?,
+			min_uptake: Checkpoint::read_checkpoint(reader)?,
+			max_uptake: Checkpoint::read_checkpoint(reader)?,
+			phase: Checkpoint::read_checkpoint(reader)?
+		})
+	}
+}
+
+impl Checkpoint for Model {
+	fn write_checkpoint<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+		self.age_groups.write_checkpoint(writer)?;
+		self.M.write_checkpoint(writer)?;
+		self.eta0.write_checkpoint(writer)?;
+		self.sigma.write_checkpoint(writer)?;
+		self.kappa0.write_checkpoint(writer)?;
+		self.tau.write_checkpoint(writer)?;
+		self.tau_vacc.write_checkpoint(writer)?;
+		self.vaccinations_per_week_dose1.write_checkpoint(writer)?;
+		self.vaccinations_per_week_dose2.write_checkpoint(writer)?;
+		self.random_vacc.write_checkpoint(writer)?;
+		self.N_TTI.write_checkpoint(writer)?;
+		self.N_test_eff.write_checkpoint(writer)?;
+		self.N_test_ineff.write_checkpoint(writer)?;
+		self.N_no_test.write_checkpoint(writer)?;
+		self.contacts.write_checkpoint(writer)
+	}
+	fn read_checkpoint<R: Read>(reader: &mut R) -> std::io::Result<Model> {
+		Ok(Model {
+			age_groups: Checkpoint::read_checkpoint(reader)?,
+			M: Checkpoint::read_checkpoint(reader)?,
+			eta0: Checkpoint::read_checkpoint(reader)?,
+			sigma: Checkpoint::read_checkpoint(reader)?,
+			kappa0: Checkpoint::read_checkpoint(reader)?,
+			tau: Checkpoint::read_checkpoint(reader)?,
+			tau_vacc: Checkpoint::read_checkpoint(reader)?,
+			vaccinations_per_week_dose1: Checkpoint::read_checkpoint(reader)?,
+			vaccinations_per_week_dose2: Checkpoint::read_checkpoint(reader)?,
+			random_vacc: Checkpoint::read_checkpoint(reader)?,
+			N_TTI: Checkpoint::read_checkpoint(reader)?,
+			N_test_eff: Checkpoint::read_checkpoint(reader)?,
+			N_test_ineff: Checkpoint::read_checkpoint(reader)?,
+			N_no_test: Checkpoint::read_checkpoint(reader)?,
+			contacts: Checkpoint::read_checkpoint(reader)?
+		})
+	}
+}
+
+impl Checkpoint for Integrator {
+	fn write_checkpoint<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+		match *self {
+			Integrator::RK4 => 0u64.write_checkpoint(writer),
+			Integrator::Adaptive { rtol, atol } => {
+				1u64.write_checkpoint(writer)?;
+				rtol.write_checkpoint(writer)?;
+				atol.write_checkpoint(writer)
+			}
+		}
+	}
+	fn read_checkpoint<R: Read>(reader: &mut R) -> std::io::Result<Integrator> {
+		match u64::read_checkpoint(reader)? {
+			0 => Ok(Integrator::RK4),
+			1 => Ok(Integrator::Adaptive { rtol: Checkpoint::read_checkpoint(reader)?, atol: Checkpoint::read_checkpoint(reader)? }),
+			_ => Err(invalid_data("Unknown integrator in checkpoint"))
+		}
+	}
+}
+
+impl Checkpoint for Holiday {
+	fn write_checkpoint<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+		self.start.write_checkpoint(writer)?;
+		self.end.write_checkpoint(writer)?;
+		self.factor.write_checkpoint(writer)
+	}
+	fn read_checkpoint<R: Read>(reader: &mut R) -> std::io::Result<Holiday> {
+		Ok(Holiday {
+			start: Checkpoint::read_checkpoint(reader)?,
+			end: Checkpoint::read_checkpoint(reader)?,
+			factor: Checkpoint::read_checkpoint(reader)?
+		})
+	}
+}
+
+impl Checkpoint for ObservationModel {
+	fn write_checkpoint<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+		self.case_delay.write_checkpoint(writer)?;
+		self.admission_delay.write_checkpoint(writer)?;
+		self.death_delay.write_checkpoint(writer)?;
+		self.ascertainment.write_checkpoint(writer)?;
+		self.weekday_effects.write_checkpoint(writer)?;
+		self.first_weekday.write_checkpoint(writer)?;
+		self.holidays.write_checkpoint(writer)
+	}
+	fn read_checkpoint<R: Read>(reader: &mut R) -> std::io::Result<ObservationModel> {
+		Ok(ObservationModel {
+			case_delay: Checkpoint::read_checkpoint(reader)?,
+			admission_delay: Checkpoint::read_checkpoint(reader)?,
+			death_delay: Checkpoint::read_checkpoint(reader)?,
+			ascertainment: Checkpoint::read_checkpoint(reader)?,
+			weekday_effects: Checkpoint::read_checkpoint(reader)?,
+			first_weekday: Checkpoint::read_checkpoint(reader)?,
+			holidays: Checkpoint::read_checkpoint(reader)?
+		})
+	}
+}
+
+impl<S: Checkpoint> Checkpoint for EventRecord<S> {
+	fn write_checkpoint<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+		self.name.write_checkpoint(writer)?;
+		self.t.write_checkpoint(writer)?;
+		self.value.write_checkpoint(writer)?;
+		self.state.write_checkpoint(writer)
+	}
+	fn read_checkpoint<R: Read>(reader: &mut R) -> std::io::Result<EventRecord<S>> {
+		Ok(EventRecord {
+			name: Checkpoint::read_checkpoint(reader)?,
+			t: Checkpoint::read_checkpoint(reader)?,
+			value: Checkpoint::read_checkpoint(reader)?,
+			state: Checkpoint::read_checkpoint(reader)?
+		})
+	}
+}
+
+/// The registered events are not saved (they contain closures), they have to be registered again after loading. The event log is saved,
+/// it also records which events that may only occur once have already occurred.
+impl<M: DelaySystem + Checkpoint> Checkpoint for Solver<M> where M::State: Checkpoint {
+	fn write_checkpoint<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+		self.model.write_checkpoint(writer)?;
+		self.dt.write_checkpoint(writer)?;
+		self.integrator.write_checkpoint(writer)?;
+		self.observation.write_checkpoint(writer)?;
+		self.t0.write_checkpoint(writer)?;
+		self.initials.write_checkpoint(writer)?;
+		self.Rt_initial.write_checkpoint(writer)?;
+		self.time.write_checkpoint(writer)?;
+		self.Rt.write_checkpoint(writer)?;
+		self.states.write_checkpoint(writer)?;
+		self.N.write_checkpoint(writer)?;
+		self.N_obs.write_checkpoint(writer)?;
+		self.index.write_checkpoint(writer)?;
+		self.event_log.write_checkpoint(writer)
+	}
+	fn read_checkpoint<R: Read>(reader: &mut R) -> std::io::Result<Solver<M>> {
+		Ok(Solver {
+			model: Checkpoint::read_checkpoint(reader)?,
+			dt: Checkpoint::read_checkpoint(reader)?,
+			integrator: Checkpoint::read_checkpoint(reader)?,
+			observation: Checkpoint::read_checkpoint(reader)?,
+			t0: Checkpoint::read_checkpoint(reader)?,
+			initials: Checkpoint::read_checkpoint(reader)?,
+			Rt_initial: Checkpoint::read_checkpoint(reader)?,
+			time: Checkpoint::read_checkpoint(reader)?,
+			Rt: Checkpoint::read_checkpoint(reader)?,
+			states: Checkpoint::read_checkpoint(reader)?,
+			N: Checkpoint::read_checkpoint(reader)?,
+			N_obs: Checkpoint::read_checkpoint(reader)?,
+			index: Checkpoint::read_checkpoint(reader)?,
+			events: Vec::new(),
+			event_log: Checkpoint::read_checkpoint(reader)?
+		})
+	}
+}
+
+impl<M: DelaySystem + Checkpoint> Solver<M> where M::State: Checkpoint {
+	/// Saves the complete state of the solver and the model to the file _filename_, e.g. to continue a long run later with _load\_checkpoint_.
+	pub fn save_checkpoint(&self, filename: &str) -> Result<()> {
+		let mut writer = std::io::BufWriter::new(create_file(filename)?);
+		self.write_checkpoint_file(&mut writer)?;
+		writer.flush()?;
+		Ok(())
+	}
+
+	/// Same as _save\_checkpoint_, but writes the file _name_ to the _sink_ (see the _sink_ submodule)
+	pub fn save_checkpoint_to(&self, sink: &mut dyn Sink, name: &str) -> Result<()> {
+		sink.write_file(name, &mut |mut file| self.write_checkpoint_file(&mut file))
+	}
+
+	/// Restores a solver saved with _save\_checkpoint_. After registering the same events again, calling _controlled\_run_ on it gives exactly
+	/// the same results as on the saved solver.
+	///
+	/// Fails if the file cannot be read or is not a checkpoint of this version.
+	pub fn load_checkpoint(filename: &str) -> Result<Solver<M>> {
+		Solver::load_checkpoint_from(&mut std::io::BufReader::new(open_file(filename)?))
+	}
+
+	/// Same as _load\_checkpoint_, but reads the checkpoint from _reader_ (e.g. the contents of a file in a _Memory_ sink)
+	pub fn load_checkpoint_from<R: Read>(reader: &mut R) -> Result<Solver<M>> {
+		let mut magic = [0u8; 8];
+		reader.read_exact(&mut magic)?;
+		if &magic != MAGIC {
+			return Err(invalid_data("Not a solver checkpoint").into());
+		}
+		if u64::read_checkpoint(reader)? != VERSION {
+			return Err(invalid_data("Unsupported checkpoint version").into());
+		}
+		let solver = Solver::read_checkpoint(reader)?;
+		if solver.states.len() != solver.time.len() || solver.index >= solver.time.len() {
+			return Err(invalid_data("Inconsistent solver history in checkpoint").into());
+		}
+		Ok(solver)
+	}
+
+	/// Writes the magic bytes, the format version and the solver
+	fn write_checkpoint_file<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+		writer.write_all(MAGIC)?;
+		VERSION.write_checkpoint(writer)?;
+		self.write_checkpoint(writer)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::vaccination_model::events::{Direction, Event};
+	use crate::vaccination_model::schedule::{Phase, Schedule};
+	use crate::vaccination_model::sink::Memory;
+	use crate::vaccination_model::solver::tests::small_solver;
+	use crate::vaccination_model::target::Target;
+	use std::sync::Arc;
+
+	/// Keeps the ICU occupancy at 20
+	fn schedule() -> Schedule<AgeGroupStateVector> {
+		Schedule::new(vec![Phase::new(Target::ICUOccupancy, 20.0).Rt_range(0.5, 2.0).max_slope(0.1)]).unwrap()
+	}
+
+	/// Alters $R_t$ at the first upward crossing of the middle of every 10 days
+	fn cut() -> Event<AgeGroupStateVector> {
+		Event::threshold("cut", Arc::new(|t, _| t % 10.0 - 5.0), 0.0, Direction::Up).alter(Arc::new(|_, _, R| *R *= 0.8)).once()
+	}
+
+	/// The checkpoint of the solver (compares all fields bit by bit)
+	fn checkpoint(solver: &Solver) -> Vec<u8> {
+		let mut memory = Memory::new();
+		solver.save_checkpoint_to(&mut memory, "checkpoint").unwrap();
+		memory.files.remove("checkpoint").unwrap()
+	}
+
+	#[test]
+	fn round_trip_through_memory() {
+		let mut solver = small_solver(1e5);
+		solver.initialize().unwrap();
+		solver.controlled_run(10.0, &schedule()).unwrap();
+
+		let saved = checkpoint(&solver);
+		let restored: Solver = Solver::load_checkpoint_from(&mut &saved[..]).unwrap();
+		assert_eq!(restored.index, solver.index);
+		assert_eq!(restored.time, solver.time);
+		assert_eq!(checkpoint(&restored), saved);
+	}
+
+	#[test]
+	fn restored_run_continues_identically() {
+		let mut solver = small_solver(1e5);
+		let schedule = schedule();
+		solver.initialize().unwrap();
+		solver.events.push(cut());
+		solver.controlled_run(20.0, &schedule).unwrap();
+
+		let mut restored: Solver = Solver::load_checkpoint_from(&mut &checkpoint(&solver)[..]).unwrap();
+		restored.events.push(cut());
+
+		solver.controlled_run(20.0, &schedule).unwrap();
+		restored.controlled_run(20.0, &schedule).unwrap();
+		assert_eq!(restored.event_log.len(), 1);
+		assert_eq!(checkpoint(&restored), checkpoint(&solver));
+	}
+
+	#[test]
+	fn rejects_other_files() {
+		assert!(Solver::<Model>::load_checkpoint_from(&mut &b"PAR1 not a checkpoint"[..]).is_err());
+	}
+}
@@ -0,0 +1,232 @@
+//! Control laws for $R_t$.
+//!
+//! Every day _controlled\_run_ previews the system for a few days with the current $R_t$ and computes the relative deviation of the
+//! controlled quantity from its set point at the end of the preview, $\Delta = (x-x_{aim})/x_{aim}$, and its rate of change $\dot\Delta$.
+//! A _Controller_ turns them into an adjustment of $R_t$: the new raw $R_t$ is $R_t$ minus the adjustment, where the adjustment is limited
+//! by the maximal slope and the result by the minimal and maximal $R_t$ of the current phase.
+
+/// A control law, see the module documentation.
+pub trait Controller: Send + Sync {
+	/// How many days the system is previewed to determine the error
+	fn preview_length(&self) -> f64;
+
+	/// Returns the adjustment of $R_t$ for one control interval of length _bin\_length_ given the error $\Delta$ and its change $\dot\Delta$
+	/// (positive adjustments decrease $R_t$).
+	fn adjustment(&mut self, error: f64, error_change: f64, bin_length: f64) -> f64;
+
+	/// Copy of the controller (including its internal state)
+	fn boxed_clone(&self) -> Box<dyn Controller>;
+
+	/// Type name and parameters (including the internal state) that rebuild the controller, used to record it in a manifest (see _manifest_).
+	/// None for controllers that cannot be rebuilt from them (the default).
+	fn description(&self) -> Option<(&'static str, Vec<(&'static str, f64)>)> {
+		None
+	}
+}
+
+impl Clone for Box<dyn Controller> {
+	fn clone(&self) -> Box<dyn Controller> {
+		self.boxed_clone()
+	}
+}
+
+/// Proportional-derivative controller with two sets of gains: one for the settled state (small error and error change) and one otherwise.
+#[derive(Clone, Debug)]
+pub struct PD {
+	/// Proportional gain $k_p$
+	pub kp: f64,
+	/// Derivative gain $k_d$
+	pub kd: f64,
+	/// Proportional gain if the system is settled
+	pub kp_settled: f64,
+	/// Derivative gain if the system is settled
+	pub kd_settled: f64,
+	/// The system is settled if $|\Delta|$ is below this tolerance ...
+	pub error_tolerance: f64,
+	/// ... and $|\dot\Delta|$ below this one
+	pub change_tolerance: f64,
+	/// Preview horizon in days
+	pub preview_length: f64
+}
+
+impl PD {
+	/// The gains used for a stable ICU occupancy in the manuscript
+	pub fn ICU() -> PD {
+		PD { kp: 0.3e-0, kd: 0.9e1, kp_settled: 0.3e-0, kd_settled: 1.5e1, error_tolerance: 0.1, change_tolerance: 0.1, preview_length: 14.0 }
+	}
+
+	/// The gains used for stable daily (observed) infections in the manuscript
+	pub fn cases() -> PD {
+		PD { kp: 6e-2, kd: 1.2e0, kp_settled: 6e-2, kd_settled: 3e0, error_tolerance: 0.05, change_tolerance: 0.1, preview_length: 14.0 }
+	}
+}
+
+impl Controller for PD {
+	fn preview_length(&self) -> f64 {
+		self.preview_length
+	}
+
+	fn boxed_clone(&self) -> Box<dyn Controller> {
+		Box::new(self.clone())
+	}
+
+	fn description(&self) -> Option<(&'static str, Vec<(&'static str, f64)>)> {
+		Some(("PD", vec![("kp", self.kp), ("kd", self.kd), ("kp_settled", self.kp_settled), ("kd_settled", self.kd_settled),
+						 ("error_tolerance", self.error_tolerance), ("change_tolerance", self.change_tolerance), ("preview_length", self.preview_length)]))
+	}
+
+	fn adjustment(&mut self, error: f64, error_change: f64, bin_length: f64) -> f64 {
+		let (kp, kd) = if error.abs() < self.error_tolerance && error_change.abs() < self.change_tolerance {
+			(self.kp_settled, self.kd_settled)
+		} else {
+			(self.kp, self.kd)
+		};
+		bin_length*(kp*error + kd*error_change)
+	}
+}
+
+/// Proportional-integral-derivative controller. The integral of the error is limited to $\pm$_integral\_limit_ (anti-windup).
+#[derive(Clone, Debug)]
+pub struct PID {
+	/// Proportional gain $k_p$
+	pub kp: f64,
+	/// Integral gain $k_i$
+	pub ki: f64,
+	/// Derivative gain $k_d$
+	pub kd: f64,
+	/// Maximal absolute value of the error integral
+	pub integral_limit: f64,
+	/// Preview horizon in days
+	pub preview_length: f64,
+	/// Integral of the error so far
+	pub integral: f64
+}
+
+impl PID {
+	/// PID controller with the given gains, a preview of 14 days and no limit for the integral
+	pub fn new(kp: f64, ki: f64, kd: f64) -> PID {
+		PID { kp, ki, kd, integral_limit: f64::INFINITY, preview_length: 14.0, integral: 0.0 }
+	}
+}
+
+impl Controller for PID {
+	fn preview_length(&self) -> f64 {
+		self.preview_length
+	}
+
+	fn boxed_clone(&self) -> Box<dyn Controller> {
+		Box::new(self.clone())
+	}
+
+	fn description(&self) -> Option<(&'static str, Vec<(&'static str, f64)>)> {
+		Some(("PID", vec![("kp", self.kp), ("ki", self.ki), ("kd", self.kd), ("integral_limit", self.integral_limit), ("preview_length", self.preview_length),
+						  ("integral", self.integral)]))
+	}
+
+	fn adjustment(&mut self, error: f64, error_change: f64, bin_length: f64) -> f64 {
+		self.integral = (self.integral + error*bin_length).clamp(-self.integral_limit, self.integral_limit);
+		bin_length*(self.kp*error + self.ki*self.integral + self.kd*error_change)
+	}
+}
+
+/// Bang-bang controller: decreases $R_t$ by _step_ per day (or less, if the maximal slope of the phase is smaller) if the error is above the
+/// tolerance and increases it if the error is below minus the tolerance, until it reaches the minimal or maximal $R_t$. Within the tolerance
+/// $R_t$ is kept.
+#[derive(Clone, Debug)]
+pub struct BangBang {
+	/// Half width of the dead band around the set point (relative)
+	pub tolerance: f64,
+	/// Change of the raw $R_t$ per day outside of the dead band (finite, so that $R_t$ stays bounded without limits in the phase)
+	pub step: f64,
+	/// Preview horizon in days
+	pub preview_length: f64
+}
+
+impl Controller for BangBang {
+	fn preview_length(&self) -> f64 {
+		self.preview_length
+	}
+
+	fn boxed_clone(&self) -> Box<dyn Controller> {
+		Box::new(self.clone())
+	}
+
+	fn description(&self) -> Option<(&'static str, Vec<(&'static str, f64)>)> {
+		Some(("BangBang", vec![("tolerance", self.tolerance), ("step", self.step), ("preview_length", self.preview_length)]))
+	}
+
+	fn adjustment(&mut self, error: f64, _error_change: f64, bin_length: f64) -> f64 {
+		if error > self.tolerance {
+			self.step*bin_length
+		} else if error < -self.tolerance {
+			-self.step*bin_length
+		} else {
+			0.0
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::vaccination_model::error::Error;
+	use crate::vaccination_model::schedule::{Phase, Schedule};
+	use crate::vaccination_model::solver::tests::small_solver;
+	use crate::vaccination_model::target::Target;
+
+	#[test]
+	fn PID_integral_saturates() {
+		let mut pid = PID { integral_limit: 2.0, ..PID::new(0.0, 1.0, 0.0) };
+		for _ in 0..10 {
+			pid.adjustment(1.0, 0.0, 1.0);
+		}
+		assert_eq!(pid.integral, 2.0);
+		assert_eq!(pid.adjustment(1.0, 0.0, 1.0), 2.0);
+
+		// The saturated integral reacts immediately to a change of sign
+		assert_eq!(pid.adjustment(-0.5, 0.0, 1.0), 1.5);
+		for _ in 0..10 {
+			pid.adjustment(-1.0, 0.0, 1.0);
+		}
+		assert_eq!(pid.integral, -2.0);
+	}
+
+	#[test]
+	fn BangBang_steps_outside_of_the_dead_band() {
+		let mut bang_bang = BangBang { tolerance: 0.1, step: 0.05, preview_length: 14.0 };
+		assert_eq!(bang_bang.adjustment(0.05, 1.0, 1.0), 0.0);
+		assert_eq!(bang_bang.adjustment(-0.1, -1.0, 1.0), 0.0);
+		// Too high: decrease Rt, too low: increase it
+		assert_eq!(bang_bang.adjustment(0.2, 0.0, 1.0), 0.05);
+		assert_eq!(bang_bang.adjustment(-0.2, 0.0, 2.0), -0.1);
+	}
+
+	/// Increases $R_t$ without bounds
+	#[derive(Clone)]
+	struct Unbounded;
+
+	impl Controller for Unbounded {
+		fn preview_length(&self) -> f64 {
+			14.0
+		}
+
+		fn adjustment(&mut self, _error: f64, _error_change: f64, _bin_length: f64) -> f64 {
+			f64::NEG_INFINITY
+		}
+
+		fn boxed_clone(&self) -> Box<dyn Controller> {
+			Box::new(self.clone())
+		}
+	}
+
+	#[test]
+	fn non_finite_Rt_is_rejected() {
+		let mut solver = small_solver(1e5);
+		solver.initialize().unwrap();
+		// Phases have no limits for Rt by default
+		let schedule = Schedule::new(vec![Phase::new(Target::ICUOccupancy, 20.0).controller(Box::new(Unbounded))]).unwrap();
+		assert!(matches!(solver.controlled_run(10.0, &schedule), Err(Error::InvalidParameter { .. })));
+		// The results of the first day are kept
+		assert!(solver.index > 0 && (solver.time[solver.index] - 1.0).abs() < 0.1);
+	}
+}
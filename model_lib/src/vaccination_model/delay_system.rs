@@ -0,0 +1,97 @@
+//! The interface between the solver and a model.
+//!
+//! The solver (Runge-Kutta 4 or adaptive integration, the control system and the writer) only needs to know a few things about the model:
+//! how the state of one block (usually an age group) looks like, the slopes of the delay dif. eqs., which quantities it needs from the
+//! delayed state and a few observables. These are collected in the trait _DelaySystem_, so that model variants can reuse the solver by
+//! implementing it. The vaccination model _Model_ is one implementation.
+
+use crate::vaccination_model::output::Table;
+
+/// State of one block (e.g. an age group) of the system. The full system state at one instant is a slice of them.
+///
+/// The solver combines states compartment by compartment, so apart from storage it only needs _apply_ and a way to visit all compartments.
+pub trait StateVector: Copy + Default + std::fmt::Debug {
+	/// Applies $x \to f(x, y_1, ..., y_K)$ to every compartment $x$, where the $y_k$ are the same compartment in the other vectors.
+	fn apply<const K: usize>(&mut self, others: [&Self; K], f: impl Fn(f64, [f64; K]) -> f64);
+
+	/// Calls _f_ with the value of every compartment (used e.g. for error norms)
+	fn for_each(&self, f: impl FnMut(f64));
+}
+
+/// A system of delay dif. eqs. with a single delay that the solver can integrate and control.
+///
+/// The right hand side depends on the current state and (through _delayed\_terms_) on the state and the raw $R_t$ a time _delay_ earlier.
+/// $R_t$ is the control parameter: it is constant during a run of the solver and adjusted by the control system in between.
+pub trait DelaySystem {
+	/// State of one block of the system
+	type State: StateVector;
+	/// Quantities derived from the delayed state and $R_t$. The fixed step solver computes them only once per step,
+	/// since the delayed arguments do not change during a Runge-Kutta step.
+	type Delayed: Default;
+
+	/// The delay $\tau$ of the dif. eqs.
+	fn delay(&self) -> f64;
+
+	/// End of the time span covered by the time dependent inputs of the model (e.g. the prepared vaccination rates). The solver refuses to
+	/// integrate beyond it (see _Solver::check\_horizon_). Unlimited by default.
+	fn horizon(&self) -> f64 {
+		f64::INFINITY
+	}
+
+	/// Computes the quantities the slopes need from the delayed $R_t$ and the delayed system state.
+	fn delayed_terms(&self, delayed_R: f64, delayed_state: &[Self::State], delayed: &mut Self::Delayed);
+
+	/// Writes the slopes d/dt of all blocks into _slopes_, given the time $t$, the current $R_t$, the full system state, the delayed system state
+	/// and the delayed quantities from _delayed\_terms_.
+	fn slopes_into(&self, t: f64, R: f64, state: &[Self::State], delayed_state: &[Self::State], delayed: &Self::Delayed, slopes: &mut [Self::State]);
+
+	/// Completes the initial state before a simulation starts with $R_t$ = _R_ (e.g. helper variables that depend on the history). Does nothing by default.
+	fn prepare_initials(&self, _R: f64, _initials: &mut [Self::State]) {}
+
+	/// Total daily new infections for a given system state (not convoluted with the observation kernel)
+	fn N(&self, state: &[Self::State]) -> f64;
+
+	/// Total ICU occupancy for a given system state
+	fn ICU_occupancy(&self, state: &[Self::State]) -> f64;
+
+	/// Daily new infections in one block (not convoluted with the observation kernel)
+	fn N_block(&self, state: &[Self::State], block: usize) -> f64;
+
+	/// Total daily ICU admissions for a given system state
+	fn ICU_admissions(&self, state: &[Self::State]) -> f64;
+
+	/// Total daily deaths for a given system state
+	fn deaths(&self, state: &[Self::State]) -> f64;
+
+	/// Daily deaths in one block
+	fn deaths_block(&self, state: &[Self::State], block: usize) -> f64;
+
+	/// Converts the raw $R_t$ used in the dif. eqs. to the one the control limits refer to, given the observed daily infections N. The identity by default.
+	fn raw_Rt_to_TTI_corrected(&self, raw_Rt: f64, _N: f64) -> f64 {
+		raw_Rt
+	}
+
+	/// Inverse of _raw\_Rt\_to\_TTI\_corrected_. The identity by default.
+	fn raw_Rt_from_TTI_corrected(&self, TTI_Rt: f64, _N: f64) -> f64 {
+		TTI_Rt
+	}
+
+	/// Names of the blocks, used for the names of the output files
+	fn block_names(&self) -> Vec<String>;
+
+	/// Names of the columns of the output file of one block (see _state\_values_)
+	fn state_columns(&self) -> Vec<String>;
+
+	/// The values of the columns of the output file of one block at time $t$. _initial_ is set for the first row, i.e. the initial values.
+	fn state_values(&self, t: f64, block: usize, state: &Self::State, initial: bool) -> Vec<f64>;
+
+	/// Description of the output file of one block
+	fn state_description(&self) -> String {
+		"first line is initial values".to_string()
+	}
+
+	/// The model parameters as tables (see _output_), written with the results. None by default.
+	fn parameter_tables(&self) -> Vec<Table> {
+		Vec::new()
+	}
+}
@@ -0,0 +1,238 @@
+//! Propagation of parameter uncertainty through a scenario.
+//!
+//! The uncertain inputs (e.g. the vaccine efficacies, the seroprevalence or the uptake, see _Factor_) are sampled from their ranges with a
+//! Latin hypercube or the quasi-random Halton sequence, which cover the ranges more evenly than independent random draws. The scenario is run
+//! for every sample and the pointwise quantiles of all time series written by _Solver::write\_to\_disk_ ($R_t$, N, $N_{obs}$ and all compartments
+//! of every age group) are written as bands, by default 5%, 25%, 50%, 75% and 95%. The runs are independent and run in parallel (see _sweep_).
+
+use crate::vaccination_model::delay_system::DelaySystem;
+use crate::vaccination_model::error::Result;
+use crate::vaccination_model::random::{quantile, Rng};
+use crate::vaccination_model::sensitivity::Factor;
+use crate::vaccination_model::sink::{Directory, Sink};
+use crate::vaccination_model::solver::Solver;
+use crate::vaccination_model::sweep::Sweep;
+
+/// Default quantiles of the bands
+pub const QUANTILES: [f64; 5] = [0.05, 0.25, 0.5, 0.75, 0.95];
+
+/// Sampling of the unit hypercube
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sampling {
+	/// Latin hypercube: every range is split into as many strata as samples and every stratum is sampled once (random position within the stratum)
+	LatinHypercube,
+	/// Halton sequence with the first primes as bases (deterministic, the seed is not used)
+	Halton
+}
+
+impl Sampling {
+	/// _n_ points in the unit hypercube of dimension _dimension_
+	pub fn sample(&self, n: usize, dimension: usize, seed: u64) -> Vec<Vec<f64>> {
+		match self {
+			Sampling::LatinHypercube => {
+				let mut rng = Rng::new(seed);
+				let mut points = vec![vec![0.0; dimension]; n];
+				for d in 0..dimension {
+					// Random permutation of the strata
+					let mut strata: Vec<usize> = (0..n).collect();
+					for i in (1..n).rev() {
+						let j = ((rng.uniform()*((i+1) as f64)) as usize).min(i);
+						strata.swap(i, j);
+					}
+					for (point, stratum) in points.iter_mut().zip(strata.iter()) {
+						point[d] = (*stratum as f64 + rng.uniform())/(n as f64);
+					}
+				}
+				points
+			},
+			Sampling::Halton => {
+				let bases = primes(dimension);
+				(1..=n).map(|i| bases.iter().map(|&base| radical_inverse(i, base)).collect()).collect()
+			}
+		}
+	}
+}
+
+/// Settings of an uncertainty ensemble
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EnsembleDesign {
+	/// How the factors are sampled
+	pub sampling: Sampling,
+	/// Number of runs
+	pub runs: usize,
+	/// Seed of the sampling
+	pub seed: u64,
+	/// Only every _write\_every_ value of the results is kept (as in _Solver::write\_to\_disk_)
+	pub write_every: usize
+}
+
+/// Time series of all runs of an ensemble, on the grid of the first run (every _write\_every_ step)
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ScenarioEnsemble {
+	/// Names of the factors
+	pub factors: Vec<String>,
+	/// Sampled factor values of every run
+	pub inputs: Vec<Vec<f64>>,
+	/// Times
+	pub time: Vec<f64>,
+	/// $R_t$, N, $N_{obs}$ and the TTI corrected $R_t$ (index order: run, series, time)
+	pub series: Vec<[Vec<f64>; 4]>,
+	/// Names of the blocks (age groups)
+	pub block_names: Vec<String>,
+	/// Names of the columns of the output file of a block (the compartments and derived values, see _DelaySystem::state\_columns_)
+	pub compartments: Vec<String>,
+	/// Columns of the output file of every block (index order: run, block, column, time)
+	pub states: Vec<Vec<Vec<Vec<f64>>>>
+}
+
+impl EnsembleDesign {
+	/// Latin hypercube design with _runs_ runs, keeping every 20th value
+	pub fn new(runs: usize, seed: u64) -> EnsembleDesign {
+		EnsembleDesign { sampling: Sampling::LatinHypercube, runs, seed, write_every: 20 }
+	}
+
+	/// Runs _scenario_ (setting up and running a solver for the factor values) for every sample of the _factors_, in parallel with _sweep_
+	pub fn run<M: DelaySystem>(&self, factors: &[Factor], sweep: &Sweep, scenario: impl Fn(&[f64]) -> Solver<M> + Sync) -> ScenarioEnsemble {
+		let samples = self.sampling.sample(self.runs, factors.len(), self.seed);
+		let inputs: Vec<Vec<f64>> = samples.iter().map(|u| factors.iter().zip(u).map(|(factor, u)| factor.lower + u*(factor.upper - factor.lower)).collect()).collect();
+		let mut ensemble = ScenarioEnsemble { factors: factors.iter().map(|factor| factor.name.clone()).collect(), ..ScenarioEnsemble::default() };
+
+		// Only the kept values of every run are collected, not the solvers
+		let runs = sweep.run(&inputs, |inputs| {
+			let mut run = ScenarioEnsemble::default();
+			run.add(&scenario(inputs), self.write_every);
+			run
+		});
+		for (run, inputs) in runs.into_iter().zip(inputs) {
+			if ensemble.time.is_empty() {
+				ensemble.time = run.time;
+				ensemble.block_names = run.block_names;
+				ensemble.compartments = run.compartments;
+			}
+			ensemble.series.extend(run.series);
+			ensemble.states.extend(run.states);
+			ensemble.inputs.push(inputs);
+		}
+		ensemble
+	}
+}
+
+impl ScenarioEnsemble {
+	/// Adds the results of a finished run
+	fn add<M: DelaySystem>(&mut self, solver: &Solver<M>, write_every: usize) {
+		let indices: Vec<usize> = (0..solver.index+1).step_by(write_every).collect();
+		if self.time.is_empty() {
+			self.time = indices.iter().map(|&j| solver.time[j]).collect();
+			self.block_names = solver.model.block_names();
+			self.compartments = solver.model.state_columns();
+		}
+
+		self.series.push([
+			indices.iter().map(|&j| solver.Rt[j]).collect(),
+			indices.iter().map(|&j| solver.N[j]).collect(),
+			indices.iter().map(|&j| solver.N_obs[j]).collect(),
+			indices.iter().map(|&j| solver.model.raw_Rt_to_TTI_corrected(solver.Rt[j], solver.N_obs[j])).collect()
+		]);
+		let mut states = vec![vec![Vec::with_capacity(indices.len()); self.compartments.len()]; self.block_names.len()];
+		for &j in indices.iter() {
+			for (block, (columns, state)) in states.iter_mut().zip(solver.states[j].iter()).enumerate() {
+				for (column, x) in columns.iter_mut().zip(solver.model.state_values(solver.time[j], block, state, false)) {
+					column.push(x);
+				}
+			}
+		}
+		self.states.push(states);
+	}
+
+	/// Number of time points available in all runs (runs can stop early, e.g. due to events)
+	fn length(&self) -> usize {
+		self.series.iter().map(|series| series[0].len()).min().unwrap_or(0)
+	}
+
+	/// Pointwise quantile q (between 0 and 1) over the runs of the values given by _value_ (from run and time index)
+	fn band(&self, q: f64, value: impl Fn(usize, usize) -> f64) -> Vec<f64> {
+		(0..self.length()).map(|j| quantile(&mut (0..self.series.len()).map(|run| value(run, j)).collect::<Vec<f64>>(), q)).collect()
+	}
+
+	/// Pointwise quantile q of series s ($R_t$, N, $N_{obs}$, TTI corrected $R_t$)
+	pub fn quantile(&self, s: usize, q: f64) -> Vec<f64> {
+		self.band(q, |run, j| self.series[run][s][j])
+	}
+
+	/// Pointwise quantile q of a column of the output file of a block (see _compartments_)
+	pub fn state_quantile(&self, block: usize, compartment: usize, q: f64) -> Vec<f64> {
+		self.band(q, |run, j| self.states[run][block][compartment][j])
+	}
+
+	/// Writes the bands of the _quantiles_ to the folder "./data/foldername/" (see _write\_to_).
+	pub fn write_to_disk(&self, foldername: &str, quantiles: &[f64]) -> Result<()> {
+		self.write_to(&mut Directory::data(foldername), quantiles)
+	}
+
+	/// Writes the bands of the _quantiles_ to the _sink_: "tHRt_quantiles.data" with the columns of "tHRt.data" and
+	/// "{name}\_age\_group\_quantiles.data" with the columns of "{name}\_age\_group.data" of every age group, each column followed by the
+	/// quantile in percent (e.g. "Rt\_50" for the median). The sampled inputs of every run are written to "ensemble_inputs.data".
+	pub fn write_to(&self, sink: &mut dyn Sink, quantiles: &[f64]) -> Result<()> {
+		let precision = 6;
+		let t = &self.time[..self.length()];
+		let columns = |names: &[String]| -> String {
+			let mut header = vec!["t".to_string()];
+			for name in names.iter() {
+				header.extend(quantiles.iter().map(|q| format!("{}_{}", name, (100.0*q).round())));
+			}
+			header.join(" \t ")
+		};
+		let write_bands = |sink: &mut dyn Sink, filename: &str, names: &[String], bands: Vec<Vec<f64>>| -> Result<()> {
+			sink.write_file(filename, &mut |file| {
+				writeln!(file, "{}", columns(names))?;
+				for (j, t) in t.iter().enumerate() {
+					let values: Vec<String> = std::iter::once(*t).chain(bands.iter().map(|band| band[j])).map(|x| format!("{:.1$}", x, precision)).collect();
+					writeln!(file, "{}", values.join(" \t "))?;
+				}
+				Ok(())
+			})
+		};
+
+		let names: Vec<String> = ["Rt", "N", "N_obs", "Rt_TTI_corrected"].iter().map(|name| name.to_string()).collect();
+		let bands = (0..4).flat_map(|s| quantiles.iter().map(move |q| (s, *q))).map(|(s, q)| self.quantile(s, q)).collect();
+		write_bands(sink, "tHRt_quantiles.data", &names, bands)?;
+
+		for (block, name) in self.block_names.iter().enumerate() {
+			let bands = (0..self.compartments.len()).flat_map(|c| quantiles.iter().map(move |q| (c, *q))).map(|(c, q)| self.state_quantile(block, c, q)).collect();
+			write_bands(sink, &format!("{}_age_group_quantiles.data", name), &self.compartments, bands)?;
+		}
+
+		sink.write_file("ensemble_inputs.data", &mut |file| {
+			writeln!(file, "{}", self.factors.join(" \t "))?;
+			for inputs in self.inputs.iter() {
+				writeln!(file, "{}", inputs.iter().map(|x| format!("{:.1$}", x, precision)).collect::<Vec<String>>().join(" \t "))?;
+			}
+			Ok(())
+		})
+	}
+}
+
+/// The first n prime numbers
+fn primes(n: usize) -> Vec<usize> {
+	let mut primes: Vec<usize> = Vec::with_capacity(n);
+	let mut candidate = 2;
+	while primes.len() < n {
+		if primes.iter().all(|p| candidate % p != 0) {
+			primes.push(candidate);
+		}
+		candidate += 1;
+	}
+	primes
+}
+
+/// Radical inverse of i in the given base (van der Corput sequence)
+fn radical_inverse(mut i: usize, base: usize) -> f64 {
+	let mut inverse = 0.0;
+	let mut factor = 1.0/(base as f64);
+	while i > 0 {
+		inverse += (i % base) as f64*factor;
+		i /= base;
+		factor /= base as f64;
+	}
+	inverse
+}
@@ -0,0 +1,135 @@
+//! Error type of the library.
+//!
+//! All fallible public functions return _Result_, i.e. they fail with an _Error_ describing the invalid parameter, the exhausted vaccination
+//! schedule or the failed file operation instead of panicking.
+
+use crate::vaccination_model::schedule::ScheduleError;
+
+/// Everything that can go wrong when setting up, running or saving a model
+#[derive(Debug)]
+pub enum Error {
+	/// A parameter is outside of its valid range. _name_ identifies the parameter (e.g. "60-69.alpha0" for an age group parameter).
+	InvalidParameter { name: String, value: f64, expected: String },
+	/// A parameter has the wrong number of entries (e.g. the contact matrix does not match the number of age groups)
+	DimensionMismatch { name: String, expected: usize, found: usize },
+	/// The total vaccine uptake cannot be reached with the minimal and maximal uptakes of the age groups
+	UnreachableUptake { total_uptake: f64, min: f64, max: f64 },
+	/// The time _t_ lies after the end of the prepared vaccination rates (see _Model::prepare\_vaccination\_rates_), which cover the times before _end_
+	VaccinationsExhausted { t: f64, end: f64 },
+	/// The control schedule is invalid
+	Schedule(ScheduleError),
+	/// Part of a run cannot be recorded in a manifest (see _manifest_), e.g. a phase ending at a condition (a closure)
+	Unrecordable(String),
+	/// Reading or writing a file failed
+	Io(std::io::Error)
+}
+
+/// Result type of all fallible functions of the library
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+	/// Shortcut for _InvalidParameter_
+	pub(crate) fn invalid(name: &str, value: f64, expected: &str) -> Error {
+		Error::InvalidParameter { name: name.to_string(), value, expected: expected.to_string() }
+	}
+}
+
+impl std::fmt::Display for Error {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			Error::InvalidParameter { name, value, expected } => write!(f, "Invalid parameter {}={}, expected {}", name, value, expected),
+			Error::DimensionMismatch { name, expected, found } => write!(f, "{} has {} entries, expected {}", name, found, expected),
+			Error::UnreachableUptake { total_uptake, min, max } => write!(f, "Total uptake {} cannot be reached, the age groups allow between {:.4} and {:.4}", total_uptake, min, max),
+			Error::VaccinationsExhausted { t, end } => write!(f, "Vaccination rates needed at t={:.2}, but they are only prepared before t={:.2} (prepare more weeks)", t, end),
+			Error::Schedule(error) => write!(f, "{}", error),
+			Error::Unrecordable(what) => write!(f, "{} cannot be recorded in a manifest", what),
+			Error::Io(error) => write!(f, "I/O error: {}", error)
+		}
+	}
+}
+
+impl std::error::Error for Error {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Error::Schedule(error) => Some(error),
+			Error::Io(error) => Some(error),
+			_ => None
+		}
+	}
+}
+
+impl From<ScheduleError> for Error {
+	fn from(error: ScheduleError) -> Error {
+		Error::Schedule(error)
+	}
+}
+
+impl From<std::io::Error> for Error {
+	fn from(error: std::io::Error) -> Error {
+		Error::Io(error)
+	}
+}
+
+/// Adds the path to the error message of a failed file operation (the standard library only reports e.g. "No such file or directory")
+pub(crate) fn with_path(path: &str) -> impl Fn(std::io::Error) -> Error + '_ {
+	move |error| Error::Io(std::io::Error::new(error.kind(), format!("{}: {}", path, error)))
+}
+
+/// Creates the file _path_ for writing
+pub(crate) fn create_file(path: &str) -> Result<std::fs::File> {
+	std::fs::File::create(path).map_err(with_path(path))
+}
+
+/// Opens the file _path_ for reading
+pub(crate) fn open_file(path: &str) -> Result<std::fs::File> {
+	std::fs::File::open(path).map_err(with_path(path))
+}
+
+/// Reads the whole file _path_
+pub(crate) fn read_file(path: &str) -> Result<String> {
+	std::fs::read_to_string(path).map_err(with_path(path))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::vaccination_model::solver::tests::small_solver;
+
+	#[test]
+	fn unreachable_uptakes_are_rejected() {
+		// The uptakes of the age groups lie between 50% and 90%
+		let mut model = small_solver(1e6).model;
+		match model.prepare_vaccination_rates(30, 0.95) {
+			Err(Error::UnreachableUptake { total_uptake, min, max }) => {
+				assert_eq!(total_uptake, 0.95);
+				assert!((min - 0.5).abs() < 1e-12 && (max - 0.9).abs() < 1e-12);
+			},
+			other => panic!("Unexpected result {:?}", other)
+		}
+		assert!(model.prepare_vaccination_rates(30, 0.7).is_ok());
+	}
+
+	#[test]
+	fn runs_beyond_the_vaccination_rates_fail() {
+		// The vaccination rates are prepared for 30 weeks
+		let mut solver = small_solver(1e6);
+		solver.initialize().unwrap();
+		assert!(solver.check_horizon(200.0).is_ok());
+		assert!(matches!(solver.check_horizon(220.0), Err(Error::VaccinationsExhausted { end, .. }) if end == 210.0));
+		assert!(matches!(solver.run_constant(220.0, 1.0), Err(Error::VaccinationsExhausted { .. })));
+	}
+
+	#[test]
+	fn invalid_parameters_are_named() {
+		let mut model = small_solver(1e6).model;
+		assert!(model.validate().is_ok());
+		model.age_groups[1].alpha[0] = -0.1;
+		match model.validate() {
+			Err(Error::InvalidParameter { name, value, .. }) => assert_eq!((name.as_str(), value), ("0-59.alpha0", -0.1)),
+			other => panic!("Unexpected result {:?}", other)
+		}
+		model.age_groups[1].alpha[0] = 0.005;
+		model.eta0 = 1.5;
+		assert!(matches!(model.validate(), Err(Error::InvalidParameter { name, .. }) if name == "eta0"));
+	}
+}
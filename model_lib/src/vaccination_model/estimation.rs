@@ -0,0 +1,361 @@
+//! Estimation of model parameters from observed time series.
+//!
+//! The observed data (e.g. loaded with _SurveillanceData::read\_from\_disk_) is compared with the expected reports of the model
+//! (_Solver::expected\_surveillance_) by a Poisson or negative binomial likelihood. The parameters to estimate are handed to a user supplied
+//! function that sets up and runs the solver, so any parameter can be estimated: the seroprevalence, the initial infections, $R_t$, severity
+//! rates, etc.
+//!
+//! The maximum likelihood estimate is found with the Nelder-Mead simplex method. Bounded parameters are transformed to the whole real line
+//! (logit or log transform), so the simplex never leaves the bounds. Approximate 95% confidence intervals are derived from the observed Fisher
+//! information, i.e. the numerical Hessian of the negative log-likelihood at the estimate. The Hessian is computed in the transformed coordinates
+//! (the model is never evaluated outside the bounds) and the covariance is transformed back with the derivatives of the transforms.
+
+use crate::vaccination_model::random::ln_gamma;
+use crate::vaccination_model::surveillance::SurveillanceData;
+use crate::vaccination_model::target::Target;
+
+/// Quantile of the standard normal distribution for 95% confidence intervals
+const Z_95: f64 = 1.959963984540054;
+
+/// An observed series used for the estimation
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Observed {
+	/// Reported cases
+	Cases,
+	/// Reported ICU admissions
+	ICUAdmissions,
+	/// Reported deaths
+	Deaths,
+	/// Reported ICU occupancy
+	ICU
+}
+
+impl Observed {
+	/// The values of this series in _data_
+	pub fn values<'a>(&self, data: &'a SurveillanceData) -> &'a [f64] {
+		match self {
+			Observed::Cases => &data.cases,
+			Observed::ICUAdmissions => &data.ICU_admissions,
+			Observed::Deaths => &data.deaths,
+			Observed::ICU => &data.ICU
+		}
+	}
+
+	/// The model quantity reported in this series
+	pub fn target(&self) -> Target {
+		match self {
+			Observed::Cases => Target::ObservedCases,
+			Observed::ICUAdmissions => Target::ICUAdmissions,
+			Observed::Deaths => Target::Deaths,
+			Observed::ICU => Target::ICUOccupancy
+		}
+	}
+}
+
+/// Distribution of the observed counts around the expected reports
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Likelihood {
+	/// Poisson distributed counts
+	Poisson,
+	/// Negative binomially distributed counts with dispersion $k$ (variance $\mu + \mu^2/k$)
+	NegativeBinomial { dispersion: f64 }
+}
+
+impl Likelihood {
+	/// Logarithm of the probability to observe _observed_ if _expected_ is expected
+	pub fn log_likelihood(&self, observed: f64, expected: f64) -> f64 {
+		let mu = expected.max(1e-10);
+		match self {
+			Likelihood::Poisson => observed*mu.ln() - mu - ln_gamma(observed + 1.0),
+			Likelihood::NegativeBinomial { dispersion: k } => {
+				ln_gamma(observed + k) - ln_gamma(*k) - ln_gamma(observed + 1.0) + k*(k/(k + mu)).ln() + observed*(mu/(k + mu)).ln()
+			}
+		}
+	}
+}
+
+/// A parameter to estimate with its starting value and bounds (which may be infinite)
+#[derive(Clone, Debug, PartialEq)]
+pub struct Parameter {
+	/// Name of the parameter
+	pub name: String,
+	/// Starting value of the optimization
+	pub initial: f64,
+	/// Lower bound
+	pub lower: f64,
+	/// Upper bound
+	pub upper: f64
+}
+
+impl Parameter {
+	/// Parameter with the starting value _initial_ between _lower_ and _upper_
+	pub fn new(name: &str, initial: f64, lower: f64, upper: f64) -> Parameter {
+		Parameter { name: name.to_string(), initial, lower, upper }
+	}
+
+	/// Maps the whole real line to the allowed range of the parameter
+	fn bounded(&self, y: f64) -> f64 {
+		match (self.lower.is_finite(), self.upper.is_finite()) {
+			(true, true) => self.lower + (self.upper - self.lower)/(1.0 + (-y).exp()),
+			(true, false) => self.lower + y.exp(),
+			(false, true) => self.upper - (-y).exp(),
+			(false, false) => y
+		}
+	}
+
+	/// Inverse of _bounded_
+	fn unbounded(&self, x: f64) -> f64 {
+		match (self.lower.is_finite(), self.upper.is_finite()) {
+			(true, true) => {
+				let p = (x - self.lower)/(self.upper - self.lower);
+				(p/(1.0 - p)).ln()
+			},
+			(true, false) => (x - self.lower).ln(),
+			(false, true) => -(self.upper - x).ln(),
+			(false, false) => x
+		}
+	}
+
+	/// Derivative of _bounded_ at the point that maps to _x_
+	fn derivative(&self, x: f64) -> f64 {
+		match (self.lower.is_finite(), self.upper.is_finite()) {
+			(true, true) => (x - self.lower)*(self.upper - x)/(self.upper - self.lower),
+			(true, false) => x - self.lower,
+			(false, true) => self.upper - x,
+			(false, false) => 1.0
+		}
+	}
+}
+
+/// Settings of the estimation
+#[derive(Clone, Debug, PartialEq)]
+pub struct Estimation {
+	/// Observed series that enter the likelihood
+	pub series: Vec<Observed>,
+	/// Distribution of the observed counts
+	pub likelihood: Likelihood,
+	/// The optimization stops when the log-likelihood values of the simplex differ by less than this
+	pub tolerance: f64,
+	/// Maximal number of model evaluations of the optimization
+	pub max_evaluations: usize
+}
+
+/// Result of the estimation
+#[derive(Clone, Debug, PartialEq)]
+pub struct Estimate {
+	/// Names of the parameters
+	pub names: Vec<String>,
+	/// Maximum likelihood estimates
+	pub values: Vec<f64>,
+	/// Log-likelihood at the estimate
+	pub log_likelihood: f64,
+	/// Approximate standard errors (NaN if the Hessian is not positive definite, e.g. for parameters at a bound)
+	pub standard_errors: Vec<f64>,
+	/// Approximate 95% confidence intervals, restricted to the bounds
+	pub confidence_intervals: Vec<(f64, f64)>,
+	/// Number of model evaluations
+	pub evaluations: usize,
+	/// Whether the optimization reached the tolerance within the maximal number of evaluations
+	pub converged: bool
+}
+
+impl Estimation {
+	/// Estimation from the given series with tolerance 1e-6 and at most 1000 model evaluations
+	pub fn new(series: Vec<Observed>, likelihood: Likelihood) -> Estimation {
+		Estimation { series, likelihood, tolerance: 1e-6, max_evaluations: 1000 }
+	}
+
+	/// Log-likelihood of the observed _data_ given the _expected_ reports of the model. Observations are matched with the expected reports
+	/// of the same day (times differing by less than half a day), observations without a match or with a NaN value are skipped.
+	pub fn log_likelihood(&self, data: &SurveillanceData, expected: &SurveillanceData) -> f64 {
+		let mut log_likelihood = 0.0;
+		let mut j_model = 0;
+		for (j, t) in data.t.iter().enumerate() {
+			while j_model < expected.t.len() && expected.t[j_model] < t - 0.5 {
+				j_model += 1;
+			}
+			if j_model == expected.t.len() {
+				break;
+			}
+			if (expected.t[j_model] - t).abs() >= 0.5 {
+				continue;
+			}
+			for series in self.series.iter() {
+				let observed = series.values(data)[j];
+				if !observed.is_nan() {
+					log_likelihood += self.likelihood.log_likelihood(observed, series.values(expected)[j_model]);
+				}
+			}
+		}
+		log_likelihood
+	}
+
+	/// Estimates _parameters_ from _data_. _simulate_ gets the parameter values (in the order of _parameters_), sets up and runs the
+	/// solver and returns its expected reports (see _Solver::expected\_surveillance_).
+	pub fn estimate(&self, data: &SurveillanceData, parameters: &[Parameter], simulate: impl Fn(&[f64]) -> SurveillanceData) -> Estimate {
+		let n = parameters.len();
+		let evaluations = std::cell::Cell::new(0usize);
+		let values = |y: &[f64]| -> Vec<f64> { parameters.iter().zip(y).map(|(p, y)| p.bounded(*y)).collect() };
+		let cost = |y: &[f64]| -> f64 {
+			evaluations.set(evaluations.get() + 1);
+			let cost = -self.log_likelihood(data, &simulate(&values(y)));
+			if cost.is_nan() { f64::INFINITY } else { cost }
+		};
+
+		// Initial simplex around the starting values (in the unbounded coordinates)
+		let start: Vec<f64> = parameters.iter().map(|p| p.unbounded(p.initial)).collect();
+		let mut simplex: Vec<Vec<f64>> = vec![start.clone()];
+		for i in 0..n {
+			let mut vertex = start.clone();
+			vertex[i] += if start[i].abs() > 1.0 { 0.1*start[i] } else { 0.1 };
+			simplex.push(vertex);
+		}
+		let mut costs: Vec<f64> = simplex.iter().map(|vertex| cost(vertex)).collect();
+
+		// Nelder-Mead iterations
+		let mut converged = false;
+		while evaluations.get() < self.max_evaluations {
+			let mut order: Vec<usize> = (0..=n).collect();
+			order.sort_by(|a, b| costs[*a].partial_cmp(&costs[*b]).unwrap_or(std::cmp::Ordering::Equal));
+			simplex = order.iter().map(|&i| simplex[i].clone()).collect();
+			costs = order.iter().map(|&i| costs[i]).collect();
+			if (costs[n] - costs[0]).abs() <= self.tolerance*(1.0 + costs[0].abs()) {
+				converged = true;
+				break;
+			}
+
+			let centroid: Vec<f64> = (0..n).map(|i| simplex[..n].iter().map(|vertex| vertex[i]).sum::<f64>()/(n as f64)).collect();
+			let along = |factor: f64| -> Vec<f64> { (0..n).map(|i| centroid[i] + factor*(simplex[n][i] - centroid[i])).collect() };
+
+			let reflected = along(-1.0);
+			let reflected_cost = cost(&reflected);
+			if reflected_cost < costs[0] {
+				let expanded = along(-2.0);
+				let expanded_cost = cost(&expanded);
+				if expanded_cost < reflected_cost {
+					simplex[n] = expanded;
+					costs[n] = expanded_cost;
+				} else {
+					simplex[n] = reflected;
+					costs[n] = reflected_cost;
+				}
+			} else if reflected_cost < costs[n-1] {
+				simplex[n] = reflected;
+				costs[n] = reflected_cost;
+			} else {
+				let contracted = if reflected_cost < costs[n] { along(-0.5) } else { along(0.5) };
+				let contracted_cost = cost(&contracted);
+				if contracted_cost < costs[n].min(reflected_cost) {
+					simplex[n] = contracted;
+					costs[n] = contracted_cost;
+				} else {
+					// Shrink towards the best vertex
+					let best = simplex[0].clone();
+					for j in 1..=n {
+						for (x, x_best) in simplex[j].iter_mut().zip(best.iter()) {
+							*x = x_best + 0.5*(*x - x_best);
+						}
+						costs[j] = cost(&simplex[j]);
+					}
+				}
+			}
+		}
+		let best = (0..=n).min_by(|a, b| costs[*a].partial_cmp(&costs[*b]).unwrap_or(std::cmp::Ordering::Equal)).unwrap_or(0);
+		let estimate = values(&simplex[best]);
+		let log_likelihood = -costs[best];
+
+		// Observed Fisher information by central differences in the unbounded coordinates (so the model is never evaluated outside the bounds)
+		let optimum = &simplex[best];
+		let negative_log_likelihood = |y: &[f64]| -self.log_likelihood(data, &simulate(&values(y)));
+		let steps: Vec<f64> = optimum.iter().map(|y| 1e-3*y.abs().max(1.0)).collect();
+		let mut hessian = vec![vec![0.0; n]; n];
+		for i in 0..n {
+			for j in i..n {
+				let at = |di: f64, dj: f64| {
+					let mut y = optimum.clone();
+					y[i] += di*steps[i];
+					y[j] += dj*steps[j];
+					negative_log_likelihood(&y)
+				};
+				hessian[i][j] = if i == j {
+					(at(1.0, 0.0) - 2.0*(-log_likelihood) + at(-1.0, 0.0))/(steps[i]*steps[i])
+				} else {
+					(at(1.0, 1.0) - at(1.0, -1.0) - at(-1.0, 1.0) + at(-1.0, -1.0))/(4.0*steps[i]*steps[j])
+				};
+				hessian[j][i] = hessian[i][j];
+			}
+		}
+		// Back to the original coordinates with the derivatives of the transforms (delta method)
+		let standard_errors: Vec<f64> = match invert(hessian) {
+			Some(covariance) => (0..n).map(|i| if covariance[i][i] > 0.0 { parameters[i].derivative(estimate[i])*covariance[i][i].sqrt() } else { f64::NAN }).collect(),
+			None => vec![f64::NAN; n]
+		};
+		let confidence_intervals = parameters.iter().zip(estimate.iter().zip(standard_errors.iter()))
+			.map(|(p, (x, se))| ((x - Z_95*se).max(p.lower), (x + Z_95*se).min(p.upper))).collect();
+
+		Estimate { names: parameters.iter().map(|p| p.name.clone()).collect(), values: estimate, log_likelihood, standard_errors, confidence_intervals, evaluations: evaluations.get(), converged }
+	}
+}
+
+/// Inverts a matrix by Gauss-Jordan elimination with partial pivoting. Returns None if it is singular.
+fn invert(mut matrix: Vec<Vec<f64>>) -> Option<Vec<Vec<f64>>> {
+	let n = matrix.len();
+	let mut inverse: Vec<Vec<f64>> = (0..n).map(|i| (0..n).map(|j| if i == j { 1.0 } else { 0.0 }).collect()).collect();
+	for column in 0..n {
+		let pivot = (column..n).max_by(|a, b| matrix[*a][column].abs().partial_cmp(&matrix[*b][column].abs()).unwrap_or(std::cmp::Ordering::Equal))?;
+		if matrix[pivot][column].abs() < 1e-300 || !matrix[pivot][column].is_finite() {
+			return None;
+		}
+		matrix.swap(column, pivot);
+		inverse.swap(column, pivot);
+		let factor = 1.0/matrix[column][column];
+		for j in 0..n {
+			matrix[column][j] *= factor;
+			inverse[column][j] *= factor;
+		}
+		for row in 0..n {
+			if row != column {
+				let factor = matrix[row][column];
+				for j in 0..n {
+					matrix[row][j] -= factor*matrix[column][j];
+					inverse[row][j] -= factor*inverse[column][j];
+				}
+			}
+		}
+	}
+	Some(inverse)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::vaccination_model::schedule::{Phase, Schedule};
+	use crate::vaccination_model::solver::tests::small_solver;
+	use crate::vaccination_model::surveillance::{Aggregation, SurveillanceGenerator};
+	use crate::vaccination_model::target::Target;
+
+	/// Expected reports of 40 days at the (TTI corrected) $R_t$ _R_
+	fn simulate(R: f64) -> SurveillanceData {
+		let mut solver = small_solver(1e6);
+		solver.initialize().unwrap();
+		solver.controlled_run(40.0, &Schedule::new(vec![Phase::new(Target::ICUOccupancy, 1.0).Rt_range(R, R)]).unwrap()).unwrap();
+		solver.expected_surveillance(Aggregation::Daily)
+	}
+
+	#[test]
+	fn recovers_Rt_from_synthetic_cases() {
+		let truth = 1.2;
+		let mut solver = small_solver(1e6);
+		solver.initialize().unwrap();
+		solver.controlled_run(40.0, &Schedule::new(vec![Phase::new(Target::ICUOccupancy, 1.0).Rt_range(truth, truth)]).unwrap()).unwrap();
+		let data = solver.synthetic_surveillance(&SurveillanceGenerator::new(11));
+
+		let estimation = Estimation::new(vec![Observed::Cases], Likelihood::Poisson);
+		let estimate = estimation.estimate(&data, &[Parameter::new("Rt", 1.0, 0.5, 2.0)], |values| simulate(values[0]));
+		assert!(estimate.converged);
+		let (lower, upper) = estimate.confidence_intervals[0];
+		assert!(lower < truth && truth < upper, "{} not in ({}, {})", truth, lower, upper);
+		assert!(lower < estimate.values[0] && estimate.values[0] < upper);
+		assert!(upper - lower < 0.1);
+	}
+}
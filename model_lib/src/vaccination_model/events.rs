@@ -0,0 +1,423 @@
+//! Events: conditions on the system state that are checked during _controlled\_run_.
+//!
+//! An event is defined by an observable $g(t, x)$ of the system state $x$ and a condition on it: a threshold crossing, a local maximum or minimum,
+//! or a custom predicate becoming true. After every simulated day the new part of the results is scanned step by step. If a condition is met
+//! within a step, the event is located precisely inside the step: the step is integrated again with Runge-Kutta 4 and a smaller step size
+//! (with the delayed state of the step held fixed), and the event time is found by bisection (crossings, predicates) or by the vertex of the
+//! parabola through three grid points (extrema). Every event occurrence is recorded with time, value and state in _Solver.event\_log_.
+//!
+//! An event can also stop the run or alter it (change the state and/or $R_t$). Both happen at the first grid point after the event,
+//! all results after that point are discarded.
+
+use crate::vaccination_model::delay_system::DelaySystem;
+use crate::vaccination_model::solver::Solver;
+use crate::vaccination_model::age_group_state_vector::{axpy, rk4_update};
+use std::sync::Arc;
+
+/// Number of bisection steps to locate crossings within a time step (locates them to $dt/2^{40}$)
+const BISECTION_STEPS: usize = 40;
+
+/// An observable of the system state $g(t, x)$, e.g. the ICU occupancy
+pub type Observable<S> = Arc<dyn Fn(f64, &[S]) -> f64 + Send + Sync>;
+/// A predicate on the system state $p(t, x)$
+pub type Predicate<S> = Arc<dyn Fn(f64, &[S]) -> bool + Send + Sync>;
+/// Alteration of the run: gets the event time and can change the state and the raw $R_t$
+pub type Alteration<S> = Arc<dyn Fn(f64, &mut [S], &mut f64) + Send + Sync>;
+
+/// Direction of a threshold crossing
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Direction {
+	/// The observable crosses the threshold from below
+	Up,
+	/// The observable crosses the threshold from above
+	Down,
+	/// Any crossing
+	Both
+}
+
+/// The condition that triggers an event
+#[derive(Clone)]
+pub enum Condition<S> {
+	/// The observable crosses the threshold in the given direction
+	Threshold { observable: Observable<S>, threshold: f64, direction: Direction },
+	/// The observable has a local maximum
+	Maximum(Observable<S>),
+	/// The observable has a local minimum
+	Minimum(Observable<S>),
+	/// The predicate changes from false to true
+	Predicate(Predicate<S>)
+}
+
+/// What happens when an event occurs
+#[derive(Clone)]
+pub enum Action<S> {
+	/// Only record the event
+	Record,
+	/// Record the event and stop the run
+	Stop,
+	/// Record the event and alter the state and/or $R_t$
+	Alter(Alteration<S>)
+}
+
+/// An event that is checked during _controlled\_run_, see the module documentation.
+///
+/// # How to use
+/// - let model = solver.model.clone();
+/// - solver.events.push(Event::threshold("ICU full", Arc::new(move |_, x| model.ICU_occupancy(x)), ICU_capacity, Direction::Up).stop());
+///
+/// stops the run as soon as the ICU occupancy exceeds the capacity.
+#[derive(Clone)]
+pub struct Event<S> {
+	/// Name of the event (used in the event log)
+	pub name: String,
+	/// Condition that triggers the event
+	pub condition: Condition<S>,
+	/// What happens when the event occurs
+	pub action: Action<S>,
+	/// Whether the event is removed after its first occurrence. Such an event is identified by its name: if the event log already contains
+	/// an occurrence of that name (e.g. in a solver restored from a checkpoint), the event is not handled again.
+	pub once: bool
+}
+
+impl<S> Event<S> {
+	/// Event when _observable_ crosses _threshold_ in the given direction
+	pub fn threshold(name: &str, observable: Observable<S>, threshold: f64, direction: Direction) -> Event<S> {
+		Event::new(name, Condition::Threshold { observable, threshold, direction })
+	}
+
+	/// Event at every local maximum of _observable_ (e.g. the peak of the incidence)
+	pub fn maximum(name: &str, observable: Observable<S>) -> Event<S> {
+		Event::new(name, Condition::Maximum(observable))
+	}
+
+	/// Event at every local minimum of _observable_
+	pub fn minimum(name: &str, observable: Observable<S>) -> Event<S> {
+		Event::new(name, Condition::Minimum(observable))
+	}
+
+	/// Event when _predicate_ becomes true
+	pub fn predicate(name: &str, predicate: Predicate<S>) -> Event<S> {
+		Event::new(name, Condition::Predicate(predicate))
+	}
+
+	fn new(name: &str, condition: Condition<S>) -> Event<S> {
+		Event { name: name.to_string(), condition, action: Action::Record, once: false }
+	}
+
+	/// Stops the run when the event occurs
+	pub fn stop(mut self) -> Event<S> {
+		self.action = Action::Stop;
+		self
+	}
+
+	/// Alters the state and/or $R_t$ when the event occurs
+	pub fn alter(mut self, alteration: Alteration<S>) -> Event<S> {
+		self.action = Action::Alter(alteration);
+		self
+	}
+
+	/// Only the first occurrence of the event is handled
+	pub fn once(mut self) -> Event<S> {
+		self.once = true;
+		self
+	}
+}
+
+/// One occurrence of an event
+#[derive(Clone, Debug)]
+pub struct EventRecord<S> {
+	/// Name of the event
+	pub name: String,
+	/// Time of the event
+	pub t: f64,
+	/// Value of the observable at the event (1 for predicates)
+	pub value: f64,
+	/// System state at the event
+	pub state: Vec<S>
+}
+
+impl<M: DelaySystem> Solver<M> {
+	/// Checks all events in the results between the indices _from_ and _to_ (one step after the other) and handles them.
+	///
+	/// Returns whether the run has to be stopped. If an event stops or alters the run, all results after the grid point following the event are
+	/// discarded and _index_ is set to that point. An alteration of $R_t$ is written to _R_ and used from that point on.
+	pub(crate) fn handle_events(&mut self, from: usize, to: usize, R: &mut f64) -> bool {
+		if self.events.is_empty() {
+			return false;
+		}
+		// Events registered again (e.g. after _load\_checkpoint_) that may only occur once and are already in the event log
+		let log = &self.event_log;
+		self.events.retain(|event| !(event.once && log.iter().any(|record| record.name == event.name)));
+		for k in from..to {
+			// Occurrences within the step from k to k+1, in order of time
+			let mut occurrences: Vec<(usize, EventRecord<M::State>)> = self.events.iter().enumerate()
+				.filter_map(|(e, event)| self.locate(&event.condition, k).map(|(t, value, state)| (e, EventRecord { name: event.name.clone(), t, value, state })))
+				.collect();
+			occurrences.sort_by(|a, b| a.1.t.partial_cmp(&b.1.t).unwrap_or(std::cmp::Ordering::Equal));
+
+			let mut finished: Vec<usize> = Vec::new();
+			for (e, record) in occurrences {
+				let t = record.t;
+				self.event_log.push(record);
+				if self.events[e].once {
+					finished.push(e);
+				}
+				let action = self.events[e].action.clone();
+				match action {
+					Action::Record => {},
+					Action::Stop => {
+						self.remove_events(finished);
+						self.truncate_results(k+1);
+						return true;
+					},
+					Action::Alter(alteration) => {
+						self.remove_events(finished);
+						self.truncate_results(k+1);
+						let mut state = self.states[k+1].to_vec();
+						alteration(t, &mut state, R);
+						self.states.truncate(k+1);
+						self.states.push(&state);
+						self.N[k+1] = self.model.N(&state);
+						return false;
+					}
+				}
+			}
+			self.remove_events(finished);
+		}
+		false
+	}
+
+	/// Removes the events with the given indices
+	fn remove_events(&mut self, mut indices: Vec<usize>) {
+		indices.sort_unstable();
+		for e in indices.into_iter().rev() {
+			self.events.remove(e);
+		}
+	}
+
+	/// Discards all results after the index _index_ and sets the current index to it
+	fn truncate_results(&mut self, index: usize) {
+		self.time.truncate(index+1);
+		self.Rt.truncate(index+1);
+		self.states.truncate(index+1);
+		self.N.truncate(index+1);
+		self.index = index;
+		let Nobs = self.observed_cases();
+		self.N_obs.resize(index+1, Nobs);
+	}
+
+	/// Checks whether the condition is met in the step from index k to k+1 and returns time, value of the observable and state of the event.
+	fn locate(&self, condition: &Condition<M::State>, k: usize) -> Option<(f64, f64, Vec<M::State>)> {
+		let (t0, t1) = (self.time[k], self.time[k+1]);
+		match condition {
+			Condition::Threshold { observable, threshold, direction } => {
+				let g = |t: f64, x: &[M::State]| observable(t, x) - threshold;
+				let (g0, g1) = (g(t0, &self.states[k]), g(t1, &self.states[k+1]));
+				let crossed = match direction {
+					Direction::Up => g0 < 0.0 && g1 >= 0.0,
+					Direction::Down => g0 > 0.0 && g1 <= 0.0,
+					Direction::Both => (g0 < 0.0 && g1 >= 0.0) || (g0 > 0.0 && g1 <= 0.0)
+				};
+				if !crossed {
+					return None;
+				}
+				let (t, state) = self.bisect(k, |t, x| (g(t, x) >= 0.0) == (g1 >= 0.0));
+				Some((t, observable(t, &state), state))
+			},
+			Condition::Maximum(observable) | Condition::Minimum(observable) => {
+				if k == 0 {
+					return None;
+				}
+				let sign = if let Condition::Maximum(_) = condition { 1.0 } else { -1.0 };
+				let g_before = sign*observable(self.time[k-1], &self.states[k-1]);
+				let g0 = sign*observable(t0, &self.states[k]);
+				let g1 = sign*observable(t1, &self.states[k+1]);
+				if !(g0 > g_before && g0 > g1) {
+					return None;
+				}
+				// Vertex of the parabola through the three points (equidistant grid)
+				let h = t1 - t0;
+				let curvature = g_before - 2.0*g0 + g1;
+				let t = t0 + 0.5*h*(g_before - g1)/curvature;
+				let state = if t < t0 {
+					self.substep(k-1, t - self.time[k-1])
+				} else {
+					self.substep(k, t - t0)
+				};
+				Some((t, observable(t, &state), state))
+			},
+			Condition::Predicate(predicate) => {
+				if predicate(t0, &self.states[k]) || !predicate(t1, &self.states[k+1]) {
+					return None;
+				}
+				let (t, state) = self.bisect(k, |t, x| predicate(t, x));
+				Some((t, 1.0, state))
+			}
+		}
+	}
+
+	/// Finds the earliest time in the step from index k to k+1 where _reached_ holds (it does not at k, but at k+1) by bisection.
+	fn bisect(&self, k: usize, reached: impl Fn(f64, &[M::State]) -> bool) -> (f64, Vec<M::State>) {
+		let (mut lower, mut upper) = (0.0f64, self.time[k+1] - self.time[k]);
+		let mut state = self.states[k+1].to_vec();
+		for _ in 0..BISECTION_STEPS {
+			let h = 0.5*(lower + upper);
+			let x = self.substep(k, h);
+			if reached(self.time[k] + h, &x) {
+				upper = h;
+				state = x;
+			} else {
+				lower = h;
+			}
+		}
+		if upper == self.time[k+1] - self.time[k] {
+			state = self.states[k+1].to_vec();
+		}
+		(self.time[k] + upper, state)
+	}
+
+	/// Integrates the state at index k with one Runge-Kutta 4 step of size h (smaller than dt) and the delayed state of the step k to k+1.
+	fn substep(&self, k: usize, h: f64) -> Vec<M::State> {
+		let index_delay = (self.model.delay()/self.dt) as usize;
+		let delayed_index = k.saturating_sub(index_delay);
+		let delayed_state = &self.states[delayed_index];
+		let R = self.Rt[k+1];
+		let t = self.time[k];
+
+		let mut state = self.states[k].to_vec();
+		let mut delayed = M::Delayed::default();
+		self.model.delayed_terms(self.Rt[delayed_index], delayed_state, &mut delayed);
+		let mut s1 = vec![M::State::default(); state.len()];
+		let mut s2 = s1.clone();
+		let mut s3 = s1.clone();
+		let mut s4 = s1.clone();
+		let mut intermediate = s1.clone();
+		self.model.slopes_into(t, R, &state, delayed_state, &delayed, &mut s1);
+		axpy(&mut intermediate, &state, 0.5*h, &s1);
+		self.model.slopes_into(t+0.5*h, R, &intermediate, delayed_state, &delayed, &mut s2);
+		axpy(&mut intermediate, &state, 0.5*h, &s2);
+		self.model.slopes_into(t+0.5*h, R, &intermediate, delayed_state, &delayed, &mut s3);
+		axpy(&mut intermediate, &state, h, &s3);
+		self.model.slopes_into(t+h, R, &intermediate, delayed_state, &delayed, &mut s4);
+		rk4_update(&mut state, &s1, &s2, &s3, &s4, h);
+		state
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::vaccination_model::age_group_state_vector::AgeGroupStateVector;
+	use crate::vaccination_model::schedule::{Phase, Schedule};
+	use crate::vaccination_model::solver::tests::small_solver;
+	use crate::vaccination_model::target::Target;
+
+	/// Keeps the ICU occupancy at 20 (it starts at 10)
+	fn steady() -> Schedule<AgeGroupStateVector> {
+		Schedule::new(vec![Phase::new(Target::ICUOccupancy, 20.0).Rt_range(0.5, 2.0).max_slope(0.1)]).unwrap()
+	}
+
+	/// $R_t$=3 for 40 days, then 0.5: the ICU occupancy falls to a minimum at about day 11, peaks at about day 47 and falls again
+	fn wave() -> Schedule<AgeGroupStateVector> {
+		Schedule::new(vec![
+			Phase::new(Target::ICUOccupancy, 20.0).until_time(40.0).Rt_range(3.0, 3.0).max_slope(10.0),
+			Phase::new(Target::ICUOccupancy, 20.0).Rt_range(0.5, 0.5).max_slope(10.0)
+		]).unwrap()
+	}
+
+	fn solver() -> Solver {
+		let mut solver = small_solver(1e5);
+		solver.initialize().unwrap();
+		solver
+	}
+
+	fn ICU(solver: &Solver) -> Observable<AgeGroupStateVector> {
+		let model = solver.model.clone();
+		Arc::new(move |_, x| model.ICU_occupancy(x))
+	}
+
+	/// Crosses 0.5 from below in the middle of every day
+	fn midday() -> Observable<AgeGroupStateVector> {
+		Arc::new(|t, _| t % 1.0 - 0.5)
+	}
+
+	#[test]
+	fn crossings_are_located_within_the_step() {
+		let mut solver = solver();
+		solver.events.push(Event::threshold("ICU 7", ICU(&solver), 7.0, Direction::Both));
+		solver.controlled_run(80.0, &wave()).unwrap();
+
+		// Brute force scan of the results
+		let g: Vec<f64> = solver.states.iter().map(|x| solver.model.ICU_occupancy(x) - 7.0).collect();
+		let crossings: Vec<usize> = (1..g.len()).filter(|&k| (g[k-1] < 0.0 && g[k] >= 0.0) || (g[k-1] > 0.0 && g[k] <= 0.0)).collect();
+		assert_eq!(crossings.len(), 3);
+		assert_eq!(solver.event_log.len(), crossings.len());
+		for (record, &k) in solver.event_log.iter().zip(&crossings) {
+			assert!(solver.time[k-1] < record.t && record.t <= solver.time[k], "{} not in ({}, {}]", record.t, solver.time[k-1], solver.time[k]);
+			assert!((record.value - 7.0).abs() < 1e-6);
+		}
+	}
+
+	#[test]
+	fn extrema_are_located_near_the_extreme_grid_values() {
+		let mut solver = solver();
+		solver.events.push(Event::maximum("ICU peak", ICU(&solver)));
+		solver.events.push(Event::minimum("ICU low", ICU(&solver)));
+		solver.controlled_run(80.0, &wave()).unwrap();
+
+		// Brute force scan of the results for local extrema
+		let ICU: Vec<f64> = solver.states.iter().map(|x| solver.model.ICU_occupancy(x)).collect();
+		let peak = (1..ICU.len()-1).filter(|&k| ICU[k] > ICU[k-1] && ICU[k] >= ICU[k+1]).collect::<Vec<usize>>();
+		let low = (1..ICU.len()-1).filter(|&k| ICU[k] < ICU[k-1] && ICU[k] <= ICU[k+1]).collect::<Vec<usize>>();
+		assert_eq!((peak.len(), low.len()), (1, 1));
+
+		assert_eq!(solver.event_log.len(), 2);
+		let (low_record, peak_record) = (&solver.event_log[0], &solver.event_log[1]);
+		assert_eq!((low_record.name.as_str(), peak_record.name.as_str()), ("ICU low", "ICU peak"));
+		assert!((peak_record.t - solver.time[peak[0]]).abs() <= solver.dt);
+		assert!((low_record.t - solver.time[low[0]]).abs() <= solver.dt);
+		assert!(peak_record.value >= ICU[peak[0]] && low_record.value <= ICU[low[0]]);
+	}
+
+	#[test]
+	fn stop_truncates_the_results() {
+		let mut solver = solver();
+		solver.events.push(Event::threshold("ICU 7", ICU(&solver), 7.0, Direction::Down).stop());
+		solver.controlled_run(60.0, &steady()).unwrap();
+
+		let t = solver.event_log[0].t;
+		assert_eq!(solver.event_log.len(), 1);
+		assert!(solver.time[solver.index-1] < t && t <= solver.time[solver.index]);
+		assert!(solver.time[solver.index] < 60.0);
+		for len in [solver.time.len(), solver.Rt.len(), solver.states.len(), solver.N.len(), solver.N_obs.len()].iter() {
+			assert_eq!(*len, solver.index+1);
+		}
+	}
+
+	#[test]
+	fn alter_changes_the_state_after_the_event() {
+		let mut solver = solver();
+		let empty_ICU: Alteration<AgeGroupStateVector> = Arc::new(|_, x, _| x.iter_mut().for_each(|group| group.ICU = [0.0; 3]));
+		solver.events.push(Event::threshold("empty ICU", Arc::new(|t, _| t - 5.5), 0.0, Direction::Up).alter(empty_ICU));
+		solver.controlled_run(10.0, &steady()).unwrap();
+
+		let after = solver.time.iter().position(|&t| t >= 5.5).unwrap();
+		assert!(solver.model.ICU_occupancy(&solver.states[after-1]) > 0.0);
+		assert_eq!(solver.model.ICU_occupancy(&solver.states[after]), 0.0);
+		assert_eq!(solver.event_log.len(), 1);
+		assert_eq!(solver.states.len(), solver.index+1);
+	}
+
+	#[test]
+	fn once_handles_only_the_first_occurrence() {
+		let mut solver = solver();
+		solver.events.push(Event::threshold("every day", midday(), 0.0, Direction::Up));
+		solver.events.push(Event::threshold("first day", midday(), 0.0, Direction::Up).once());
+		solver.controlled_run(10.0, &steady()).unwrap();
+
+		let count = |name: &str| solver.event_log.iter().filter(|record| record.name == name).count();
+		assert_eq!(count("every day"), 10);
+		assert_eq!(count("first day"), 1);
+		assert_eq!(solver.events.len(), 1);
+	}
+}
@@ -0,0 +1,302 @@
+//! Sequential data assimilation with a bootstrap particle filter.
+//!
+//! Instead of refitting the whole epidemic whenever new data arrive, the filter keeps an ensemble of solvers (the particles) that are continued
+//! day by day. Every particle has its own raw $R_t$, which follows a random walk: each day $\log R_t$ changes by a normally distributed step with
+//! standard deviation _Rt\_volatility_. When an observation arrives, every particle is weighted with the likelihood of the observed values given
+//! its expected reports (see _estimation_ and _observation_). If the weights degenerate (the effective sample size drops below a fraction of the
+//! particles), the particles are resampled with systematic resampling.
+//!
+//! The filtered $R_t$, infections and ICU occupancy are recorded after every assimilated day, and short-term forecasts continue the weighted
+//! ensemble with the same random walk. Only the last days of every particle are kept (enough for the delay and the reporting kernels), so
+//! long assimilation runs do not grow the memory.
+
+use crate::vaccination_model::delay_system::DelaySystem;
+use crate::vaccination_model::error::{Error, Result};
+use crate::vaccination_model::estimation::{Likelihood, Observed};
+use crate::vaccination_model::model::Model;
+use crate::vaccination_model::random::Rng;
+use crate::vaccination_model::sink::{Directory, Sink};
+use crate::vaccination_model::solver::Solver;
+use crate::vaccination_model::surveillance::{Aggregation, SurveillanceData};
+use crate::vaccination_model::target::Series;
+
+/// Bootstrap particle filter for a solver (by default of the vaccination model), see the module documentation
+#[derive(Clone)]
+pub struct ParticleFilter<M: DelaySystem = Model> {
+	/// Standard deviation of the daily change of $\log R_t$
+	pub Rt_volatility: f64,
+	/// Observed series used to weight the particles
+	pub series: Vec<Observed>,
+	/// Distribution of the observed counts around the expected reports
+	pub likelihood: Likelihood,
+	/// The particles are resampled if the effective sample size drops below this fraction of the particles
+	pub resampling_threshold: f64,
+	/// Number of days of the history kept in every particle (at least the delay of the model plus the longest reporting delay)
+	pub history_length: f64,
+	/// Filtered estimates after every assimilated observation
+	pub filtered: Vec<Filtered>,
+
+	/// The solvers of the particles
+	particles: Vec<Solver<M>>,
+	/// Current raw $R_t$ of every particle
+	R: Vec<f64>,
+	/// Normalized weight of every particle
+	weights: Vec<f64>,
+	/// Random number generator for the random walks and the resampling
+	rng: Rng
+}
+
+/// Filtered estimates at one time. The arrays contain the weighted 5%, 50% and 95% quantiles over the particles.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Filtered {
+	/// Time of the observation
+	pub t: f64,
+	/// Test-trace-and-isolate corrected $R_t$
+	pub Rt: [f64; 3],
+	/// Daily new infections
+	pub infections: [f64; 3],
+	/// ICU occupancy
+	pub ICU: [f64; 3],
+	/// Effective sample size $1/\sum_i w_i^2$ after the weighting
+	pub effective_sample_size: f64,
+	/// Logarithm of the likelihood of the observation given the previous observations (the sum over all observations is the log marginal likelihood)
+	pub log_likelihood: f64
+}
+
+impl<M: DelaySystem + Clone> ParticleFilter<M> {
+	/// Filter with _particles_ copies of _solver_ (continuing from its current index). The raw $R_t$ of the particles is drawn log-normally around
+	/// the current $R_t$ of the solver with the standard deviation _Rt\_spread_ of $\log R_t$. By default the filter uses the reported cases and
+	/// the ICU occupancy with a negative binomial likelihood (dispersion 10), a volatility of 0.05 and keeps 30 days of history.
+	/// Fails if there are no particles.
+	pub fn new(solver: &Solver<M>, particles: usize, Rt_spread: f64, seed: u64) -> Result<ParticleFilter<M>> {
+		if particles == 0 {
+			return Err(Error::invalid("particles", 0.0, "at least one particle"));
+		}
+		let mut rng = Rng::new(seed);
+		let R = (0..particles).map(|_| solver.Rt[solver.index]*(Rt_spread*rng.normal()).exp()).collect();
+		let mut filter = ParticleFilter {
+			Rt_volatility: 0.05,
+			series: vec![Observed::Cases, Observed::ICU],
+			likelihood: Likelihood::NegativeBinomial { dispersion: 10.0 },
+			resampling_threshold: 0.5,
+			history_length: 30.0,
+			filtered: Vec::new(),
+			particles: vec![solver.clone(); particles],
+			R,
+			weights: vec![1.0/(particles as f64); particles],
+			rng
+		};
+		for particle in filter.particles.iter_mut() {
+			forget_history(particle, filter.history_length);
+		}
+		Ok(filter)
+	}
+
+	/// The solvers of the particles
+	pub fn particles(&self) -> &[Solver<M>] {
+		&self.particles
+	}
+
+	/// The normalized weights of the particles
+	pub fn weights(&self) -> &[f64] {
+		&self.weights
+	}
+
+	/// Current time of the particles
+	pub fn time(&self) -> f64 {
+		let particle = &self.particles[0];
+		particle.time[particle.index]
+	}
+
+	/// Assimilates the daily observations of _data_ after the current time: the particles are continued to the time of every observation and
+	/// weighted with its likelihood. NaN values are skipped. Fails if the particles cannot be continued (see _Solver::check\_horizon_).
+	pub fn assimilate(&mut self, data: &SurveillanceData) -> Result<()> {
+		let dt = self.particles[0].dt;
+		for j in 0..data.t.len() {
+			let days = (data.t[j] - self.time()).round();
+			if days < 1.0 {
+				continue;
+			}
+			self.advance(days)?;
+
+			// Weight the particles with the likelihood of the observation
+			let log_likelihoods: Vec<f64> = self.particles.iter().map(|particle| {
+				let series = Series { states: &particle.states, N: &particle.N, end: particle.index, preview: None, t0: particle.time[0], dt };
+				self.series.iter().filter(|observed| !observed.values(data)[j].is_nan()).map(|observed| {
+					let expected = observed.target().observe(&particle.model, &particle.observation, &series, particle.index);
+					self.likelihood.log_likelihood(observed.values(data)[j], expected)
+				}).sum()
+			}).collect();
+			let max = log_likelihoods.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+			let mut total = 0.0;
+			for (weight, log_likelihood) in self.weights.iter_mut().zip(log_likelihoods.iter()) {
+				*weight *= (log_likelihood - max).exp();
+				total += *weight;
+			}
+			for weight in self.weights.iter_mut() {
+				*weight /= total;
+			}
+
+			let effective_sample_size = 1.0/self.weights.iter().map(|w| w*w).sum::<f64>();
+			let estimate = self.estimate(effective_sample_size, max + total.ln());
+			self.filtered.push(estimate);
+
+			if effective_sample_size < self.resampling_threshold*(self.particles.len() as f64) {
+				self.resample();
+			}
+		}
+		Ok(())
+	}
+
+	/// Forecast of the daily expected reports for the next _days_ days: the particles are drawn according to their weights and continued with the
+	/// random walk of $R_t$. Returns one forecast per particle, e.g. for _SurveillanceData::quantile_. The filter itself is left unchanged.
+	/// Fails like _assimilate_.
+	pub fn forecast(&self, days: f64, seed: u64) -> Result<Vec<SurveillanceData>> {
+		let mut rng = Rng::new(seed);
+		let now = self.time();
+		systematic_resampling(&self.weights, rng.uniform()).into_iter().map(|i| {
+			let mut particle = self.particles[i].clone();
+			let mut R = self.R[i];
+			for _day in 0..days as usize {
+				R *= (self.Rt_volatility*rng.normal()).exp();
+				particle.run_constant(1.0, R)?;
+			}
+			let expected = particle.expected_surveillance(Aggregation::Daily);
+			let first = expected.t.iter().position(|t| *t > now + 0.5*particle.dt).unwrap_or(expected.t.len());
+			Ok(SurveillanceData { t: expected.t[first..].to_vec(), cases: expected.cases[first..].to_vec(), ICU_admissions: expected.ICU_admissions[first..].to_vec(),
+								  deaths: expected.deaths[first..].to_vec(), ICU: expected.ICU[first..].to_vec() })
+		}).collect()
+	}
+
+	/// Writes the filtered estimates to the file "./data/foldername/filter.data" (tab separated columns with a header line)
+	pub fn write_to_disk(&self, foldername: &str) -> Result<()> {
+		self.write_to(&mut Directory::data(foldername))
+	}
+
+	/// Same as _write\_to\_disk_, but writes "filter.data" to the _sink_ (see the _sink_ submodule)
+	pub fn write_to(&self, sink: &mut dyn Sink) -> Result<()> {
+		let precision = 6;
+		sink.write_file("filter.data", &mut |file| {
+			writeln!(file, "t \t Rt_5 \t Rt_50 \t Rt_95 \t N_5 \t N_50 \t N_95 \t ICU_5 \t ICU_50 \t ICU_95 \t ESS \t log_likelihood")?;
+			for f in self.filtered.iter() {
+				let values: Vec<String> = [f.t].iter().chain(f.Rt.iter()).chain(f.infections.iter()).chain(f.ICU.iter()).chain([f.effective_sample_size, f.log_likelihood].iter())
+					.map(|x| format!("{:.1$}", x, precision)).collect();
+				writeln!(file, "{}", values.join(" \t "))?;
+			}
+			Ok(())
+		})
+	}
+
+	/// Continues all particles for _days_ days, every day with a new step of the random walk of $R_t$
+	fn advance(&mut self, days: f64) -> Result<()> {
+		for _day in 0..days as usize {
+			for (particle, R) in self.particles.iter_mut().zip(self.R.iter_mut()) {
+				*R *= (self.Rt_volatility*self.rng.normal()).exp();
+				particle.run_constant(1.0, *R)?;
+				forget_history(particle, self.history_length);
+			}
+		}
+		Ok(())
+	}
+
+	/// Weighted quantiles of the current state of the particles
+	fn estimate(&self, effective_sample_size: f64, log_likelihood: f64) -> Filtered {
+		let bands = |values: Vec<f64>| [0.05, 0.5, 0.95].map(|q| weighted_quantile(&values, &self.weights, q));
+		Filtered {
+			t: self.time(),
+			Rt: bands(self.particles.iter().zip(self.R.iter()).map(|(particle, R)| particle.model.raw_Rt_to_TTI_corrected(*R, particle.N_obs[particle.index])).collect()),
+			infections: bands(self.particles.iter().map(|particle| particle.N[particle.index]).collect()),
+			ICU: bands(self.particles.iter().map(|particle| particle.model.ICU_occupancy(&particle.states[particle.index])).collect()),
+			effective_sample_size,
+			log_likelihood
+		}
+	}
+
+	/// Replaces the particles by a systematic resample according to their weights and resets the weights
+	fn resample(&mut self) {
+		let indices = systematic_resampling(&self.weights, self.rng.uniform());
+		self.particles = indices.iter().map(|&i| self.particles[i].clone()).collect();
+		self.R = indices.iter().map(|&i| self.R[i]).collect();
+		let n = self.particles.len() as f64;
+		self.weights.iter_mut().for_each(|w| *w = 1.0/n);
+	}
+}
+
+/// Indices of the particles drawn by systematic resampling with the offset u (between 0 and 1)
+fn systematic_resampling(weights: &[f64], u: f64) -> Vec<usize> {
+	let n = weights.len();
+	let mut indices = Vec::with_capacity(n);
+	let mut cumulative = weights[0];
+	let mut i = 0;
+	for k in 0..n {
+		let position = (k as f64 + u)/(n as f64);
+		while position > cumulative && i < n-1 {
+			i += 1;
+			cumulative += weights[i];
+		}
+		indices.push(i);
+	}
+	indices
+}
+
+/// Quantile q (between 0 and 1) of values with the given normalized weights
+fn weighted_quantile(values: &[f64], weights: &[f64], q: f64) -> f64 {
+	let mut order: Vec<usize> = (0..values.len()).collect();
+	order.sort_by(|&a, &b| values[a].partial_cmp(&values[b]).unwrap_or(std::cmp::Ordering::Equal));
+	let mut cumulative = 0.0;
+	for &i in order.iter() {
+		cumulative += weights[i];
+		if cumulative >= q {
+			return values[i];
+		}
+	}
+	order.last().map_or(f64::NAN, |&i| values[i])
+}
+
+/// Drops the results older than _days_ days before the current index of the solver
+fn forget_history<M: DelaySystem>(solver: &mut Solver<M>, days: f64) {
+	let keep = (days/solver.dt) as usize;
+	if solver.index > keep {
+		let n = solver.index - keep;
+		solver.time.drain(..n);
+		solver.Rt.drain(..n);
+		solver.states.drain_front(n);
+		solver.N.drain(..n);
+		solver.N_obs.drain(..n);
+		solver.index -= n;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::vaccination_model::solver::tests::small_solver;
+	use crate::vaccination_model::surveillance::SurveillanceGenerator;
+
+	#[test]
+	fn assimilation_brackets_the_true_Rt() {
+		let mut solver = small_solver(1e6);
+		solver.initialize().unwrap();
+		solver.run_constant(20.0, 1.0).unwrap();
+		let mut truth = solver.clone();
+		truth.run_constant(30.0, 1.3).unwrap();
+		let data = truth.synthetic_surveillance(&SurveillanceGenerator::new(3));
+
+		let mut filter = ParticleFilter::new(&solver, 200, 0.3, 1).unwrap();
+		filter.assimilate(&data).unwrap();
+		assert_eq!(filter.filtered.len(), 30);
+		assert!((filter.weights().iter().sum::<f64>() - 1.0).abs() < 1e-12);
+		assert!(filter.weights().iter().all(|w| *w >= 0.0));
+
+		let Rt = truth.model.raw_Rt_to_TTI_corrected(1.3, truth.N_obs[truth.index]);
+		let last = filter.filtered.last().unwrap();
+		assert!(last.Rt[0] < Rt && Rt < last.Rt[2], "{} not in ({}, {})", Rt, last.Rt[0], last.Rt[2]);
+	}
+
+	#[test]
+	fn filters_need_particles() {
+		let mut solver = small_solver(1e6);
+		solver.initialize().unwrap();
+		assert!(matches!(ParticleFilter::new(&solver, 0, 0.3, 1), Err(Error::InvalidParameter { .. })));
+	}
+}
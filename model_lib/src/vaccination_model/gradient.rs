@@ -0,0 +1,612 @@
+//! Exact gradients of trajectories with respect to model parameters (forward mode automatic differentiation).
+//!
+//! The dif. eqs. of the model are implemented once for a generic scalar type (see _Scalar_). With f64 they are the usual model equations,
+//! with dual numbers $x + \sum_k \dot x_k \epsilon_k$ (see _Dual_) every compartment carries its derivatives with respect to P chosen
+//! parameters along (_ModelParameter_: $\eta_0$, $\kappa_0$, $\sigma_\nu$, contact matrix entries and the rates of the age groups). The
+//! Runge-Kutta and adaptive updates are linear, so integrating the dual numbers gives the exact derivatives of the numerical solution,
+//! i.e. the forward sensitivities, without the noise of finite differences.
+//!
+//! _DualModel_ implements _DelaySystem_ with dual numbers, so the usual solver integrates it. _Solver::gradients_ replays a finished run
+//! with it: the raw $R_t$ of the run is held fixed (the derivatives do not include the reaction of the control system), as are the initial
+//! states and the delay $\tau$. Runs whose state was altered by events are not reproduced.
+
+use crate::vaccination_model::age_group_state_vector::{AgeGroupStateVector, States};
+use crate::vaccination_model::delay_system::{DelaySystem, StateVector};
+use crate::vaccination_model::error::Result;
+use crate::vaccination_model::model::{Model, Parameters};
+use crate::vaccination_model::output::Table;
+use crate::vaccination_model::solver::Solver;
+use std::ops::{Add, AddAssign, Div, Mul, Neg, Sub};
+
+/// Scalar type of the dif. eqs.: f64, or dual numbers for derivatives
+pub trait Scalar: Copy + Default + std::fmt::Debug + From<f64> + Add<Output = Self> + Sub<Output = Self> + Mul<Output = Self>
+	+ Div<Output = Self> + Neg<Output = Self> + AddAssign {
+	/// The value (without derivatives)
+	fn value(&self) -> f64;
+	/// Exponential function
+	fn exp(self) -> Self;
+	/// Square root
+	fn sqrt(self) -> Self;
+	/// Minimum with a constant (as f64::min)
+	fn min(self, bound: f64) -> Self;
+	/// Maximum with a constant (as f64::max)
+	fn max(self, bound: f64) -> Self;
+}
+
+impl Scalar for f64 {
+	#[inline(always)]
+	fn value(&self) -> f64 {
+		*self
+	}
+	#[inline(always)]
+	fn exp(self) -> f64 {
+		f64::exp(self)
+	}
+	#[inline(always)]
+	fn sqrt(self) -> f64 {
+		f64::sqrt(self)
+	}
+	#[inline(always)]
+	fn min(self, bound: f64) -> f64 {
+		f64::min(self, bound)
+	}
+	#[inline(always)]
+	fn max(self, bound: f64) -> f64 {
+		f64::max(self, bound)
+	}
+}
+
+/// Dual number: a value and its derivatives with respect to P parameters
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Dual<const P: usize> {
+	/// Value
+	pub value: f64,
+	/// Derivatives of the value with respect to the parameters
+	pub gradient: [f64; P]
+}
+
+impl<const P: usize> Dual<P> {
+	/// The k-th parameter itself, i.e. with derivative 1 with respect to parameter k
+	pub fn variable(value: f64, k: usize) -> Dual<P> {
+		let mut x = Dual::from(value);
+		x.gradient[k] = 1.0;
+		x
+	}
+
+	/// Applies $x \to f(x)$ with the derivative $f'(x)$ (chain rule)
+	fn chain(self, value: f64, derivative: f64) -> Dual<P> {
+		Dual { value, gradient: self.gradient.map(|g| derivative*g) }
+	}
+}
+
+impl<const P: usize> Default for Dual<P> {
+	fn default() -> Dual<P> {
+		Dual { value: 0.0, gradient: [0.0; P] }
+	}
+}
+
+/// Constants have no derivatives
+impl<const P: usize> From<f64> for Dual<P> {
+	fn from(value: f64) -> Dual<P> {
+		Dual { value, gradient: [0.0; P] }
+	}
+}
+
+impl<const P: usize> Add for Dual<P> {
+	type Output = Dual<P>;
+	fn add(self, rhs: Dual<P>) -> Dual<P> {
+		let mut gradient = self.gradient;
+		for (g, r) in gradient.iter_mut().zip(rhs.gradient.iter()) {
+			*g += r;
+		}
+		Dual { value: self.value + rhs.value, gradient }
+	}
+}
+
+impl<const P: usize> AddAssign for Dual<P> {
+	fn add_assign(&mut self, rhs: Dual<P>) {
+		*self = *self + rhs;
+	}
+}
+
+impl<const P: usize> Sub for Dual<P> {
+	type Output = Dual<P>;
+	fn sub(self, rhs: Dual<P>) -> Dual<P> {
+		let mut gradient = self.gradient;
+		for (g, r) in gradient.iter_mut().zip(rhs.gradient.iter()) {
+			*g -= r;
+		}
+		Dual { value: self.value - rhs.value, gradient }
+	}
+}
+
+impl<const P: usize> Mul for Dual<P> {
+	type Output = Dual<P>;
+	fn mul(self, rhs: Dual<P>) -> Dual<P> {
+		let mut gradient = self.gradient;
+		for (g, r) in gradient.iter_mut().zip(rhs.gradient.iter()) {
+			*g = *g*rhs.value + self.value*r;
+		}
+		Dual { value: self.value*rhs.value, gradient }
+	}
+}
+
+impl<const P: usize> Div for Dual<P> {
+	type Output = Dual<P>;
+	fn div(self, rhs: Dual<P>) -> Dual<P> {
+		let value = self.value/rhs.value;
+		let mut gradient = self.gradient;
+		for (g, r) in gradient.iter_mut().zip(rhs.gradient.iter()) {
+			*g = (*g - value*r)/rhs.value;
+		}
+		Dual { value, gradient }
+	}
+}
+
+impl<const P: usize> Neg for Dual<P> {
+	type Output = Dual<P>;
+	fn neg(self) -> Dual<P> {
+		Dual { value: -self.value, gradient: self.gradient.map(|g| -g) }
+	}
+}
+
+impl<const P: usize> Scalar for Dual<P> {
+	fn value(&self) -> f64 {
+		self.value
+	}
+	fn exp(self) -> Dual<P> {
+		let value = self.value.exp();
+		self.chain(value, value)
+	}
+	fn sqrt(self) -> Dual<P> {
+		let value = self.value.sqrt();
+		self.chain(value, 0.5/value)
+	}
+	fn min(self, bound: f64) -> Dual<P> {
+		if self.value <= bound { self } else { Dual::from(bound) }
+	}
+	fn max(self, bound: f64) -> Dual<P> {
+		if self.value >= bound { self } else { Dual::from(bound) }
+	}
+}
+
+/// The solver updates the values and all derivatives alike
+impl<const P: usize> StateVector for AgeGroupStateVector<Dual<P>> {
+	fn apply<const K: usize>(&mut self, others: [&AgeGroupStateVector<Dual<P>>; K], f: impl Fn(f64, [f64; K]) -> f64) {
+		let f = |x: &mut Dual<P>, y: [Dual<P>; K]| {
+			x.value = f(x.value, y.map(|y| y.value));
+			for k in 0..P {
+				x.gradient[k] = f(x.gradient[k], y.map(|y| y.gradient[k]));
+			}
+		};
+		for vacc in 0..3 {
+			f(&mut self.S[vacc], others.map(|o| o.S[vacc]));
+			f(&mut self.E[vacc], others.map(|o| o.E[vacc]));
+			f(&mut self.I[vacc], others.map(|o| o.I[vacc]));
+			f(&mut self.ICU[vacc], others.map(|o| o.ICU[vacc]));
+			f(&mut self.R[vacc], others.map(|o| o.R[vacc]));
+		}
+		for dose in 0..2 {
+			f(&mut self.V[dose], others.map(|o| o.V[dose]));
+		}
+		f(&mut self.D, others.map(|o| o.D));
+		f(&mut self.h, others.map(|o| o.h));
+	}
+
+	/// Visits the values of all compartments, then their derivatives parameter by parameter
+	fn for_each(&self, mut f: impl FnMut(f64)) {
+		let x = self.to_array();
+		x.iter().for_each(|x| f(x.value));
+		for k in 0..P {
+			x.iter().for_each(|x| f(x.gradient[k]));
+		}
+	}
+}
+
+/// A parameter of the vaccination model that derivatives can be taken with respect to. The rates are the ones of the unvaccinated
+/// ($\nu=0$) in age group i, the rates of the vaccinated follow from them as in _Model::initialize_.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ModelParameter {
+	/// $\eta_0$
+	Eta0,
+	/// $\kappa_0$ (enters through the rates of the vaccinated)
+	Kappa0,
+	/// $\sigma_\nu$ of vaccination status $\nu$
+	Sigma(usize),
+	/// Contact matrix entry $c_{ij}$
+	Contact(usize, usize),
+	/// Influx $\phi_i$
+	Influx(usize),
+	/// E-to-I rate $\rho_i$
+	Rho(usize),
+	/// Recovery rate $\gamma_i$ in the I compartment
+	GammaI(usize),
+	/// Recovery rate $\gamma^{ICU}_i$ in the ICU compartment
+	GammaICU(usize),
+	/// I-to-ICU rate $\alpha_i$
+	Alpha(usize),
+	/// Fatality rate $\delta_i$ in the I compartment
+	DeltaI(usize),
+	/// Fatality rate $\delta^{ICU}_i$ in the ICU compartment
+	DeltaICU(usize)
+}
+
+impl ModelParameter {
+	/// Name used in the output files, e.g. "eta0", "contacts_2_3" or "alpha_4" (with the indices of the age groups)
+	pub fn name(&self) -> String {
+		match self {
+			ModelParameter::Eta0 => "eta0".to_string(),
+			ModelParameter::Kappa0 => "kappa0".to_string(),
+			ModelParameter::Sigma(vacc) => format!("sigma{}", vacc),
+			ModelParameter::Contact(i, j) => format!("contacts_{}_{}", i, j),
+			ModelParameter::Influx(i) => format!("influx_{}", i),
+			ModelParameter::Rho(i) => format!("rho_{}", i),
+			ModelParameter::GammaI(i) => format!("gamma_I_{}", i),
+			ModelParameter::GammaICU(i) => format!("gamma_ICU_{}", i),
+			ModelParameter::Alpha(i) => format!("alpha_{}", i),
+			ModelParameter::DeltaI(i) => format!("delta_I_{}", i),
+			ModelParameter::DeltaICU(i) => format!("delta_ICU_{}", i)
+		}
+	}
+}
+
+/// Rates of one age group as dual numbers
+#[derive(Clone, Debug)]
+struct DualRates<const P: usize> {
+	influx: Dual<P>,
+	rho: Dual<P>,
+	gamma_I: [Dual<P>; 3],
+	gamma_ICU: [Dual<P>; 3],
+	alpha: [Dual<P>; 3],
+	delta_I: [Dual<P>; 3],
+	delta_ICU: [Dual<P>; 3]
+}
+
+/// The vaccination model with dual numbers, i.e. with the derivatives with respect to P parameters (see the module documentation)
+#[derive(Clone)]
+pub struct DualModel<const P: usize> {
+	/// The model (vaccination rates, population sizes, delay and TTI limits)
+	model: Model,
+	/// The parameters of the derivatives
+	parameters: [ModelParameter; P],
+	eta0: Dual<P>,
+	sigma: [Dual<P>; 3],
+	contacts: Vec<Vec<Dual<P>>>,
+	age_groups: Vec<DualRates<P>>
+}
+
+impl<const P: usize> DualModel<P> {
+	/// Derivatives of _model_ (initialized, see _Model::initialize_) with respect to the _parameters_
+	pub fn new(model: &Model, parameters: [ModelParameter; P]) -> DualModel<P> {
+		let seed = |parameter: ModelParameter, value: f64| -> Dual<P> {
+			let mut x = Dual::from(value);
+			for (k, p) in parameters.iter().enumerate() {
+				if *p == parameter {
+					x.gradient[k] = 1.0;
+				}
+			}
+			x
+		};
+		let kappa0 = seed(ModelParameter::Kappa0, model.kappa0);
+		let one = Dual::from(1.0);
+
+		let age_groups = model.age_groups.iter().enumerate().map(|(i, ag)| {
+			let gamma_I = seed(ModelParameter::GammaI(i), ag.gamma_I[0]);
+			let alpha = seed(ModelParameter::Alpha(i), ag.alpha[0]);
+			let delta_I = seed(ModelParameter::DeltaI(i), ag.delta_I[0]);
+			let gamma_ICU = seed(ModelParameter::GammaICU(i), ag.gamma_ICU[0]);
+			let delta_ICU = seed(ModelParameter::DeltaICU(i), ag.delta_ICU[0]);
+
+			// Rates of the vaccinated as in Model::initialize
+			let gamma_bar = gamma_I + alpha + delta_I;
+			let alpha = [alpha, (one-kappa0).sqrt()*alpha, (one-kappa0)*alpha];
+			let delta_I = [delta_I, (one-kappa0).sqrt()*delta_I, (one-kappa0)*delta_I];
+			DualRates {
+				influx: seed(ModelParameter::Influx(i), ag.influx),
+				rho: seed(ModelParameter::Rho(i), ag.rho),
+				gamma_I: [gamma_I, gamma_bar-alpha[1]-delta_I[1], gamma_bar-alpha[2]-delta_I[2]],
+				gamma_ICU: [gamma_ICU; 3],
+				alpha,
+				delta_I,
+				delta_ICU: [delta_ICU; 3]
+			}
+		}).collect();
+
+		DualModel {
+			model: model.clone(),
+			parameters,
+			eta0: seed(ModelParameter::Eta0, model.eta0),
+			sigma: [0, 1, 2].map(|vacc| seed(ModelParameter::Sigma(vacc), model.sigma[vacc])),
+			contacts: model.contacts.iter().enumerate().map(|(i, row)| row.iter().enumerate().map(|(j, c)| seed(ModelParameter::Contact(i, j), *c)).collect()).collect(),
+			age_groups
+		}
+	}
+
+	/// The model
+	pub fn model(&self) -> &Model {
+		&self.model
+	}
+
+	/// The parameters of the derivatives
+	pub fn parameters(&self) -> &[ModelParameter; P] {
+		&self.parameters
+	}
+
+	/// Total daily new infections with derivatives
+	pub fn N_dual(&self, state: &[AgeGroupStateVector<Dual<P>>]) -> Dual<P> {
+		self.model.N_with(self, state)
+	}
+
+	/// Daily new infections in age group i with derivatives
+	pub fn N_age_group_dual(&self, state: &[AgeGroupStateVector<Dual<P>>], i: usize) -> Dual<P> {
+		self.model.N_age_group_with(self, state, i)
+	}
+
+	/// Daily ICU admissions with derivatives
+	pub fn ICU_admissions_dual(&self, state: &[AgeGroupStateVector<Dual<P>>]) -> Dual<P> {
+		self.model.ICU_admissions_with(self, state)
+	}
+
+	/// Daily deaths with derivatives
+	pub fn deaths_dual(&self, state: &[AgeGroupStateVector<Dual<P>>]) -> Dual<P> {
+		self.model.deaths_with(self, state)
+	}
+
+	/// Daily deaths in age group i with derivatives
+	pub fn deaths_age_group_dual(&self, state: &[AgeGroupStateVector<Dual<P>>], i: usize) -> Dual<P> {
+		self.model.deaths_age_group_with(self, state, i)
+	}
+
+	/// ICU occupancy with derivatives
+	pub fn ICU_occupancy_dual(&self, state: &[AgeGroupStateVector<Dual<P>>]) -> Dual<P> {
+		self.model.ICU_occupancy_with(state)
+	}
+}
+
+impl<const P: usize> Parameters<Dual<P>> for DualModel<P> {
+	fn eta0(&self) -> Dual<P> {
+		self.eta0
+	}
+	fn sigma(&self, vacc: usize) -> Dual<P> {
+		self.sigma[vacc]
+	}
+	fn contact(&self, i: usize, j: usize) -> Dual<P> {
+		self.contacts[i][j]
+	}
+	fn influx(&self, i: usize) -> Dual<P> {
+		self.age_groups[i].influx
+	}
+	fn rho(&self, i: usize) -> Dual<P> {
+		self.age_groups[i].rho
+	}
+	fn gamma_bar(&self, i: usize) -> Dual<P> {
+		let ag = &self.age_groups[i];
+		ag.gamma_I[0] + ag.alpha[0] + ag.delta_I[0]
+	}
+	fn gamma_I(&self, i: usize, vacc: usize) -> Dual<P> {
+		self.age_groups[i].gamma_I[vacc]
+	}
+	fn gamma_ICU(&self, i: usize, vacc: usize) -> Dual<P> {
+		self.age_groups[i].gamma_ICU[vacc]
+	}
+	fn alpha(&self, i: usize, vacc: usize) -> Dual<P> {
+		self.age_groups[i].alpha[vacc]
+	}
+	fn delta_I(&self, i: usize, vacc: usize) -> Dual<P> {
+		self.age_groups[i].delta_I[vacc]
+	}
+	fn delta_ICU(&self, i: usize, vacc: usize) -> Dual<P> {
+		self.age_groups[i].delta_ICU[vacc]
+	}
+}
+
+/// The vaccination model with derivatives: the observables are the values, the output tables contain the derivatives of all compartments
+/// after the usual columns.
+impl<const P: usize> DelaySystem for DualModel<P> {
+	type State = AgeGroupStateVector<Dual<P>>;
+	/// Delayed infections per member of every age group
+	type Delayed = Vec<Dual<P>>;
+
+	fn delay(&self) -> f64 {
+		self.model.tau
+	}
+
+	fn horizon(&self) -> f64 {
+		self.model.horizon()
+	}
+
+	fn delayed_terms(&self, delayed_R: f64, delayed_state: &[AgeGroupStateVector<Dual<P>>], delayed: &mut Vec<Dual<P>>) {
+		delayed.resize(self.age_groups.len(), Dual::default());
+		for (age_group_index, ipm) in delayed.iter_mut().enumerate() {
+			*ipm = Dual::from(delayed_R)*self.model.I_eff_with(self, age_group_index, delayed_state);
+		}
+	}
+
+	fn slopes_into(&self, t: f64, R: f64, state: &[AgeGroupStateVector<Dual<P>>], delayed_state: &[AgeGroupStateVector<Dual<P>>], delayed: &Vec<Dual<P>>, slopes: &mut [AgeGroupStateVector<Dual<P>>]) {
+		self.model.slopes_with(self, t, R, state, delayed_state, |age_group_index| delayed[age_group_index], slopes);
+	}
+
+	fn prepare_initials(&self, R: f64, initials: &mut [AgeGroupStateVector<Dual<P>>]) {
+		for age_group_index in 0..initials.len() {
+			initials[age_group_index].h = Dual::from(R)*self.model.I_eff_with(self, age_group_index, initials)*Dual::from(self.model.tau);
+		}
+	}
+
+	fn N(&self, state: &[AgeGroupStateVector<Dual<P>>]) -> f64 {
+		self.N_dual(state).value
+	}
+
+	fn ICU_occupancy(&self, state: &[AgeGroupStateVector<Dual<P>>]) -> f64 {
+		self.ICU_occupancy_dual(state).value
+	}
+
+	fn N_block(&self, state: &[AgeGroupStateVector<Dual<P>>], block: usize) -> f64 {
+		self.N_age_group_dual(state, block).value
+	}
+
+	fn ICU_admissions(&self, state: &[AgeGroupStateVector<Dual<P>>]) -> f64 {
+		self.ICU_admissions_dual(state).value
+	}
+
+	fn deaths(&self, state: &[AgeGroupStateVector<Dual<P>>]) -> f64 {
+		self.deaths_dual(state).value
+	}
+
+	fn deaths_block(&self, state: &[AgeGroupStateVector<Dual<P>>], block: usize) -> f64 {
+		self.deaths_age_group_dual(state, block).value
+	}
+
+	fn raw_Rt_to_TTI_corrected(&self, raw_Rt: f64, N: f64) -> f64 {
+		self.model.raw_Rt_to_TTI_corrected(raw_Rt, N)
+	}
+
+	fn raw_Rt_from_TTI_corrected(&self, TTI_Rt: f64, N: f64) -> f64 {
+		self.model.raw_Rt_from_TTI_corrected(TTI_Rt, N)
+	}
+
+	fn block_names(&self) -> Vec<String> {
+		self.model.block_names()
+	}
+
+	/// The columns of the model followed by the derivatives of the compartments, e.g. "dI0/deta0"
+	fn state_columns(&self) -> Vec<String> {
+		let mut columns = self.model.state_columns();
+		let compartments: Vec<String> = columns.iter().take(19).cloned().collect();
+		for parameter in self.parameters.iter() {
+			columns.extend(compartments.iter().map(|name| format!("d{}/d{}", name, parameter.name())));
+		}
+		columns
+	}
+
+	fn state_values(&self, t: f64, block: usize, state: &AgeGroupStateVector<Dual<P>>, initial: bool) -> Vec<f64> {
+		let x = state.to_array();
+		let mut values = self.model.state_values(t, block, &AgeGroupStateVector::from_array(x.map(|x| x.value)), initial);
+		for k in 0..P {
+			values.extend(x.iter().map(|x| x.gradient[k]));
+		}
+		values
+	}
+
+	fn state_description(&self) -> String {
+		self.model.state_description()
+	}
+
+	fn parameter_tables(&self) -> Vec<Table> {
+		self.model.parameter_tables()
+	}
+}
+
+impl Solver<Model> {
+	/// Derivatives of this run with respect to the _parameters_: the run is replayed from its first stored state with the _DualModel_ and the
+	/// same raw $R_t$ (held fixed) and integrator. The returned solver contains the same results (identical with RK4, within the tolerances with
+	/// the adaptive solver, whose error control includes the derivatives), with the derivatives in the states. Fails if the vaccination rates of
+	/// the model do not cover the run (e.g. if they were changed after the run).
+	pub fn gradients<const P: usize>(&self, parameters: [ModelParameter; P]) -> Result<Solver<DualModel<P>>> {
+		let mut solver = Solver {
+			model: DualModel::new(&self.model, parameters),
+			dt: self.dt,
+			integrator: self.integrator,
+			observation: self.observation.clone(),
+			t0: self.time[0],
+			initials: self.states[0].iter().map(|state| AgeGroupStateVector::from_array(state.to_array().map(Dual::from))).collect(),
+			Rt_initial: self.Rt[0],
+			time: Vec::new(),
+			Rt: Vec::new(),
+			states: States::default(),
+			N: Vec::new(),
+			N_obs: Vec::new(),
+			index: 0,
+			events: Vec::new(),
+			event_log: Vec::new()
+		};
+		solver.initialize()?;
+
+		// Integrate day by day like controlled_run (the RK4 solver looks up the delayed state differently within longer integrations),
+		// splitting the days where Rt changes (Rt[j] is used for the step from j-1 to j)
+		let day = (1./self.dt) as usize;
+		let mut time: Vec<f64> = Vec::new();
+		let mut Rt: Vec<f64> = Vec::new();
+		let mut states = States::default();
+		let mut N: Vec<f64> = Vec::new();
+		let mut start = 1;
+		while start <= self.index {
+			let R = self.Rt[start];
+			let steps = self.Rt[start..self.index+1].iter().take_while(|&&x| x == R).count().min(day);
+			solver.integrate((steps as f64 + 0.5)*self.dt, &mut time, &mut Rt, &mut states, &mut N, &solver.time, &solver.Rt, &solver.states, R)?;
+			solver.time.extend_from_slice(&time);
+			solver.Rt.extend_from_slice(&Rt);
+			solver.states.extend_from(&states, 0..states.len());
+			solver.N.extend_from_slice(&N);
+			solver.index += time.len();
+
+			time.clear();
+			Rt.clear();
+			states.clear();
+			N.clear();
+			start += steps;
+		}
+		solver.N_obs = self.N_obs.clone();
+		Ok(solver)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::vaccination_model::solver::tests::small_solver;
+
+	/// The model with the parameter changed by h (and the rates of the vaccinated following as in Model::initialize)
+	fn perturbed(model: &Model, parameter: ModelParameter, h: f64) -> Model {
+		let mut model = model.clone();
+		match parameter {
+			ModelParameter::Eta0 => model.eta0 += h,
+			ModelParameter::Kappa0 => model.kappa0 += h,
+			ModelParameter::Contact(i, j) => model.contacts[i][j] += h,
+			ModelParameter::Alpha(i) => model.age_groups[i].alpha[0] += h,
+			_ => unimplemented!()
+		}
+		let kappa0 = model.kappa0;
+		for ag in model.age_groups.iter_mut() {
+			ag.alpha[1] = (1.-kappa0).sqrt()*ag.alpha[0];
+			ag.alpha[2] = (1.-kappa0)*ag.alpha[0];
+			ag.delta_I[1] = (1.-kappa0).sqrt()*ag.delta_I[0];
+			ag.delta_I[2] = (1.-kappa0)*ag.delta_I[0];
+			ag.gamma_I[1] = ag.gamma_bar()-ag.alpha[1]-ag.delta_I[1];
+			ag.gamma_I[2] = ag.gamma_bar()-ag.alpha[2]-ag.delta_I[2];
+		}
+		model
+	}
+
+	#[test]
+	fn gradients_agree_with_finite_differences() {
+		let mut solver = small_solver(1e6);
+		solver.initialize().unwrap();
+		solver.run_constant(120.0, 1.2).unwrap();
+		let parameters = [ModelParameter::Eta0, ModelParameter::Kappa0, ModelParameter::Contact(0, 1), ModelParameter::Alpha(0)];
+		let dual = solver.gradients(parameters).unwrap();
+		assert_eq!(dual.index, solver.index);
+		let ICU = dual.model.ICU_occupancy_dual(&dual.states[dual.index]);
+		let deaths = dual.model.deaths_dual(&dual.states[dual.index]);
+		assert!((ICU.value() - solver.model.ICU_occupancy(&solver.states[solver.index])).abs() < 1e-12*ICU.value());
+
+		// Fourth order central differences of runs from the same first state (the finite differences of second order are limited to a relative
+		// accuracy of about 1e-8 by their truncation and rounding errors)
+		let run = |model: Model| -> (f64, f64) {
+			let mut run = solver.clone();
+			run.model = model;
+			run.initials = solver.states[0].to_vec();
+			run.initialize().unwrap();
+			run.run_constant(120.0, 1.2).unwrap();
+			(run.model.ICU_occupancy(&run.states[run.index]), run.model.deaths(&run.states[run.index]))
+		};
+		let values = [solver.model.eta0, solver.model.kappa0, solver.model.contacts[0][1], solver.model.age_groups[0].alpha[0]];
+		for (k, (parameter, value)) in parameters.iter().zip(values.iter()).enumerate() {
+			let h = 1e-3*value;
+			let [up2, up, down, down2] = [2.0, 1.0, -1.0, -2.0].map(|steps| run(perturbed(&solver.model, *parameter, steps*h)));
+			let difference = |x: fn(&(f64, f64)) -> f64| (-x(&up2) + 8.0*x(&up) - 8.0*x(&down) + x(&down2))/(12.0*h);
+			for (exact, difference) in [(ICU.gradient[k], difference(|x| x.0)), (deaths.gradient[k], difference(|x| x.1))] {
+				assert!((exact - difference).abs() < 1e-8*exact.abs(), "{}: {} instead of {}", parameter.name(), exact, difference);
+			}
+		}
+	}
+}
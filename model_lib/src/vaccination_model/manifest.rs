@@ -0,0 +1,838 @@
+//! Self-describing manifests of runs for reproducibility.
+//!
+//! A _Manifest_ records everything that determines a run: the name and version of the library, all model and age group parameters (after
+//! initialization, including the prepared vaccination rates), the solver settings and initial values, the schedule with its controllers and the
+//! length of the run, the ICU capacity for the summary metrics (optional), plus free-form metadata (e.g. the inputs of the tool in their original
+//! form). It is written as JSON ("manifest.json") next to the results, _Manifest::read\_from\_disk_ reads it back and _Manifest::rerun_
+//! repeats the run.
+//!
+//! Floating point numbers are written in the shortest form that reads back to the same bits (non-finite numbers as the strings "inf", "-inf"
+//! and "NaN"), so the repeated run is bit-identical. Conditions of phases and events are closures, they cannot be written down: schedules with
+//! phases ending at conditions, controllers without a _Controller::description_ and solvers with registered events are rejected.
+
+use crate::vaccination_model::age_group_state_vector::{AgeGroupStateVector, States};
+use crate::vaccination_model::controller::{BangBang, Controller, PD, PID};
+use crate::vaccination_model::error::{read_file, with_path, Error, Result};
+use crate::vaccination_model::model::{AgeGroup, Model};
+use crate::vaccination_model::observation::{Holiday, ObservationModel};
+use crate::vaccination_model::schedule::{Phase, Schedule, Until};
+use crate::vaccination_model::sink::{Directory, Sink};
+use crate::vaccination_model::solver::{Integrator, Solver};
+use crate::vaccination_model::target::Target;
+
+/// Version of the manifest format
+const FORMAT_VERSION: f64 = 1.0;
+
+/// The complete configuration of a run, see the module documentation.
+#[derive(Clone)]
+pub struct Manifest {
+	/// Name of the library that produced the run
+	pub software: String,
+	/// Version of the library
+	pub version: String,
+	/// Free-form description of the run (name and value pairs), not needed to repeat it
+	pub metadata: Vec<(String, String)>,
+	/// The solver before the run: model, settings and initial values, without results
+	pub solver: Solver,
+	/// Schedule of the controlled run (None if the solver was only initialized)
+	pub schedule: Option<Schedule<AgeGroupStateVector>>,
+	/// Length of the run
+	pub T: f64,
+	/// ICU capacity of the run for the summary metrics (see _Solver::summary_), None if not given
+	pub ICU_capacity: Option<f64>
+}
+
+impl Manifest {
+	/// Manifest of a run of _solver_ for a timespan T following _schedule_, i.e. of _solver.initialize()_ followed by
+	/// _solver.controlled\_run(T, schedule)_ (the solver can be the one before or after the run).
+	///
+	/// Fails if the solver has registered events or the schedule has phases ending at conditions or controllers without a description.
+	pub fn new(solver: &Solver, schedule: Option<&Schedule<AgeGroupStateVector>>, T: f64) -> Result<Manifest> {
+		if !solver.events.is_empty() {
+			return Err(Error::Unrecordable(format!("Event \"{}\"", solver.events[0].name)));
+		}
+		for (i, phase) in schedule.map_or(&[][..], |schedule| schedule.phases()).iter().enumerate() {
+			if let Until::Condition(_) = phase.until {
+				return Err(Error::Unrecordable(format!("The end condition of phase {}", i)));
+			}
+			if phase.controller.description().is_none() {
+				return Err(Error::Unrecordable(format!("The controller of phase {}", i)));
+			}
+		}
+
+		// The first state is the one before the initialization of the solver adjusted the initial values (see DelaySystem::prepare_initials)
+		let initials = if solver.states.is_empty() { solver.initials.clone() } else { solver.states[0].to_vec() };
+		Ok(Manifest {
+			software: env!("CARGO_PKG_NAME").to_string(),
+			version: env!("CARGO_PKG_VERSION").to_string(),
+			metadata: Vec::new(),
+			solver: Solver {
+				model: solver.model.clone(),
+				dt: solver.dt,
+				integrator: solver.integrator,
+				observation: solver.observation.clone(),
+				t0: solver.t0,
+				states: States::with_capacity(initials.len(), 0),
+				initials,
+				Rt_initial: solver.Rt_initial,
+				time: Vec::new(),
+				Rt: Vec::new(),
+				N: Vec::new(),
+				N_obs: Vec::new(),
+				index: 0,
+				events: Vec::new(),
+				event_log: Vec::new()
+			},
+			schedule: schedule.cloned(),
+			T,
+			ICU_capacity: None
+		})
+	}
+
+	/// Adds an entry to the metadata
+	pub fn metadata(mut self, name: &str, value: &str) -> Manifest {
+		self.metadata.push((name.to_string(), value.to_string()));
+		self
+	}
+
+	/// Sets the ICU capacity of the run
+	pub fn ICU_capacity(mut self, ICU_capacity: f64) -> Manifest {
+		self.ICU_capacity = Some(ICU_capacity);
+		self
+	}
+
+	/// Repeats the run: initializes a copy of the recorded solver and runs it following the schedule (if any). Fails like _Solver::controlled\_run_.
+	pub fn rerun(&self) -> Result<Solver> {
+		let mut solver = self.solver.clone();
+		solver.initialize()?;
+		if let Some(schedule) = &self.schedule {
+			solver.controlled_run(self.T, schedule)?;
+		}
+		Ok(solver)
+	}
+
+	/// Writes the manifest to the file "./data/foldername/manifest.json"
+	pub fn write_to_disk(&self, foldername: &str) -> Result<()> {
+		self.write_to(&mut Directory::data(foldername))
+	}
+
+	/// Same as _write\_to\_disk_, but writes "manifest.json" to the _sink_ (see the _sink_ submodule)
+	pub fn write_to(&self, sink: &mut dyn Sink) -> Result<()> {
+		let json = self.to_json();
+		sink.write_file("manifest.json", &mut |file| file.write_all(json.as_bytes()))
+	}
+
+	/// Reads a manifest written by _write\_to\_disk_ from the file _filename_. Fails if the file is not a manifest of this format version.
+	pub fn read_from_disk(filename: &str) -> Result<Manifest> {
+		Manifest::from_json(&read_file(filename)?).map_err(|error| match error {
+			Error::Io(error) => with_path(filename)(error),
+			error => error
+		})
+	}
+
+	/// The manifest as JSON
+	pub fn to_json(&self) -> String {
+		let model = &self.solver.model;
+		let age_groups = model.age_groups.iter().map(|age_group| Value::object(vec![
+			("name", Value::String(age_group.name.clone())),
+			("M", number(age_group.M)),
+			("influx", number(age_group.influx)),
+			("rho", number(age_group.rho)),
+			("gamma_I", numbers(&age_group.gamma_I)),
+			("gamma_ICU", numbers(&age_group.gamma_ICU)),
+			("alpha", numbers(&age_group.alpha)),
+			("delta_I", numbers(&age_group.delta_I)),
+			("delta_ICU", numbers(&age_group.delta_ICU)),
+			("eligible_fraction", number(age_group.eligible_fraction)),
+			("min_uptake", number(age_group.min_uptake)),
+			("max_uptake", number(age_group.max_uptake)),
+			("phase", number(age_group.phase as f64))
+		])).collect();
+		let model = Value::object(vec![
+			("M", number(model.M)),
+			("eta0", number(model.eta0)),
+			("sigma", numbers(&model.sigma)),
+			("kappa0", number(model.kappa0)),
+			("tau", number(model.tau)),
+			("tau_vacc", number(model.tau_vacc as f64)),
+			("random_vacc", number(model.random_vacc)),
+			("N_TTI", number(model.N_TTI)),
+			("N_test_eff", number(model.N_test_eff)),
+			("N_test_ineff", number(model.N_test_ineff)),
+			("N_no_test", number(model.N_no_test)),
+			("contacts", matrix(&model.contacts)),
+			("vaccinations_per_week_dose1", matrix(&model.vaccinations_per_week_dose1)),
+			("vaccinations_per_week_dose2", matrix(&model.vaccinations_per_week_dose2)),
+			("age_groups", Value::Array(age_groups))
+		]);
+
+		let observation = &self.solver.observation;
+		let holidays = observation.holidays.iter().map(|holiday| Value::object(vec![
+			("start", number(holiday.start)), ("end", number(holiday.end)), ("factor", number(holiday.factor))
+		])).collect();
+		let integrator = match self.solver.integrator {
+			Integrator::RK4 => Value::object(vec![("type", Value::string("RK4"))]),
+			Integrator::Adaptive { rtol, atol } => Value::object(vec![("type", Value::string("Adaptive")), ("rtol", number(rtol)), ("atol", number(atol))])
+		};
+		let initials = self.solver.initials.iter().map(|state| Value::object(vec![
+			("S", numbers(&state.S)), ("V", numbers(&state.V)), ("E", numbers(&state.E)), ("I", numbers(&state.I)),
+			("ICU", numbers(&state.ICU)), ("D", number(state.D)), ("R", numbers(&state.R)), ("h", number(state.h))
+		])).collect();
+		let solver = Value::object(vec![
+			("dt", number(self.solver.dt)),
+			("integrator", integrator),
+			("t0", number(self.solver.t0)),
+			("Rt_initial", number(self.solver.Rt_initial)),
+			("initials", Value::Array(initials)),
+			("observation", Value::object(vec![
+				("case_delay", numbers(&observation.case_delay)),
+				("admission_delay", numbers(&observation.admission_delay)),
+				("death_delay", numbers(&observation.death_delay)),
+				("ascertainment", numbers(&observation.ascertainment)),
+				("weekday_effects", numbers(&observation.weekday_effects)),
+				("first_weekday", number(observation.first_weekday as f64)),
+				("holidays", Value::Array(holidays))
+			]))
+		]);
+
+		let schedule = match &self.schedule {
+			Some(schedule) => Value::Array(schedule.phases().iter().map(|phase| {
+				let until = match phase.until {
+					Until::Time(t) => Value::object(vec![("type", Value::string("Time")), ("t", number(t))]),
+					_ => Value::object(vec![("type", Value::string("Never"))])
+				};
+				let (name, parameters) = phase.controller.description().unwrap_or(("", Vec::new()));
+				let mut controller = vec![("type", Value::string(name))];
+				controller.extend(parameters.into_iter().map(|(name, value)| (name, number(value))));
+				Value::object(vec![
+					("until", until),
+					("target", target_value(&phase.target)),
+					("set_point", number(phase.set_point)),
+					("min_Rt", number(phase.min_Rt)),
+					("max_Rt", number(phase.max_Rt)),
+					("max_slope", number(phase.max_slope)),
+					("controller", Value::object(controller)),
+					("measure_ICU_duration", Value::Bool(phase.measure_ICU_duration))
+				])
+			}).collect()),
+			None => Value::Null
+		};
+
+		let manifest = Value::object(vec![
+			("manifest_version", number(FORMAT_VERSION)),
+			("software", Value::String(self.software.clone())),
+			("version", Value::String(self.version.clone())),
+			("metadata", Value::Object(self.metadata.iter().map(|(name, value)| (name.clone(), Value::String(value.clone()))).collect())),
+			("T", number(self.T)),
+			("ICU_capacity", self.ICU_capacity.map_or(Value::Null, number)),
+			("model", model),
+			("solver", solver),
+			("schedule", schedule)
+		]);
+		let mut json = String::new();
+		manifest.write(&mut json, 0);
+		json.push('\n');
+		json
+	}
+
+	/// Reads a manifest from JSON written by _to\_json_
+	pub fn from_json(json: &str) -> Result<Manifest> {
+		let manifest = Parser { text: json.as_bytes(), position: 0 }.document()?;
+		let manifest = manifest.fields("")?;
+		if manifest.f64("manifest_version")? != FORMAT_VERSION {
+			return Err(invalid("unsupported manifest version".to_string()));
+		}
+
+		let fields = manifest.object("model")?;
+		let mut age_groups = Vec::new();
+		for (i, age_group) in fields.array("age_groups")?.iter().enumerate() {
+			let fields = age_group.fields(&format!("model.age_groups[{}]", i))?;
+			age_groups.push(AgeGroup {
+				name: fields.string("name")?,
+				M: fields.f64("M")?,
+				influx: fields.f64("influx")?,
+				rho: fields.f64("rho")?,
+				gamma_I: fields.f64s("gamma_I")?,
+				gamma_ICU: fields.f64s("gamma_ICU")?,
+				alpha: fields.f64s("alpha")?,
+				delta_I: fields.f64s("delta_I")?,
+				delta_ICU: fields.f64s("delta_ICU")?,
+				eligible_fraction: fields.f64("eligible_fraction")?,
+				min_uptake: fields.f64("min_uptake")?,
+				max_uptake: fields.f64("max_uptake")?,
+				phase: fields.integer("phase")? as i32
+			});
+		}
+		let model = Model {
+			age_groups,
+			M: fields.f64("M")?,
+			eta0: fields.f64("eta0")?,
+			sigma: fields.f64s("sigma")?,
+			kappa0: fields.f64("kappa0")?,
+			tau: fields.f64("tau")?,
+			tau_vacc: fields.integer("tau_vacc")? as usize,
+			vaccinations_per_week_dose1: fields.matrix("vaccinations_per_week_dose1")?,
+			vaccinations_per_week_dose2: fields.matrix("vaccinations_per_week_dose2")?,
+			random_vacc: fields.f64("random_vacc")?,
+			N_TTI: fields.f64("N_TTI")?,
+			N_test_eff: fields.f64("N_test_eff")?,
+			N_test_ineff: fields.f64("N_test_ineff")?,
+			N_no_test: fields.f64("N_no_test")?,
+			contacts: fields.matrix("contacts")?
+		};
+
+		let fields = manifest.object("solver")?;
+		let integrator = fields.object("integrator")?;
+		let integrator = match integrator.string("type")?.as_str() {
+			"RK4" => Integrator::RK4,
+			"Adaptive" => Integrator::Adaptive { rtol: integrator.f64("rtol")?, atol: integrator.f64("atol")? },
+			name => return Err(invalid(format!("unknown integrator {}", name)))
+		};
+		let mut initials = Vec::new();
+		for (i, state) in fields.array("initials")?.iter().enumerate() {
+			let state = state.fields(&format!("solver.initials[{}]", i))?;
+			initials.push(AgeGroupStateVector { S: state.f64s("S")?, V: state.f64s("V")?, E: state.f64s("E")?, I: state.f64s("I")?,
+												ICU: state.f64s("ICU")?, D: state.f64("D")?, R: state.f64s("R")?, h: state.f64("h")? });
+		}
+		let observation = fields.object("observation")?;
+		let mut holidays = Vec::new();
+		for (i, holiday) in observation.array("holidays")?.iter().enumerate() {
+			let holiday = holiday.fields(&format!("solver.observation.holidays[{}]", i))?;
+			holidays.push(Holiday { start: holiday.f64("start")?, end: holiday.f64("end")?, factor: holiday.f64("factor")? });
+		}
+		let observation = ObservationModel {
+			case_delay: observation.f64s("case_delay")?,
+			admission_delay: observation.f64s("admission_delay")?,
+			death_delay: observation.f64s("death_delay")?,
+			ascertainment: observation.f64s("ascertainment")?,
+			weekday_effects: observation.f64s("weekday_effects")?,
+			first_weekday: observation.integer("first_weekday")? as usize,
+			holidays
+		};
+		let solver = Solver {
+			model,
+			dt: fields.f64("dt")?,
+			integrator,
+			observation,
+			t0: fields.f64("t0")?,
+			states: States::with_capacity(initials.len(), 0),
+			initials,
+			Rt_initial: fields.f64("Rt_initial")?,
+			time: Vec::new(),
+			Rt: Vec::new(),
+			N: Vec::new(),
+			N_obs: Vec::new(),
+			index: 0,
+			events: Vec::new(),
+			event_log: Vec::new()
+		};
+
+		let schedule = match manifest.value("schedule")? {
+			Value::Null => None,
+			Value::Array(phases) => {
+				let mut schedule = Vec::new();
+				for (i, phase) in phases.iter().enumerate() {
+					let fields = phase.fields(&format!("schedule[{}]", i))?;
+					let until = fields.object("until")?;
+					let until = match until.string("type")?.as_str() {
+						"Time" => Until::Time(until.f64("t")?),
+						"Never" => Until::Never,
+						name => return Err(invalid(format!("unknown end of phase {}", name)))
+					};
+					schedule.push(Phase {
+						until,
+						min_Rt: fields.f64("min_Rt")?,
+						max_Rt: fields.f64("max_Rt")?,
+						max_slope: fields.f64("max_slope")?,
+						target: target_from(&fields.object("target")?)?,
+						set_point: fields.f64("set_point")?,
+						controller: controller_from(&fields.object("controller")?)?,
+						measure_ICU_duration: fields.bool("measure_ICU_duration")?
+					});
+				}
+				Some(Schedule::new(schedule)?)
+			},
+			_ => return Err(invalid("schedule is neither null nor an array".to_string()))
+		};
+
+		Ok(Manifest {
+			software: manifest.string("software")?,
+			version: manifest.string("version")?,
+			metadata: manifest.object("metadata")?.entries.iter().map(|(name, value)| match value {
+				Value::String(value) => Ok((name.clone(), value.clone())),
+				_ => Err(invalid(format!("metadata.{} is not a string", name)))
+			}).collect::<Result<_>>()?,
+			solver,
+			schedule,
+			T: manifest.f64("T")?,
+			ICU_capacity: manifest.optional_f64("ICU_capacity")?
+		})
+	}
+}
+
+/// Error for invalid manifests
+fn invalid(message: String) -> Error {
+	Error::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Invalid manifest: {}", message)))
+}
+
+/// JSON value of the target (tagged with its type)
+fn target_value(target: &Target) -> Value {
+	let tag = |name: &str| ("type", Value::string(name));
+	match target {
+		Target::ObservedCases => Value::object(vec![tag("ObservedCases")]),
+		Target::ICUOccupancy => Value::object(vec![tag("ICUOccupancy")]),
+		Target::ICUAdmissions => Value::object(vec![tag("ICUAdmissions")]),
+		Target::Deaths => Value::object(vec![tag("Deaths")]),
+		Target::TestPositivity { tests_per_day } => Value::object(vec![tag("TestPositivity"), ("tests_per_day", number(*tests_per_day))]),
+		Target::AgeIncidence { age_groups } => Value::object(vec![tag("AgeIncidence"), ("age_groups", Value::Array(age_groups.iter().map(|i| number(*i as f64)).collect()))]),
+		Target::Weighted(components) => Value::object(vec![tag("Weighted"), ("components", Value::Array(components.iter().map(|(weight, target)| {
+			Value::object(vec![("weight", number(*weight)), ("target", target_value(target))])
+		}).collect()))]),
+		Target::Delayed { target, kernel } => Value::object(vec![tag("Delayed"), ("target", target_value(target)), ("kernel", numbers(kernel))])
+	}
+}
+
+/// Target written by _target\_value_
+fn target_from(fields: &Fields) -> Result<Target> {
+	Ok(match fields.string("type")?.as_str() {
+		"ObservedCases" => Target::ObservedCases,
+		"ICUOccupancy" => Target::ICUOccupancy,
+		"ICUAdmissions" => Target::ICUAdmissions,
+		"Deaths" => Target::Deaths,
+		"TestPositivity" => Target::TestPositivity { tests_per_day: fields.f64("tests_per_day")? },
+		"AgeIncidence" => Target::AgeIncidence { age_groups: fields.f64s::<Vec<f64>>("age_groups")?.iter().map(|i| *i as usize).collect() },
+		"Weighted" => {
+			let mut components = Vec::new();
+			for (i, component) in fields.array("components")?.iter().enumerate() {
+				let component = component.fields(&format!("{}.components[{}]", fields.path, i))?;
+				components.push((component.f64("weight")?, target_from(&component.object("target")?)?));
+			}
+			Target::Weighted(components)
+		},
+		"Delayed" => Target::Delayed { target: Box::new(target_from(&fields.object("target")?)?), kernel: fields.f64s("kernel")? },
+		name => return Err(invalid(format!("unknown target {}", name)))
+	})
+}
+
+/// Controller written from its _Controller::description_
+fn controller_from(fields: &Fields) -> Result<Box<dyn Controller>> {
+	Ok(match fields.string("type")?.as_str() {
+		"PD" => Box::new(PD { kp: fields.f64("kp")?, kd: fields.f64("kd")?, kp_settled: fields.f64("kp_settled")?, kd_settled: fields.f64("kd_settled")?,
+							  error_tolerance: fields.f64("error_tolerance")?, change_tolerance: fields.f64("change_tolerance")?, preview_length: fields.f64("preview_length")? }),
+		"PID" => Box::new(PID { kp: fields.f64("kp")?, ki: fields.f64("ki")?, kd: fields.f64("kd")?, integral_limit: fields.f64("integral_limit")?,
+								preview_length: fields.f64("preview_length")?, integral: fields.f64("integral")? }),
+		"BangBang" => Box::new(BangBang { tolerance: fields.f64("tolerance")?, step: fields.f64("step")?, preview_length: fields.f64("preview_length")? }),
+		name => return Err(invalid(format!("unknown controller {}", name)))
+	})
+}
+
+/// JSON number, non-finite numbers as strings
+fn number(x: f64) -> Value {
+	if x.is_finite() {
+		Value::Number(x)
+	} else {
+		Value::String(format!("{}", x))
+	}
+}
+
+/// JSON array of numbers
+fn numbers(x: &[f64]) -> Value {
+	Value::Array(x.iter().map(|x| number(*x)).collect())
+}
+
+/// JSON array of arrays of numbers
+fn matrix(x: &[Vec<f64>]) -> Value {
+	Value::Array(x.iter().map(|row| numbers(row)).collect())
+}
+
+/// A JSON value. Objects keep the order of their entries.
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+	Null,
+	Bool(bool),
+	Number(f64),
+	String(String),
+	Array(Vec<Value>),
+	Object(Vec<(String, Value)>)
+}
+
+impl Value {
+	fn string(text: &str) -> Value {
+		Value::String(text.to_string())
+	}
+
+	fn object(entries: Vec<(&str, Value)>) -> Value {
+		Value::Object(entries.into_iter().map(|(name, value)| (name.to_string(), value)).collect())
+	}
+
+	/// Appends the value to _json_, objects and arrays of arrays or objects with one entry per line
+	fn write(&self, json: &mut String, indent: usize) {
+		let newline = |json: &mut String, indent: usize| {
+			json.push('\n');
+			json.extend(std::iter::repeat_n('\t', indent));
+		};
+		match self {
+			Value::Null => json.push_str("null"),
+			Value::Bool(value) => json.push_str(if *value { "true" } else { "false" }),
+			Value::Number(x) => json.push_str(&format!("{:?}", x)),
+			Value::String(text) => {
+				json.push('"');
+				for c in text.chars() {
+					match c {
+						'"' => json.push_str("\\\""),
+						'\\' => json.push_str("\\\\"),
+						'\n' => json.push_str("\\n"),
+						'\r' => json.push_str("\\r"),
+						'\t' => json.push_str("\\t"),
+						c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+						c => json.push(c)
+					}
+				}
+				json.push('"');
+			},
+			Value::Array(values) => {
+				let nested = values.iter().any(|value| matches!(value, Value::Array(_) | Value::Object(_)));
+				json.push('[');
+				for (i, value) in values.iter().enumerate() {
+					if i > 0 {
+						json.push(',');
+						if !nested {
+							json.push(' ');
+						}
+					}
+					if nested {
+						newline(json, indent+1);
+					}
+					value.write(json, indent+1);
+				}
+				if nested {
+					newline(json, indent);
+				}
+				json.push(']');
+			},
+			Value::Object(entries) => {
+				json.push('{');
+				for (i, (name, value)) in entries.iter().enumerate() {
+					if i > 0 {
+						json.push(',');
+					}
+					newline(json, indent+1);
+					Value::String(name.clone()).write(json, indent+1);
+					json.push_str(": ");
+					value.write(json, indent+1);
+				}
+				if !entries.is_empty() {
+					newline(json, indent);
+				}
+				json.push('}');
+			}
+		}
+	}
+
+	/// The entries of an object, _path_ names the value in error messages
+	fn fields(&self, path: &str) -> Result<Fields<'_>> {
+		match self {
+			Value::Object(entries) => Ok(Fields { entries, path: path.to_string() }),
+			_ => Err(invalid(format!("{} is not an object", path)))
+		}
+	}
+
+	/// Number (or non-finite number written as a string)
+	fn number(&self, path: &str) -> Result<f64> {
+		match self {
+			Value::Number(x) => Ok(*x),
+			Value::String(text) if text == "inf" || text == "-inf" || text == "NaN" => Ok(text.parse().unwrap_or(f64::NAN)),
+			_ => Err(invalid(format!("{} is not a number", path)))
+		}
+	}
+}
+
+/// The entries of a JSON object with typed access
+struct Fields<'a> {
+	entries: &'a [(String, Value)],
+	/// Path of the object (for error messages)
+	path: String
+}
+
+impl<'a> Fields<'a> {
+	fn path(&self, name: &str) -> String {
+		if self.path.is_empty() { name.to_string() } else { format!("{}.{}", self.path, name) }
+	}
+
+	fn value(&self, name: &str) -> Result<&'a Value> {
+		self.entries.iter().find(|(key, _)| key == name).map(|(_, value)| value).ok_or_else(|| invalid(format!("{} is missing", self.path(name))))
+	}
+
+	fn object(&self, name: &str) -> Result<Fields<'a>> {
+		self.value(name)?.fields(&self.path(name))
+	}
+
+	fn array(&self, name: &str) -> Result<&'a [Value]> {
+		match self.value(name)? {
+			Value::Array(values) => Ok(values),
+			_ => Err(invalid(format!("{} is not an array", self.path(name))))
+		}
+	}
+
+	fn f64(&self, name: &str) -> Result<f64> {
+		self.value(name)?.number(&self.path(name))
+	}
+
+	/// Number or null (also if missing)
+	fn optional_f64(&self, name: &str) -> Result<Option<f64>> {
+		match self.value(name) {
+			Ok(Value::Null) | Err(_) => Ok(None),
+			Ok(value) => value.number(&self.path(name)).map(Some)
+		}
+	}
+
+	/// Number without fractional part
+	fn integer(&self, name: &str) -> Result<i64> {
+		let x = self.f64(name)?;
+		if x.fract() == 0.0 && x.abs() < 9e15 {
+			Ok(x as i64)
+		} else {
+			Err(invalid(format!("{} is not an integer", self.path(name))))
+		}
+	}
+
+	fn string(&self, name: &str) -> Result<String> {
+		match self.value(name)? {
+			Value::String(text) => Ok(text.clone()),
+			_ => Err(invalid(format!("{} is not a string", self.path(name))))
+		}
+	}
+
+	fn bool(&self, name: &str) -> Result<bool> {
+		match self.value(name)? {
+			Value::Bool(value) => Ok(*value),
+			_ => Err(invalid(format!("{} is not a boolean", self.path(name))))
+		}
+	}
+
+	/// Array of numbers as a vector or a fixed size array
+	fn f64s<T: std::convert::TryFrom<Vec<f64>>>(&self, name: &str) -> Result<T> {
+		let path = self.path(name);
+		let values = self.array(name)?.iter().enumerate().map(|(i, value)| value.number(&format!("{}[{}]", path, i))).collect::<Result<Vec<f64>>>()?;
+		let len = values.len();
+		T::try_from(values).map_err(|_| invalid(format!("{} has the wrong length {}", path, len)))
+	}
+
+	fn matrix(&self, name: &str) -> Result<Vec<Vec<f64>>> {
+		let path = self.path(name);
+		self.array(name)?.iter().enumerate().map(|(i, row)| match row {
+			Value::Array(values) => values.iter().enumerate().map(|(j, value)| value.number(&format!("{}[{}][{}]", path, i, j))).collect(),
+			_ => Err(invalid(format!("{}[{}] is not an array", path, i)))
+		}).collect()
+	}
+}
+
+/// Minimal JSON parser (RFC 8259)
+struct Parser<'a> {
+	text: &'a [u8],
+	position: usize
+}
+
+impl<'a> Parser<'a> {
+	/// Parses the whole text as one value
+	fn document(mut self) -> Result<Value> {
+		let value = self.value()?;
+		self.skip_whitespace();
+		if self.position < self.text.len() {
+			return Err(self.error("unexpected characters after the end"));
+		}
+		Ok(value)
+	}
+
+	fn error(&self, message: &str) -> Error {
+		invalid(format!("{} at byte {}", message, self.position))
+	}
+
+	fn skip_whitespace(&mut self) {
+		while self.position < self.text.len() && matches!(self.text[self.position], b' ' | b'\t' | b'\n' | b'\r') {
+			self.position += 1;
+		}
+	}
+
+	fn peek(&mut self) -> Option<u8> {
+		self.skip_whitespace();
+		self.text.get(self.position).copied()
+	}
+
+	fn expect(&mut self, c: u8) -> Result<()> {
+		if self.peek() == Some(c) {
+			self.position += 1;
+			Ok(())
+		} else {
+			Err(self.error(&format!("expected '{}'", c as char)))
+		}
+	}
+
+	fn literal(&mut self, literal: &str, value: Value) -> Result<Value> {
+		if self.text[self.position..].starts_with(literal.as_bytes()) {
+			self.position += literal.len();
+			Ok(value)
+		} else {
+			Err(self.error("invalid literal"))
+		}
+	}
+
+	fn value(&mut self) -> Result<Value> {
+		match self.peek() {
+			Some(b'{') => {
+				self.position += 1;
+				let mut entries = Vec::new();
+				if self.peek() == Some(b'}') {
+					self.position += 1;
+					return Ok(Value::Object(entries));
+				}
+				loop {
+					let name = self.string()?;
+					self.expect(b':')?;
+					entries.push((name, self.value()?));
+					match self.peek() {
+						Some(b',') => self.position += 1,
+						Some(b'}') => { self.position += 1; return Ok(Value::Object(entries)); },
+						_ => return Err(self.error("expected ',' or '}'"))
+					}
+				}
+			},
+			Some(b'[') => {
+				self.position += 1;
+				let mut values = Vec::new();
+				if self.peek() == Some(b']') {
+					self.position += 1;
+					return Ok(Value::Array(values));
+				}
+				loop {
+					values.push(self.value()?);
+					match self.peek() {
+						Some(b',') => self.position += 1,
+						Some(b']') => { self.position += 1; return Ok(Value::Array(values)); },
+						_ => return Err(self.error("expected ',' or ']'"))
+					}
+				}
+			},
+			Some(b'"') => Ok(Value::String(self.string()?)),
+			Some(b'n') => self.literal("null", Value::Null),
+			Some(b't') => self.literal("true", Value::Bool(true)),
+			Some(b'f') => self.literal("false", Value::Bool(false)),
+			Some(_) => {
+				let start = self.position;
+				while self.position < self.text.len() && matches!(self.text[self.position], b'0'..=b'9' | b'-' | b'+' | b'.' | b'e' | b'E') {
+					self.position += 1;
+				}
+				let number = std::str::from_utf8(&self.text[start..self.position]).ok().and_then(|number| number.parse::<f64>().ok());
+				number.map(Value::Number).ok_or_else(|| invalid(format!("invalid number at byte {}", start)))
+			},
+			None => Err(self.error("unexpected end"))
+		}
+	}
+
+	fn string(&mut self) -> Result<String> {
+		self.expect(b'"')?;
+		let mut bytes = Vec::new();
+		loop {
+			let c = *self.text.get(self.position).ok_or_else(|| self.error("unterminated string"))?;
+			self.position += 1;
+			match c {
+				b'"' => break,
+				b'\\' => {
+					let escape = *self.text.get(self.position).ok_or_else(|| self.error("unterminated string"))?;
+					self.position += 1;
+					let c = match escape {
+						b'"' => '"',
+						b'\\' => '\\',
+						b'/' => '/',
+						b'b' => '\u{8}',
+						b'f' => '\u{c}',
+						b'n' => '\n',
+						b'r' => '\r',
+						b't' => '\t',
+						b'u' => {
+							let code = self.text.get(self.position..self.position+4).and_then(|hex| std::str::from_utf8(hex).ok())
+								.and_then(|hex| u32::from_str_radix(hex, 16).ok()).and_then(std::char::from_u32);
+							self.position += 4;
+							code.ok_or_else(|| self.error("invalid unicode escape"))?
+						},
+						_ => return Err(self.error("invalid escape"))
+					};
+					let mut buffer = [0u8; 4];
+					bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+				},
+				c => bytes.push(c)
+			}
+		}
+		String::from_utf8(bytes).map_err(|_| self.error("invalid UTF-8 in string"))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::vaccination_model::controller::{BangBang, PD};
+	use crate::vaccination_model::output::Csv;
+	use crate::vaccination_model::schedule::Phase;
+	use crate::vaccination_model::sink::Memory;
+	use crate::vaccination_model::solver::tests::small_solver;
+	use crate::vaccination_model::target::Target;
+
+	fn schedule() -> Schedule<AgeGroupStateVector> {
+		Schedule::new(vec![
+			Phase::new(Target::ICUOccupancy, 10.0).until_time(15.0).Rt_range(0.5, f64::INFINITY).controller(Box::new(PD::ICU())),
+			Phase::new(Target::Weighted(vec![(0.5, Target::ObservedCases), (2.0, Target::AgeIncidence { age_groups: vec![0] })]), 50.0)
+				.until_time(25.0).controller(Box::new(PD::cases())),
+			Phase::new(Target::ICUOccupancy, 5.0).controller(Box::new(BangBang { tolerance: 0.1, step: 0.02, preview_length: 7.0 }))
+		]).unwrap()
+	}
+
+	/// All results of the solver with full precision
+	fn results(solver: &Solver) -> Memory {
+		let mut memory = Memory::new();
+		solver.write_to(&mut memory, 1, &Csv).unwrap();
+		memory
+	}
+
+	#[test]
+	fn json_round_trip() {
+		let manifest = Manifest::new(&small_solver(1e5), Some(&schedule()), 30.0).unwrap()
+			.metadata("scenario", "tab\tquote\"backslash\\ unicode \u{e9}\u{1F600}")
+			.metadata("influx", &format!("{:?}", 0.1f64 + 0.2))
+			.ICU_capacity(0.1 + 0.2);
+		let json = manifest.to_json();
+		let read = Manifest::from_json(&json).unwrap();
+		assert_eq!(read.metadata, manifest.metadata);
+		assert_eq!(read.T, manifest.T);
+		assert_eq!(read.ICU_capacity, Some(0.1 + 0.2));
+		assert_eq!(Manifest::from_json(&Manifest::new(&small_solver(1e5), None, 10.0).unwrap().to_json()).unwrap().ICU_capacity, None);
+		assert_eq!(read.to_json(), json);
+	}
+
+	#[test]
+	fn rejects_malformed_json() {
+		let json = Manifest::new(&small_solver(1e5), None, 10.0).unwrap().to_json();
+		assert!(Manifest::from_json(&json[..json.len()/2]).is_err());
+		assert!(Manifest::from_json(&json.replacen("\"solver\"", "\"solvers\"", 1)).is_err());
+		assert!(Manifest::from_json("[1, 2,]").is_err());
+	}
+
+	#[test]
+	fn rerun_repeats_the_run() {
+		let mut solver = small_solver(1e5);
+		let manifest = Manifest::new(&solver, Some(&schedule()), 30.0).unwrap();
+		solver.initialize().unwrap();
+		solver.controlled_run(30.0, &schedule()).unwrap();
+
+		let mut memory = Memory::new();
+		manifest.write_to(&mut memory).unwrap();
+		let read = Manifest::from_json(memory.text("manifest.json").unwrap()).unwrap();
+		assert_eq!(results(&read.rerun().unwrap()).files, results(&solver).files);
+	}
+
+	#[test]
+	fn rejects_events() {
+		let mut solver = small_solver(1e5);
+		solver.events.push(crate::vaccination_model::events::Event::predicate("never", std::sync::Arc::new(|_, _| false)));
+		assert!(matches!(Manifest::new(&solver, None, 10.0), Err(Error::Unrecordable(_))));
+	}
+}
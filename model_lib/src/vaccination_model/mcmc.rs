@@ -0,0 +1,352 @@
+//! Bayesian inference of model parameters with Markov chain Monte Carlo.
+//!
+//! The posterior of the uncertain inputs is the product of their priors and a likelihood, usually the likelihood of observed data given the
+//! expected reports of the solver (see _estimation_). It is sampled with the adaptive Metropolis algorithm (Haario et al. 2001): the Gaussian
+//! proposal starts with independent steps and, after _adaptation\_start_ iterations, uses the scaled covariance of the chain so far.
+//! Several chains are run from slightly perturbed starting points, so that the convergence can be checked with the potential scale reduction
+//! factor $\hat R$ (close to 1 for converged chains) and the effective sample size.
+//!
+//! The posterior samples can be written to and read from disk and used for posterior predictive runs (see _Posterior::predictive_), which run in
+//! parallel (see _sweep_).
+
+use crate::vaccination_model::error::{read_file, Error, Result};
+use crate::vaccination_model::estimation::Estimation;
+use crate::vaccination_model::random::{ln_gamma, quantile, Rng};
+use crate::vaccination_model::sink::{Directory, Sink};
+use crate::vaccination_model::surveillance::SurveillanceData;
+use crate::vaccination_model::sweep::Sweep;
+
+/// Prior distribution of a parameter
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Prior {
+	/// Uniform between the bounds
+	Uniform { lower: f64, upper: f64 },
+	/// Normal distribution
+	Normal { mean: f64, sd: f64 },
+	/// Log-normal distribution, i.e. the logarithm is normally distributed with mean _mu_ and standard deviation _sigma_
+	LogNormal { mu: f64, sigma: f64 },
+	/// Beta distribution on $\[0, 1\]$ (e.g. for efficacies and fractions)
+	Beta { a: f64, b: f64 }
+}
+
+impl Prior {
+	/// Logarithm of the prior density ($-\infty$ outside the support)
+	pub fn log_density(&self, x: f64) -> f64 {
+		match *self {
+			Prior::Uniform { lower, upper } => if x >= lower && x <= upper { -(upper - lower).ln() } else { f64::NEG_INFINITY },
+			Prior::Normal { mean, sd } => -0.5*((x - mean)/sd).powi(2) - (sd*(2.0*std::f64::consts::PI).sqrt()).ln(),
+			Prior::LogNormal { mu, sigma } => {
+				if x <= 0.0 {
+					return f64::NEG_INFINITY;
+				}
+				-0.5*((x.ln() - mu)/sigma).powi(2) - (x*sigma*(2.0*std::f64::consts::PI).sqrt()).ln()
+			},
+			Prior::Beta { a, b } => {
+				if x <= 0.0 || x >= 1.0 {
+					return f64::NEG_INFINITY;
+				}
+				(a - 1.0)*x.ln() + (b - 1.0)*(1.0 - x).ln() + ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b)
+			}
+		}
+	}
+
+	/// Rough scale of the prior, used for the initial proposal steps
+	fn scale(&self) -> f64 {
+		match *self {
+			Prior::Uniform { lower, upper } => (upper - lower)/12f64.sqrt(),
+			Prior::Normal { sd, .. } => sd,
+			Prior::LogNormal { mu, sigma } => (mu + 0.5*sigma*sigma).exp()*sigma,
+			Prior::Beta { a, b } => (a*b/((a + b).powi(2)*(a + b + 1.0))).sqrt()
+		}
+	}
+}
+
+/// An uncertain input with its prior and the starting value of the chains
+#[derive(Clone, Debug, PartialEq)]
+pub struct Uncertain {
+	/// Name of the parameter
+	pub name: String,
+	/// Prior distribution
+	pub prior: Prior,
+	/// Starting value of the chains
+	pub initial: f64
+}
+
+impl Uncertain {
+	/// Uncertain parameter _name_ with the given prior, the chains start at _initial_
+	pub fn new(name: &str, prior: Prior, initial: f64) -> Uncertain {
+		Uncertain { name: name.to_string(), prior, initial }
+	}
+}
+
+/// Settings of the adaptive Metropolis sampler
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MCMC {
+	/// Number of independent chains
+	pub chains: usize,
+	/// Number of kept samples per chain
+	pub samples: usize,
+	/// Number of discarded samples at the beginning of every chain
+	pub burn_in: usize,
+	/// Iteration after which the proposal covariance is adapted
+	pub adaptation_start: usize,
+	/// Seed of the random number generator
+	pub seed: u64
+}
+
+/// Posterior samples of all chains
+#[derive(Clone, Debug, PartialEq)]
+pub struct Posterior {
+	/// Names of the parameters
+	pub names: Vec<String>,
+	/// Samples (index order: chain, sample, parameter)
+	pub chains: Vec<Vec<Vec<f64>>>,
+	/// Log-posterior of every sample (index order: chain, sample)
+	pub log_posterior: Vec<Vec<f64>>,
+	/// Fraction of accepted proposals in every chain (after the burn-in)
+	pub acceptance_rates: Vec<f64>
+}
+
+impl MCMC {
+	/// Sampler with 4 chains of _samples_ samples each, a burn-in of the same length and adaptation after 200 iterations
+	pub fn new(samples: usize, seed: u64) -> MCMC {
+		MCMC { chains: 4, samples, burn_in: samples, adaptation_start: 200, seed }
+	}
+
+	/// Samples the posterior of _parameters_ given the _log\_likelihood_ of the parameter values (in the order of _parameters_).
+	pub fn sample(&self, parameters: &[Uncertain], log_likelihood: impl Fn(&[f64]) -> f64) -> Posterior {
+		let d = parameters.len();
+		let log_posterior = |x: &[f64]| -> f64 {
+			let log_prior: f64 = parameters.iter().zip(x).map(|(p, x)| p.prior.log_density(*x)).sum();
+			if !log_prior.is_finite() {
+				return f64::NEG_INFINITY;
+			}
+			let log_posterior = log_prior + log_likelihood(x);
+			if log_posterior.is_nan() { f64::NEG_INFINITY } else { log_posterior }
+		};
+		let scales: Vec<f64> = parameters.iter().map(|p| 0.1*p.prior.scale()).collect();
+		let optimal_scaling = 2.38*2.38/(d as f64);
+
+		let mut rng = Rng::new(self.seed);
+		let mut posterior = Posterior { names: parameters.iter().map(|p| p.name.clone()).collect(), chains: Vec::with_capacity(self.chains),
+										log_posterior: Vec::with_capacity(self.chains), acceptance_rates: Vec::with_capacity(self.chains) };
+
+		for chain in 0..self.chains {
+			let mut chain_rng = rng.split();
+
+			// Start close to the initial values (the first chain exactly there)
+			let mut x: Vec<f64> = parameters.iter().zip(scales.iter()).map(|(p, s)| if chain == 0 { p.initial } else { p.initial + s*chain_rng.normal() }).collect();
+			let mut current = log_posterior(&x);
+			if !current.is_finite() {
+				x = parameters.iter().map(|p| p.initial).collect();
+				current = log_posterior(&x);
+			}
+
+			// Running mean and covariance of the chain (Welford)
+			let mut mean = x.clone();
+			let mut covariance = vec![vec![0.0; d]; d];
+			let mut cholesky: Vec<Vec<f64>> = (0..d).map(|i| (0..d).map(|j| if i == j { scales[i] } else { 0.0 }).collect()).collect();
+
+			let mut samples = Vec::with_capacity(self.samples);
+			let mut log_posteriors = Vec::with_capacity(self.samples);
+			let mut accepted = 0;
+			for iteration in 1..=self.burn_in + self.samples {
+				// Gaussian proposal with covariance L L^T
+				let z: Vec<f64> = (0..d).map(|_| chain_rng.normal()).collect();
+				let proposal: Vec<f64> = (0..d).map(|i| x[i] + (0..=i).map(|j| cholesky[i][j]*z[j]).sum::<f64>()).collect();
+				let proposed = log_posterior(&proposal);
+				if proposed.is_finite() && chain_rng.uniform().ln() < proposed - current {
+					x = proposal;
+					current = proposed;
+					if iteration > self.burn_in {
+						accepted += 1;
+					}
+				}
+
+				// Adapt the proposal to the covariance of the chain
+				let n = iteration as f64 + 1.0;
+				let delta: Vec<f64> = (0..d).map(|i| x[i] - mean[i]).collect();
+				for i in 0..d {
+					mean[i] += delta[i]/n;
+				}
+				for i in 0..d {
+					for j in 0..d {
+						covariance[i][j] += ((x[i] - mean[i])*delta[j] - covariance[i][j])/n;
+					}
+				}
+				if iteration >= self.adaptation_start {
+					let proposal_covariance: Vec<Vec<f64>> = (0..d).map(|i| (0..d).map(|j| {
+						optimal_scaling*(covariance[i][j] + if i == j { 1e-6*scales[i]*scales[i] } else { 0.0 })
+					}).collect()).collect();
+					if let Some(factor) = cholesky_factor(&proposal_covariance) {
+						cholesky = factor;
+					}
+				}
+
+				if iteration > self.burn_in {
+					samples.push(x.clone());
+					log_posteriors.push(current);
+				}
+			}
+			posterior.chains.push(samples);
+			posterior.log_posterior.push(log_posteriors);
+			posterior.acceptance_rates.push(accepted as f64/(self.samples.max(1) as f64));
+		}
+		posterior
+	}
+
+	/// Samples the posterior of _parameters_ given the observed _data_, with the likelihood of _estimation_ and the expected reports
+	/// of _simulate_ for the parameter values (as in _Estimation::estimate_).
+	pub fn sample_data(&self, estimation: &Estimation, data: &SurveillanceData, parameters: &[Uncertain], simulate: impl Fn(&[f64]) -> SurveillanceData) -> Posterior {
+		self.sample(parameters, |x| estimation.log_likelihood(data, &simulate(x)))
+	}
+}
+
+impl Posterior {
+	/// All samples of all chains
+	pub fn samples(&self) -> Vec<Vec<f64>> {
+		self.chains.iter().flat_map(|chain| chain.iter().cloned()).collect()
+	}
+
+	/// Index of the parameter _name_
+	pub fn index(&self, name: &str) -> Option<usize> {
+		self.names.iter().position(|n| n == name)
+	}
+
+	/// Posterior mean of parameter i
+	pub fn mean(&self, i: usize) -> f64 {
+		let samples = self.samples();
+		samples.iter().map(|x| x[i]).sum::<f64>()/(samples.len() as f64)
+	}
+
+	/// Posterior quantile _q_ (between 0 and 1) of parameter i
+	pub fn quantile(&self, i: usize, q: f64) -> f64 {
+		let mut values: Vec<f64> = self.samples().iter().map(|x| x[i]).collect();
+		quantile(&mut values, q)
+	}
+
+	/// Potential scale reduction factor $\hat R$ (Gelman and Rubin) of every parameter. Values close to 1 (e.g. below 1.01) indicate converged chains.
+	pub fn r_hat(&self) -> Vec<f64> {
+		let m = self.chains.len() as f64;
+		let n = self.chains.first().map_or(0, |chain| chain.len()) as f64;
+		(0..self.names.len()).map(|i| {
+			let means: Vec<f64> = self.chains.iter().map(|chain| chain.iter().map(|x| x[i]).sum::<f64>()/n).collect();
+			let variances: Vec<f64> = self.chains.iter().zip(means.iter()).map(|(chain, mean)| chain.iter().map(|x| (x[i] - mean).powi(2)).sum::<f64>()/(n - 1.0)).collect();
+			let grand_mean = means.iter().sum::<f64>()/m;
+			let between = n/(m - 1.0)*means.iter().map(|mean| (mean - grand_mean).powi(2)).sum::<f64>();
+			let within = variances.iter().sum::<f64>()/m;
+			(((n - 1.0)/n*within + between/n)/within).sqrt()
+		}).collect()
+	}
+
+	/// Effective sample size of every parameter (summed over the chains), estimated from the autocorrelations with Geyer's initial positive sequence
+	pub fn effective_sample_size(&self) -> Vec<f64> {
+		(0..self.names.len()).map(|i| {
+			self.chains.iter().map(|chain| {
+				let n = chain.len();
+				let mean = chain.iter().map(|x| x[i]).sum::<f64>()/(n as f64);
+				let autocovariance = |lag: usize| (0..n-lag).map(|k| (chain[k][i] - mean)*(chain[k+lag][i] - mean)).sum::<f64>()/(n as f64);
+				let variance = autocovariance(0);
+				if n < 4 || variance <= 0.0 {
+					return n as f64;
+				}
+				// Sum pairs of autocorrelations as long as they are positive
+				let mut tau = -1.0;
+				let mut lag = 0;
+				while lag + 1 < n {
+					let pair = (autocovariance(lag) + autocovariance(lag + 1))/variance;
+					if pair <= 0.0 {
+						break;
+					}
+					tau += 2.0*pair;
+					lag += 2;
+				}
+				n as f64/tau.max(1.0/(n as f64))
+			}).sum()
+		}).collect()
+	}
+
+	/// Runs _run_ for _draws_ randomly drawn posterior samples (e.g. a scenario with the sampled inputs) in parallel with _sweep_ and returns the results.
+	/// Fails if the posterior has no samples.
+	pub fn predictive<T: Send>(&self, draws: usize, seed: u64, sweep: &Sweep, run: impl Fn(&[f64]) -> T + Sync) -> Result<Vec<T>> {
+		let samples = self.samples();
+		if samples.is_empty() {
+			return Err(Error::invalid("posterior samples", 0.0, "at least one sample"));
+		}
+		let mut rng = Rng::new(seed);
+		let drawn: Vec<&Vec<f64>> = (0..draws).map(|_| &samples[((rng.uniform()*(samples.len() as f64)) as usize).min(samples.len() - 1)]).collect();
+		Ok(sweep.run(&drawn, |sample| run(sample)))
+	}
+
+	/// Writes the samples to the file "./data/foldername/posterior.data": one line per sample with the chain, the log-posterior and the parameters.
+	pub fn write_to_disk(&self, foldername: &str) -> Result<()> {
+		self.write_to(&mut Directory::data(foldername))
+	}
+
+	/// Same as _write\_to\_disk_, but writes "posterior.data" to the _sink_ (see the _sink_ submodule)
+	pub fn write_to(&self, sink: &mut dyn Sink) -> Result<()> {
+		let precision = 6;
+		sink.write_file("posterior.data", &mut |file| {
+			writeln!(file, "chain \t log_posterior \t {}", self.names.join(" \t "))?;
+			for (chain, (samples, log_posteriors)) in self.chains.iter().zip(self.log_posterior.iter()).enumerate() {
+				for (x, log_posterior) in samples.iter().zip(log_posteriors.iter()) {
+					let values: Vec<String> = x.iter().map(|x| format!("{:.1$}", x, precision)).collect();
+					writeln!(file, "{} \t {:.3$} \t {}", chain, log_posterior, values.join(" \t "), precision)?;
+				}
+			}
+			Ok(())
+		})
+	}
+
+	/// Reads samples written by _write\_to\_disk_. The acceptance rates are not stored and set to NaN. Files without samples are rejected.
+	pub fn read_from_disk(filename: &str) -> Result<Posterior> {
+		let invalid = |message: String| Error::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, message));
+		let content = read_file(filename)?;
+		let mut lines = content.lines().filter(|line| !line.trim().is_empty());
+		let header: Vec<&str> = lines.next().ok_or_else(|| invalid(format!("{} is empty", filename)))?.split_whitespace().collect();
+		if header.len() < 2 || header[0] != "chain" || header[1] != "log_posterior" {
+			return Err(invalid(format!("{} is not a posterior file", filename)));
+		}
+		let mut posterior = Posterior { names: header[2..].iter().map(|name| name.to_string()).collect(), chains: Vec::new(), log_posterior: Vec::new(), acceptance_rates: Vec::new() };
+		for (number, line) in lines.enumerate() {
+			let values = line.split_whitespace().map(|x| x.parse::<f64>()).collect::<Result<Vec<f64>, _>>()
+				.map_err(|e| invalid(format!("{} line {}: {}", filename, number+2, e)))?;
+			if values.len() != header.len() {
+				return Err(invalid(format!("{} line {}: {} values for {} columns", filename, number+2, values.len(), header.len())));
+			}
+			let chain = values[0] as usize;
+			while posterior.chains.len() <= chain {
+				posterior.chains.push(Vec::new());
+				posterior.log_posterior.push(Vec::new());
+				posterior.acceptance_rates.push(f64::NAN);
+			}
+			posterior.chains[chain].push(values[2..].to_vec());
+			posterior.log_posterior[chain].push(values[1]);
+		}
+		if posterior.chains.iter().all(|samples| samples.is_empty()) {
+			return Err(invalid(format!("{} contains no samples", filename)));
+		}
+		Ok(posterior)
+	}
+}
+
+/// Cholesky factor L (lower triangular, $A = L L^T$) of a symmetric positive definite matrix. Returns None if it is not positive definite.
+fn cholesky_factor(matrix: &[Vec<f64>]) -> Option<Vec<Vec<f64>>> {
+	let n = matrix.len();
+	let mut factor = vec![vec![0.0; n]; n];
+	for i in 0..n {
+		for j in 0..=i {
+			let sum: f64 = (0..j).map(|k| factor[i][k]*factor[j][k]).sum();
+			if i == j {
+				let diagonal = matrix[i][i] - sum;
+				if diagonal <= 0.0 || diagonal.is_nan() {
+					return None;
+				}
+				factor[i][i] = diagonal.sqrt();
+			} else {
+				factor[i][j] = (matrix[i][j] - sum)/factor[j][j];
+			}
+		}
+	}
+	Some(factor)
+}
@@ -1,8 +1,13 @@
 //! Everything related to the model. Including collections of global and age-group specific parameters and the differential equations.
 
 use crate::vaccination_model::age_group_state_vector::AgeGroupStateVector;
+use crate::vaccination_model::delay_system::DelaySystem;
+use crate::vaccination_model::error::{Error, Result};
+use crate::vaccination_model::gradient::Scalar;
+use crate::vaccination_model::output::{Table, TableKind};
 
 /// A collection of all the parameters for a given age group
+#[derive(Clone)]
 pub struct AgeGroup {
 	/// Name of the age group (will be used as the name of the output file when saving the results)
 	pub name: String,
@@ -53,7 +58,35 @@ impl AgeGroup {
 	}
 }
 
+/// The parameters entering the dif. eqs. in the scalar type T. The model provides its own parameters as f64, _DualModel_ (see _gradient_) provides
+/// them as dual numbers, so that both share one implementation of the dif. eqs.
+pub(crate) trait Parameters<T> {
+	/// $\eta_0$
+	fn eta0(&self) -> T;
+	/// $\sigma_\nu$
+	fn sigma(&self, vacc: usize) -> T;
+	/// Contact matrix entry $c_{ij}$
+	fn contact(&self, i: usize, j: usize) -> T;
+	/// $\phi_i$
+	fn influx(&self, i: usize) -> T;
+	/// $\rho_i$
+	fn rho(&self, i: usize) -> T;
+	/// $\bar\gamma_i$
+	fn gamma_bar(&self, i: usize) -> T;
+	/// $\gamma_i^\nu$
+	fn gamma_I(&self, i: usize, vacc: usize) -> T;
+	/// $\gamma_i^{ICU,\nu}$
+	fn gamma_ICU(&self, i: usize, vacc: usize) -> T;
+	/// $\alpha_i^\nu$
+	fn alpha(&self, i: usize, vacc: usize) -> T;
+	/// $\delta_i^\nu$
+	fn delta_I(&self, i: usize, vacc: usize) -> T;
+	/// $\delta_i^{ICU,\nu}$
+	fn delta_ICU(&self, i: usize, vacc: usize) -> T;
+}
+
 /// A collection of global parameters (especially the vaccination parameters) and all the age groups. Includes the dif. eqs.
+#[derive(Clone)]
 pub struct Model {
 	/// A vector of all the age groups
 	pub age_groups: Vec<AgeGroup>,
@@ -98,11 +131,16 @@ impl Model {
 	
 	/// Sums up all I compartments of all age groups and vaccinations status weighted by the removal rate from the I compartment, i.e. returns $\sum_{i,\nu}\bar\gamma_i I^\nu_i$.
 	/// Used for the contagion terms in the dif. eqs.
-	pub fn I_eff(&self, group: usize, state: &Vec<AgeGroupStateVector>) -> f64{
-		let mut ipm: f64 = 0.0;
+	pub fn I_eff(&self, group: usize, state: &[AgeGroupStateVector]) -> f64{
+		self.I_eff_with(self, group, state)
+	}
+
+	/// _I\_eff_ with the parameters _p_ in the scalar type T
+	pub(crate) fn I_eff_with<T: Scalar>(&self, p: &impl Parameters<T>, group: usize, state: &[AgeGroupStateVector<T>]) -> T {
+		let mut ipm = T::from(0.0);
 		for j in 0..self.age_groups.len() {
 			for vacc in 0..3 {
-				ipm += self.contacts[group][j]*self.age_groups[j].gamma_bar()*self.sigma[vacc]*state[j].I[vacc]/self.age_groups[j].M;
+				ipm += p.contact(group, j)*p.gamma_bar(j)*p.sigma(vacc)*state[j].I[vacc]/T::from(self.age_groups[j].M);
 			}
 		}
 		ipm
@@ -111,28 +149,68 @@ impl Model {
 	/// Implements the dif. eqs. and returns a vector of all slopes d/dt. Needs the current time $t$, the current H-value h (see Solver.H for an explanation) the current $R_t$ value, full system state as well as the delayed $R_{t-\tau}$
 	/// and the delayed system state at time $t-\tau$.
 	/// 
-	/// Returns: (vector of slopes for all age group compartments, slope for H)
-	pub fn slopes(&self, t: f64, R: f64, state: &Vec<AgeGroupStateVector>, delayed_R: f64, delayed_state: &Vec<AgeGroupStateVector>) -> Vec<AgeGroupStateVector>{
+	/// Returns: vector of slopes for all age group compartments (including the slope for H). Fails if the vaccination rates are not prepared for $t$.
+	pub fn slopes(&self, t: f64, R: f64, state: &[AgeGroupStateVector], delayed_R: f64, delayed_state: &[AgeGroupStateVector]) -> Result<Vec<AgeGroupStateVector>>{
+		self.check_time(t)?;
+		let mut full_slopes = vec![AgeGroupStateVector::default(); self.age_groups.len()];		// initiate result vector
+		self.slopes_with(self, t, R, state, delayed_state, |age_group_index| delayed_R*self.I_eff(age_group_index, delayed_state), &mut full_slopes);
+		Ok(full_slopes)
+	}
+
+	/// Checks that the vaccination rates are prepared for the time $t$ (see _prepare\_vaccination\_rates_).
+	pub(crate) fn check_time(&self, t: f64) -> Result<()> {
+		let end = self.horizon();
+		if t < end {
+			Ok(())
+		} else {
+			Err(Error::VaccinationsExhausted { t, end })
+		}
+	}
+
+	/// Writes $R\sum_{j,\nu}c_{ij}\bar\gamma_j\sigma_\nu I^\nu_j/M_j$ (the "infections per member") of every age group i into _ipm_.
+	///
+	/// The delayed value only changes once per time step, so it is what the model needs from the delayed state besides the fractions S/(S+R)
+	/// (see the implementation of _DelaySystem_).
+	pub fn ipm_into(&self, R: f64, state: &[AgeGroupStateVector], ipm: &mut [f64]) {
+		for (age_group_index, ipm) in ipm.iter_mut().enumerate() {
+			*ipm = R*self.I_eff(age_group_index, state);
+		}
+	}
+
+	/// Implementation of the dif. eqs. shared by _slopes_ and the _DelaySystem_ implementations (of the model and of _DualModel_), with the
+	/// parameters _p_ in the scalar type T
+	#[inline(always)]
+	#[allow(clippy::too_many_arguments)]
+	pub(crate) fn slopes_with<T: Scalar>(&self, p: &impl Parameters<T>, t: f64, R: f64, state: &[AgeGroupStateVector<T>], delayed_state: &[AgeGroupStateVector<T>], delayed_ipm: impl Fn(usize) -> T, full_slopes: &mut [AgeGroupStateVector<T>]) {
 		let week = (t/7.0).floor() as usize;							// current week at t
 		let delayed_week = ((t-self.tau)/7.0).floor() as usize;			// week at t-tau
-
-		let mut full_slopes = Vec::<AgeGroupStateVector>::with_capacity(self.age_groups.len());		// initiate result vector
+		let one = T::from(1.0);
+		let eta0 = p.eta0();
 
 		for age_group_index in 0..self.age_groups.len() {
-			let i = &self.age_groups[age_group_index];				// age group i (for easy access of the age-specific parameters)
+			// parameters of age group i
+			let M = T::from(self.age_groups[age_group_index].M);
+			let influx = p.influx(age_group_index);
+			let rho = p.rho(age_group_index);
+			let gamma_bar = p.gamma_bar(age_group_index);
+			let gamma_I = [0, 1, 2].map(|vacc| p.gamma_I(age_group_index, vacc));
+			let gamma_ICU = [0, 1, 2].map(|vacc| p.gamma_ICU(age_group_index, vacc));
+			let alpha = [0, 1, 2].map(|vacc| p.alpha(age_group_index, vacc));
+			let delta_I = [0, 1, 2].map(|vacc| p.delta_I(age_group_index, vacc));
+			let delta_ICU = [0, 1, 2].map(|vacc| p.delta_ICU(age_group_index, vacc));
 
 			// Retrieve delayed value for ipm ("infections per member")
-			let ipm = R*self.I_eff(age_group_index, state);
-			let delayed_ipm = delayed_R*self.I_eff(age_group_index, &delayed_state);
+			let ipm = T::from(R)*self.I_eff_with(p, age_group_index, state);
+			let delayed_ipm = delayed_ipm(age_group_index);
 
 			let i_state = state[age_group_index];					// current state of age group i (at t)
 			let i_state_delayed = delayed_state[age_group_index];	// delayed state of age group i (at t-tau)
 			
 			// get daily vaccination rates
-			let f1 = self.vaccinations_per_week_dose1[week][age_group_index]/7.0;
-			let f2 = self.vaccinations_per_week_dose2[week][age_group_index]/7.0;
-			let f1_delayed = self.vaccinations_per_week_dose1[delayed_week][age_group_index]/7.0;
-			let f2_delayed = self.vaccinations_per_week_dose2[delayed_week][age_group_index]/7.0;
+			let f1 = T::from(self.vaccinations_per_week_dose1[week][age_group_index]/7.0);
+			let f2 = T::from(self.vaccinations_per_week_dose2[week][age_group_index]/7.0);
+			let f1_delayed = T::from(self.vaccinations_per_week_dose1[delayed_week][age_group_index]/7.0);
+			let f2_delayed = T::from(self.vaccinations_per_week_dose2[delayed_week][age_group_index]/7.0);
 
 			// Fractions (S/(S+R)) where to deliver the vaccinations (in S or in R compartments)
 			let frac0 = (i_state.S[0]/(i_state.S[0] + i_state.R[0])).min(1.0).max(0.0);
@@ -141,41 +219,37 @@ impl Model {
 			let frac1_delayed = (i_state_delayed.S[1]/(i_state_delayed.S[1] + i_state_delayed.R[1])).min(1.0).max(0.0);
 
 			// p_i(t)
-			let pi = 1.0 - (-i_state.h - i.influx*self.tau/i.M).exp();
+			let pi = one - (-i_state.h - influx*T::from(self.tau)/M).exp();
 
 			// Slopes for this age group
-			let slopes = AgeGroupStateVector { 
-				S: [- i_state.S[0]*ipm - i_state.S[0]/i.M*i.influx - f1*frac0,
-					- i_state.S[1]*ipm - i_state.S[1]/i.M*i.influx - f2*frac1 + (1.-self.eta0)*f1_delayed*frac0_delayed*(1.-pi),
-					- i_state.S[2]*ipm - i_state.S[2]/i.M*i.influx 			+ (1.-self.eta0)*f2_delayed*frac1_delayed*(1.-pi)],
+			full_slopes[age_group_index] = AgeGroupStateVector { 
+				S: [- i_state.S[0]*ipm - i_state.S[0]/M*influx - f1*frac0,
+					- i_state.S[1]*ipm - i_state.S[1]/M*influx - f2*frac1 + (one-eta0)*f1_delayed*frac0_delayed*(one-pi),
+					- i_state.S[2]*ipm - i_state.S[2]/M*influx 			+ (one-eta0)*f2_delayed*frac1_delayed*(one-pi)],
 				
-				V: [- i_state.V[0]*ipm - i_state.V[0]/i.M*i.influx + f1*frac0 - f1_delayed*frac0_delayed*(1.-pi),
-					- i_state.V[1]*ipm - i_state.V[1]/i.M*i.influx + f2*frac1 - f2_delayed*frac1_delayed*(1.-pi)],
+				V: [- i_state.V[0]*ipm - i_state.V[0]/M*influx + f1*frac0 - f1_delayed*frac0_delayed*(one-pi),
+					- i_state.V[1]*ipm - i_state.V[1]/M*influx + f2*frac1 - f2_delayed*frac1_delayed*(one-pi)],
 
-				E: [(i_state.S[0] + i_state.V[0])*ipm - i.rho*i_state.E[0],
-					(i_state.S[1] + i_state.V[1])*ipm - i.rho*i_state.E[1],
-					i_state.S[2]*ipm - i.rho*i_state.E[2]],
+				E: [(i_state.S[0] + i_state.V[0])*ipm - rho*i_state.E[0],
+					(i_state.S[1] + i_state.V[1])*ipm - rho*i_state.E[1],
+					i_state.S[2]*ipm - rho*i_state.E[2]],
 
-				I: [i.rho*i_state.E[0] - i.gamma_bar()*i_state.I[0] + (i_state.S[0] + i_state.V[0])/i.M*i.influx,
-					i.rho*i_state.E[1] - i.gamma_bar()*i_state.I[1] + (i_state.S[1] + i_state.V[1])/i.M*i.influx,
-					i.rho*i_state.E[2] - i.gamma_bar()*i_state.I[2] + i_state.S[2]/i.M*i.influx],
+				I: [rho*i_state.E[0] - gamma_bar*i_state.I[0] + (i_state.S[0] + i_state.V[0])/M*influx,
+					rho*i_state.E[1] - gamma_bar*i_state.I[1] + (i_state.S[1] + i_state.V[1])/M*influx,
+					rho*i_state.E[2] - gamma_bar*i_state.I[2] + i_state.S[2]/M*influx],
 				
-				ICU: [i.alpha[0]*i_state.I[0] - (i.delta_ICU[0] + i.gamma_ICU[0])*i_state.ICU[0],
-					  i.alpha[1]*i_state.I[1] - (i.delta_ICU[1] + i.gamma_ICU[1])*i_state.ICU[1],
-					  i.alpha[2]*i_state.I[2] - (i.delta_ICU[2] + i.gamma_ICU[2])*i_state.ICU[2]],
+				ICU: [alpha[0]*i_state.I[0] - (delta_ICU[0] + gamma_ICU[0])*i_state.ICU[0],
+					  alpha[1]*i_state.I[1] - (delta_ICU[1] + gamma_ICU[1])*i_state.ICU[1],
+					  alpha[2]*i_state.I[2] - (delta_ICU[2] + gamma_ICU[2])*i_state.ICU[2]],
 
-				D: (i.delta_I[0]*i_state.I[0] + i.delta_ICU[0]*i_state.ICU[0] + i.delta_I[1]*i_state.I[1] + i.delta_ICU[1]*i_state.ICU[1] + i.delta_I[2]*i_state.I[2] + i.delta_ICU[2]*i_state.ICU[2]),
+				D: (delta_I[0]*i_state.I[0] + delta_ICU[0]*i_state.ICU[0] + delta_I[1]*i_state.I[1] + delta_ICU[1]*i_state.ICU[1] + delta_I[2]*i_state.I[2] + delta_ICU[2]*i_state.ICU[2]),
 
-				R: [i.gamma_I[0]*i_state.I[0] + i.gamma_ICU[0]*i_state.ICU[0] - f1*(1.-frac0),
-					i.gamma_I[1]*i_state.I[1] + i.gamma_ICU[1]*i_state.ICU[1] - f2*(1.-frac1) + f1*(1.-frac0) + self.eta0*f1_delayed*frac0_delayed*(1.-pi),
-					i.gamma_I[2]*i_state.I[2] + i.gamma_ICU[2]*i_state.ICU[2] 				  + f2*(1.-frac1) + self.eta0*f2_delayed*frac1_delayed*(1.-pi)],
+				R: [gamma_I[0]*i_state.I[0] + gamma_ICU[0]*i_state.ICU[0] - f1*(one-frac0),
+					gamma_I[1]*i_state.I[1] + gamma_ICU[1]*i_state.ICU[1] - f2*(one-frac1) + f1*(one-frac0) + eta0*f1_delayed*frac0_delayed*(one-pi),
+					gamma_I[2]*i_state.I[2] + gamma_ICU[2]*i_state.ICU[2] 				  + f2*(one-frac1) + eta0*f2_delayed*frac1_delayed*(one-pi)],
 				h: ipm-delayed_ipm
 			};
-			full_slopes.push(slopes);
 		}
-
-		// Return (all compartment slopes, dH/dt)
-		full_slopes
 	}
 
 	/// Add an age group to the model. Adds also this age groups population $M_i$ to the total $M$.
@@ -208,9 +282,8 @@ impl Model {
 		rate
 	}
 
-	/// Find the linear interpolation parameter s parametrising the individual uptakes for the given total_vaccination_fraction
-	fn s_for_given_total_uptake(&self, total_uptake: f64) -> f64 {
-		// Find the minimum and maximum total vaccine uptake
+	/// Find the minimum and maximum total vaccine uptake (as fractions of the eligible population)
+	fn total_uptake_range(&self) -> (f64, f64) {
 		let mut min_total_uptake: f64 = 0.0;
 		let mut max_total_uptake: f64 = 0.0;
 		let mut total_eligible: f64 = 0.0;
@@ -219,27 +292,35 @@ impl Model {
 			min_total_uptake += ag.eligible_fraction*ag.min_uptake*ag.M;
 			max_total_uptake += ag.eligible_fraction*ag.max_uptake*ag.M;
 		}
-		min_total_uptake /= total_eligible;
-		max_total_uptake /= total_eligible;
+		(min_total_uptake/total_eligible, max_total_uptake/total_eligible)
+	}
+
+	/// Find the linear interpolation parameter s parametrising the individual uptakes for the given total_vaccination_fraction
+	fn s_for_given_total_uptake(&self, total_uptake: f64) -> f64 {
+		let (min_total_uptake, max_total_uptake) = self.total_uptake_range();
 
 		// the given total_vaccination_fraction is the linear interpolation between the two extrema
 		(total_uptake-min_total_uptake)/(max_total_uptake-min_total_uptake)
 	}
 
 	/// Prepares the weekly vaccination rates per age group (to get the daily rates $f_i^1(t)$ and $f_i^2(t)$ devide by 7). Prepares them for _weeks_ weeks in advance and for a given total uptake.
-	pub fn prepare_vaccination_rates(&mut self, weeks: usize, total_uptake: f64) {
+	///
+	/// Fails if the total uptake cannot be reached by interpolating between the minimal and maximal uptakes of the age groups.
+	pub fn prepare_vaccination_rates(&mut self, weeks: usize, total_uptake: f64) -> Result<()> {
 		let N_age_groups = self.age_groups.len();
 		self.vaccinations_per_week_dose1 = vec![vec![0.0f64; N_age_groups]; weeks];
 		self.vaccinations_per_week_dose2 = vec![vec![0.0f64; N_age_groups]; weeks];
 
-		if total_uptake == 0.0 {return;}
+		if total_uptake == 0.0 {return Ok(());}
 
 		// Note: The below function is neglecting the case where more second doses would get vaccinated then there are available in a given week. This does not occur in practice for our scenarios.
 		let second_dose_fraction = 1.0;	// percentage of people that got the first dose, but for whatever reasons never get the second dose
 
 		let s: f64 = self.s_for_given_total_uptake(total_uptake);
-		assert!(s>=0., "Total uptake too low! {}", s);
-		assert!(s<=1., "Total uptake too high! {}", s);
+		if !(0.0..=1.0).contains(&s) {
+			let (min, max) = self.total_uptake_range();
+			return Err(Error::UnreachableUptake { total_uptake, min, max });
+		}
 		// Find the number of phases in the vaccination program (highest priority phase to be found in all age groups + 1)
 		let mut N_phases = 0usize;	// number of phases in the vacc. program
 		for ag in &self.age_groups {
@@ -344,10 +425,20 @@ impl Model {
 				}
 			}
 		}
+		Ok(())
 	}
 
 	/// Returns how many first and second dose vaccines are distributed between t0 and t1 for a given age group. Used to retrieve the initial conditions.
-	pub fn vaccinated_between(&self, t0:f64, t1: f64, age_group: usize) -> (f64, f64) {
+	/// Fails if the vaccination rates are not prepared until t1.
+	pub fn vaccinated_between(&self, t0:f64, t1: f64, age_group: usize) -> Result<(f64, f64)> {
+		if t1 > self.horizon() {
+			return Err(Error::VaccinationsExhausted { t: t1, end: self.horizon() });
+		}
+		Ok(self.vaccinated_in(t0, t1, age_group))
+	}
+
+	/// _vaccinated\_between_ without checking the time span
+	fn vaccinated_in(&self, t0:f64, t1: f64, age_group: usize) -> (f64, f64) {
 		let week0 = (t0/7.0).ceil()  as usize;
 		let week1 = (t1/7.0).floor() as usize;
 		let mut vaccinated = 0.0f64;	// first doses given
@@ -375,19 +466,84 @@ impl Model {
 	}
 
 	/// Calculate the total daily new infections for a given system state (not convoluted by the empirical delay yet)
-	pub fn N(&self, state: &Vec<AgeGroupStateVector>) -> f64 {
-		let mut N = 0.0f64;
+	pub fn N(&self, state: &[AgeGroupStateVector]) -> f64 {
+		self.N_with(self, state)
+	}
+
+	/// Calculate the daily new infections in age group i for a given system state (not convoluted by the empirical delay yet)
+	pub fn N_age_group(&self, state: &[AgeGroupStateVector], i: usize) -> f64 {
+		self.N_age_group_with(self, state, i)
+	}
+
+	/// Calculate the daily ICU admissions for a given system state, i.e. $\sum_{i,\nu}\alpha^\nu_i I^\nu_i$
+	pub fn ICU_admissions(&self, state: &[AgeGroupStateVector]) -> f64 {
+		self.ICU_admissions_with(self, state)
+	}
+
+	/// Calculate the daily deaths for a given system state, i.e. $\sum_{i,\nu}(\delta^\nu_i I^\nu_i + \delta^{ICU,\nu}_i ICU^\nu_i)$
+	pub fn deaths(&self, state: &[AgeGroupStateVector]) -> f64 {
+		self.deaths_with(self, state)
+	}
+
+	/// Calculate the daily deaths in age group i for a given system state, i.e. $\sum_\nu(\delta^\nu_i I^\nu_i + \delta^{ICU,\nu}_i ICU^\nu_i)$
+	pub fn deaths_age_group(&self, state: &[AgeGroupStateVector], i: usize) -> f64 {
+		self.deaths_age_group_with(self, state, i)
+	}
+
+	/// Calculate the ICU occupancy for a given system state (Adds all age groups and vaccination status)
+	pub fn ICU_occupancy(&self, state: &[AgeGroupStateVector]) -> f64 {
+		self.ICU_occupancy_with(state)
+	}
+
+	/// _N_ with the parameters _p_ in the scalar type T
+	pub(crate) fn N_with<T: Scalar>(&self, p: &impl Parameters<T>, state: &[AgeGroupStateVector<T>]) -> T {
+		let mut N = T::from(0.0);
 		for i in 0..self.age_groups.len() {
-			let ag = &self.age_groups[i];
-			N += ag.rho*(state[i].E[0] + state[i].E[1] + state[i].E[2]) + ag.influx/ag.M*(state[i].S[0] + state[i].S[1] + state[i].S[2] + state[i].E[0] + state[i].E[1]);
+			N += self.N_age_group_with(p, state, i);
 		}
 		N
 	}
 
-	/// Calculate the ICU occupancy for a given system state (Adds all age groups and vaccination status)
-	pub fn ICU_occupancy(&self, state: &Vec<AgeGroupStateVector>) -> f64 {
+	/// _N\_age\_group_ with the parameters _p_ in the scalar type T
+	pub(crate) fn N_age_group_with<T: Scalar>(&self, p: &impl Parameters<T>, state: &[AgeGroupStateVector<T>], i: usize) -> T {
+		p.rho(i)*(state[i].E[0] + state[i].E[1] + state[i].E[2]) + p.influx(i)/T::from(self.age_groups[i].M)*(state[i].S[0] + state[i].S[1] + state[i].S[2] + state[i].E[0] + state[i].E[1])
+	}
+
+	/// _ICU\_admissions_ with the parameters _p_ in the scalar type T
+	pub(crate) fn ICU_admissions_with<T: Scalar>(&self, p: &impl Parameters<T>, state: &[AgeGroupStateVector<T>]) -> T {
+		let mut admissions = T::from(0.0);
+		for (i, i_state) in state.iter().enumerate().take(self.age_groups.len()) {
+			for vacc in 0..3 {
+				admissions += p.alpha(i, vacc)*i_state.I[vacc];
+			}
+		}
+		admissions
+	}
+
+	/// _deaths_ with the parameters _p_ in the scalar type T
+	pub(crate) fn deaths_with<T: Scalar>(&self, p: &impl Parameters<T>, state: &[AgeGroupStateVector<T>]) -> T {
+		let mut deaths = T::from(0.0);
+		for (i, i_state) in state.iter().enumerate().take(self.age_groups.len()) {
+			for vacc in 0..3 {
+				deaths += p.delta_I(i, vacc)*i_state.I[vacc] + p.delta_ICU(i, vacc)*i_state.ICU[vacc];
+			}
+		}
+		deaths
+	}
+
+	/// _deaths\_age\_group_ with the parameters _p_ in the scalar type T
+	pub(crate) fn deaths_age_group_with<T: Scalar>(&self, p: &impl Parameters<T>, state: &[AgeGroupStateVector<T>], i: usize) -> T {
+		let mut deaths = T::from(0.0);
+		for vacc in 0..3 {
+			deaths += p.delta_I(i, vacc)*state[i].I[vacc] + p.delta_ICU(i, vacc)*state[i].ICU[vacc];
+		}
+		deaths
+	}
+
+	/// _ICU\_occupancy_ in the scalar type T
+	pub(crate) fn ICU_occupancy_with<T: Scalar>(&self, state: &[AgeGroupStateVector<T>]) -> T {
 		// Calculate total ICU occupancy
-		let mut icu = 0.0f64;
+		let mut icu = T::from(0.0);
 		for i in 0..self.age_groups.len() {
 			icu += state[i].ICU[0] + state[i].ICU[1] + state[i].ICU[2];
 		}
@@ -403,9 +559,12 @@ impl Model {
 	/// $\delta_i^{\nu}   = (\sqrt{1-\kappa_0})^{\nu}\\delta_i $, $\alpha_i^{\nu} = (\sqrt{1-\kappa_0})^{\nu}\alpha_i$ and $ \gamma_i^{\nu}+\delta_i^{\nu}+\alpha_i^{\nu} = \gamma_i$
     ///  
 	/// See the supplementary for more information.
-	pub fn initialize(&mut self) {
+	///
+	/// Fails if a parameter is invalid (see _validate_), the model is left unchanged then.
+	pub fn initialize(&mut self) -> Result<()> {
+		self.validate()?;
 
-		for mut ag in self.age_groups.iter_mut() {
+		for ag in self.age_groups.iter_mut() {
 			// ICU rates remain the same with vaccination
 			ag.influx *= ag.M/self.M;
 			ag.gamma_ICU[1] = ag.gamma_ICU[0];
@@ -421,6 +580,63 @@ impl Model {
 			ag.gamma_I[1] = ag.gamma_bar()-ag.alpha[1]-ag.delta_I[1];
 			ag.gamma_I[2] = ag.gamma_bar()-ag.alpha[2]-ag.delta_I[2];
 		}
+		Ok(())
+	}
+
+	/// Checks the parameters: positive population sizes, non-negative rates and delays, fractions (uptakes, efficacies, $\eta_0$, _random\_vacc_)
+	/// between 0 and 1, vaccination phases of at least -1 and a square contact matrix with one row per age group.
+	pub fn validate(&self) -> Result<()> {
+		let fraction = |name: &str, value: f64| if (0.0..=1.0).contains(&value) { Ok(()) } else { Err(Error::invalid(name, value, "a fraction between 0 and 1")) };
+		let non_negative = |name: &str, value: f64| if value >= 0.0 && value.is_finite() { Ok(()) } else { Err(Error::invalid(name, value, "a non-negative number")) };
+
+		if self.age_groups.is_empty() {
+			return Err(Error::invalid("age_groups", 0.0, "at least one age group"));
+		}
+		for ag in self.age_groups.iter() {
+			let name = |parameter: &str| format!("{}.{}", ag.name, parameter);
+			if !(ag.M > 0.0 && ag.M.is_finite()) {
+				return Err(Error::invalid(&name("M"), ag.M, "a positive population size"));
+			}
+			non_negative(&name("influx"), ag.influx)?;
+			non_negative(&name("rho"), ag.rho)?;
+			non_negative(&name("gamma0"), ag.gamma_I[0])?;
+			non_negative(&name("gamma^ICU0"), ag.gamma_ICU[0])?;
+			non_negative(&name("alpha0"), ag.alpha[0])?;
+			non_negative(&name("delta0"), ag.delta_I[0])?;
+			non_negative(&name("delta^ICU0"), ag.delta_ICU[0])?;
+			fraction(&name("eligible_fraction"), ag.eligible_fraction)?;
+			fraction(&name("min_uptake"), ag.min_uptake)?;
+			fraction(&name("max_uptake"), ag.max_uptake)?;
+			if ag.phase < -1 {
+				return Err(Error::invalid(&name("phase"), ag.phase as f64, "a vaccination phase of at least -1"));
+			}
+		}
+
+		fraction("eta0", self.eta0)?;
+		fraction("kappa0", self.kappa0)?;
+		fraction("random_vacc", self.random_vacc)?;
+		for (nu, sigma) in self.sigma.iter().enumerate() {
+			non_negative(&format!("sigma{}", nu), *sigma)?;
+		}
+		non_negative("tau", self.tau)?;
+		non_negative("N_TTI", self.N_TTI)?;
+		non_negative("N_test_eff", self.N_test_eff)?;
+		non_negative("N_test_ineff", self.N_test_ineff)?;
+		non_negative("N_no_test", self.N_no_test)?;
+
+		let N_age_groups = self.age_groups.len();
+		if self.contacts.len() != N_age_groups {
+			return Err(Error::DimensionMismatch { name: "contacts".to_string(), expected: N_age_groups, found: self.contacts.len() });
+		}
+		for (i, row) in self.contacts.iter().enumerate() {
+			if row.len() != N_age_groups {
+				return Err(Error::DimensionMismatch { name: format!("contacts[{}]", i), expected: N_age_groups, found: row.len() });
+			}
+			for (j, c) in row.iter().enumerate() {
+				non_negative(&format!("contacts[{}][{}]", i, j), *c)?;
+			}
+		}
+		Ok(())
 	}
 
 	/// Corrects the raw $R_t$ value used in the dif. eqs. by test-trace-and-isolate (TTI) measures, increasing the percieved number of contacts, depending on the current daily infections N.
@@ -470,4 +686,178 @@ impl Model {
 		}
 		return TTI_Rt;
 	}
-}
\ No newline at end of file
+}
+impl Parameters<f64> for Model {
+	#[inline(always)]
+	fn eta0(&self) -> f64 {
+		self.eta0
+	}
+	#[inline(always)]
+	fn sigma(&self, vacc: usize) -> f64 {
+		self.sigma[vacc]
+	}
+	#[inline(always)]
+	fn contact(&self, i: usize, j: usize) -> f64 {
+		self.contacts[i][j]
+	}
+	#[inline(always)]
+	fn influx(&self, i: usize) -> f64 {
+		self.age_groups[i].influx
+	}
+	#[inline(always)]
+	fn rho(&self, i: usize) -> f64 {
+		self.age_groups[i].rho
+	}
+	#[inline(always)]
+	fn gamma_bar(&self, i: usize) -> f64 {
+		self.age_groups[i].gamma_bar()
+	}
+	#[inline(always)]
+	fn gamma_I(&self, i: usize, vacc: usize) -> f64 {
+		self.age_groups[i].gamma_I[vacc]
+	}
+	#[inline(always)]
+	fn gamma_ICU(&self, i: usize, vacc: usize) -> f64 {
+		self.age_groups[i].gamma_ICU[vacc]
+	}
+	#[inline(always)]
+	fn alpha(&self, i: usize, vacc: usize) -> f64 {
+		self.age_groups[i].alpha[vacc]
+	}
+	#[inline(always)]
+	fn delta_I(&self, i: usize, vacc: usize) -> f64 {
+		self.age_groups[i].delta_I[vacc]
+	}
+	#[inline(always)]
+	fn delta_ICU(&self, i: usize, vacc: usize) -> f64 {
+		self.age_groups[i].delta_ICU[vacc]
+	}
+}
+
+/// The vaccination model as a system of delay dif. eqs. with one block per age group.
+impl DelaySystem for Model {
+	type State = AgeGroupStateVector;
+	/// Delayed infections per member of every age group (see _ipm\_into_)
+	type Delayed = Vec<f64>;
+
+	fn delay(&self) -> f64 {
+		self.tau
+	}
+
+	/// The end of the prepared vaccination rates
+	fn horizon(&self) -> f64 {
+		7.0*self.vaccinations_per_week_dose1.len() as f64
+	}
+
+	fn delayed_terms(&self, delayed_R: f64, delayed_state: &[AgeGroupStateVector], delayed: &mut Vec<f64>) {
+		delayed.resize(self.age_groups.len(), 0.0);
+		self.ipm_into(delayed_R, delayed_state, delayed);
+	}
+
+	fn slopes_into(&self, t: f64, R: f64, state: &[AgeGroupStateVector], delayed_state: &[AgeGroupStateVector], delayed: &Vec<f64>, slopes: &mut [AgeGroupStateVector]) {
+		self.slopes_with(self, t, R, state, delayed_state, |age_group_index| delayed[age_group_index], slopes);
+	}
+
+	/// Sets the helper variable h, assuming the system was in the initial state for a time $\tau$ before.
+	fn prepare_initials(&self, R: f64, initials: &mut [AgeGroupStateVector]) {
+		for age_group_index in 0..initials.len() {
+			initials[age_group_index].h = R*self.I_eff(age_group_index, initials)*self.tau;
+		}
+	}
+
+	fn N(&self, state: &[AgeGroupStateVector]) -> f64 {
+		Model::N(self, state)
+	}
+
+	fn ICU_occupancy(&self, state: &[AgeGroupStateVector]) -> f64 {
+		Model::ICU_occupancy(self, state)
+	}
+
+	fn N_block(&self, state: &[AgeGroupStateVector], block: usize) -> f64 {
+		Model::N_age_group(self, state, block)
+	}
+
+	fn ICU_admissions(&self, state: &[AgeGroupStateVector]) -> f64 {
+		Model::ICU_admissions(self, state)
+	}
+
+	fn deaths(&self, state: &[AgeGroupStateVector]) -> f64 {
+		Model::deaths(self, state)
+	}
+
+	fn deaths_block(&self, state: &[AgeGroupStateVector], block: usize) -> f64 {
+		Model::deaths_age_group(self, state, block)
+	}
+
+	fn raw_Rt_to_TTI_corrected(&self, raw_Rt: f64, N: f64) -> f64 {
+		Model::raw_Rt_to_TTI_corrected(self, raw_Rt, N)
+	}
+
+	fn raw_Rt_from_TTI_corrected(&self, TTI_Rt: f64, N: f64) -> f64 {
+		Model::raw_Rt_from_TTI_corrected(self, TTI_Rt, N)
+	}
+
+	fn block_names(&self) -> Vec<String> {
+		self.age_groups.iter().map(|ag| ag.name.clone()).collect()
+	}
+
+	fn state_columns(&self) -> Vec<String> {
+		["S0", "S1", "S2", "V1", "V2", "E0", "E1", "E2", "I0", "I1", "I2", "ICU0", "ICU1", "ICU2", "D", "R0", "R1", "R2", "h", "f1", "f2"].iter().map(|name| name.to_string()).collect()
+	}
+
+	fn state_description(&self) -> String {
+		"first line is initial values + initially vaccianted".to_string()
+	}
+
+	/// Appends the daily first and second dose vaccinations at time t to the state (for the initial row: all vaccinations before t instead).
+	fn state_values(&self, t: f64, block: usize, state: &AgeGroupStateVector, initial: bool) -> Vec<f64> {
+		let (vaccinated1, vaccinated2) = if initial {
+			self.vaccinated_in(0.0, t, block)
+		} else {
+			let week = (t/7.0).floor() as usize;
+			(self.vaccinations_per_week_dose1[week][block]/7., self.vaccinations_per_week_dose2[week][block]/7.)
+		};
+		let mut values = state.to_array().to_vec();
+		values.push(vaccinated1);
+		values.push(vaccinated2);
+		values
+	}
+
+	/// The global parameters ("model") and the parameters of the age groups ("age\_groups")
+	fn parameter_tables(&self) -> Vec<Table> {
+		let model = Table::new("model", TableKind::Parameters)
+			.float("M", vec![self.M])
+			.float("eta0", vec![self.eta0])
+			.float("tau", vec![self.tau])
+			.integer("tau_vacc", vec![self.tau_vacc as i64])
+			.float("random_vacc", vec![self.random_vacc])
+			.float("kappa0", vec![self.kappa0])
+			.float("N_TTI", vec![self.N_TTI])
+			.float("N_test_eff", vec![self.N_test_eff])
+			.float("N_test_ineff", vec![self.N_test_ineff])
+			.float("N_no_test", vec![self.N_no_test])
+			.float("sigma0", vec![self.sigma[0]])
+			.float("sigma1", vec![self.sigma[1]])
+			.float("sigma2", vec![self.sigma[2]]);
+
+		// (the chi and phi columns are always 0, parameters removed from the final model, kept for the column positions)
+		let column = |f: &dyn Fn(&AgeGroup) -> f64| self.age_groups.iter().map(f).collect::<Vec<f64>>();
+		let mut age_groups = Table::new("age_groups", TableKind::Parameters)
+			.text("name", self.age_groups.iter().map(|ag| ag.name.clone()).collect())
+			.float("M", column(&|ag| ag.M))
+			.float("influx", column(&|ag| ag.influx))
+			.float("chi", column(&|_| 0.0))
+			.float("phi0", column(&|_| 0.0))
+			.float("phi1", column(&|_| 0.0))
+			.float("phi2", column(&|_| 0.0))
+			.float("rho", column(&|ag| ag.rho));
+		for (name, rates) in [("gamma", (|ag: &AgeGroup| ag.gamma_I) as fn(&AgeGroup) -> [f64; 3]), ("gamma^ICU", |ag| ag.gamma_ICU), ("alpha", |ag| ag.alpha),
+							  ("delta", |ag| ag.delta_I), ("delta^ICU", |ag| ag.delta_ICU)] {
+			for vacc in 0..3 {
+				age_groups = age_groups.float(&format!("{}{}", name, vacc), column(&|ag| rates(ag)[vacc]));
+			}
+		}
+		vec![model, age_groups]
+	}
+
+}
@@ -0,0 +1,265 @@
+//! Model predictive control of the contact restrictions.
+//!
+//! Instead of reacting to the deviation from a set point (see the _controller_ submodule), the model predictive controller plans a
+//! piecewise-constant $R_t$ trajectory over a rolling horizon: the first piece lasts one day (the decision applied now), all further pieces last
+//! _segment\_length_ days. The plan allows as many contacts as possible, i.e. the pieces are chosen one after the other as large as possible,
+//! such that the ICU occupancy predicted by the model stays below the capacity over the whole horizon even if all later pieces decrease $R_t$
+//! as fast as allowed. $R_t$ changes by at most _max\_slope_ per day and stays between _min\_Rt_ and _max\_Rt_ (all TTI corrected). The limits
+//! of every piece are converted to the raw $R_t$ with the observed cases predicted for the start of the piece.
+//!
+//! The predictions use the model itself, starting from the current state with the delay history of the solver. Only the first piece is applied,
+//! the next day the plan is optimized again.
+
+use crate::vaccination_model::age_group_state_vector::States;
+use crate::vaccination_model::delay_system::DelaySystem;
+use crate::vaccination_model::error::Result;
+use crate::vaccination_model::solver::{locate_position, Integrator, Solver};
+use crate::vaccination_model::target::{Series, Target};
+
+/// Settings of the model predictive controller (see the module documentation)
+#[derive(Clone, Debug)]
+pub struct MPC {
+	/// Prediction horizon in days
+	pub horizon: f64,
+	/// Length of the pieces of the planned trajectory after the first day (in days)
+	pub segment_length: f64,
+	/// Maximal ICU occupancy allowed in the predictions
+	pub ICU_capacity: f64,
+	/// Minimal $R_t$ (TTI corrected)
+	pub min_Rt: f64,
+	/// Maximal $R_t$ (TTI corrected)
+	pub max_Rt: f64,
+	/// Maximal change of $R_t$ per day (TTI corrected)
+	pub max_slope: f64,
+	/// Precision of the optimized $R_t$ values
+	pub tolerance: f64,
+	/// Integrator used for the predictions
+	pub integrator: Integrator,
+	/// The last planned trajectory as (start time, raw $R_t$) of every piece
+	pub plan: Vec<(f64, f64)>
+}
+
+impl MPC {
+	/// Model predictive controller with a horizon of four weeks planned in weekly pieces. The predictions use the adaptive integrator.
+	pub fn new(ICU_capacity: f64, min_Rt: f64, max_Rt: f64, max_slope: f64) -> MPC {
+		MPC { horizon: 28.0, segment_length: 7.0, ICU_capacity, min_Rt, max_Rt, max_slope, tolerance: 1e-3,
+			  integrator: Integrator::Adaptive { rtol: 1e-5, atol: 1e-3 }, plan: Vec::new() }
+	}
+
+	/// Lengths of the pieces of the planned trajectory (in days)
+	fn pieces(&self) -> Vec<f64> {
+		let mut pieces = vec![1.0];
+		let mut planned = 1.0;
+		while planned < self.horizon {
+			let length = self.segment_length.min(self.horizon - planned);
+			pieces.push(length);
+			planned += length;
+		}
+		pieces
+	}
+}
+
+/// Result of a prediction
+struct Prediction {
+	/// Maximal predicted ICU occupancy
+	max_ICU: f64,
+	/// Predicted observed cases at the start of every piece
+	N_obs: Vec<f64>
+}
+
+/// The end of the solver history needed for predictions (at least one delay and the observation kernel of the cases)
+struct Tail<S> {
+	time: Vec<f64>,
+	Rt: Vec<f64>,
+	states: States<S>,
+	N: Vec<f64>
+}
+
+impl<M: DelaySystem> Solver<M> {
+	/// Runs the simulation for a timespan T with $R_t$ determined every day by the model predictive controller _mpc_.
+	/// Registered events are handled like in _controlled\_run_.
+	///
+	/// Returns the mean allowed (TTI corrected) $R_t$ over the run. Fails if the run or the predictions reach beyond the model inputs
+	/// (see _check\_horizon_).
+	pub fn mpc_run(&mut self, T: f64, mpc: &mut MPC) -> Result<f64> {
+		let bin_length = 1.0;
+		let N_bins = (T/bin_length) as usize;
+		let t0 = self.time[self.index];
+		let pieces = mpc.pieces();
+
+		let N = (bin_length/self.dt) as usize + 1;
+		let mut day_time: Vec<f64> = Vec::with_capacity(N);
+		let mut day_Rt: Vec<f64> = Vec::with_capacity(N);
+		let mut day_states = States::with_capacity(self.initials.len(), N);
+		let mut day_N: Vec<f64> = Vec::with_capacity(N);
+		let mut R = self.Rt[self.index];
+		let mut Rt_sum = 0.0;
+
+		for bin in 1..N_bins+1 {
+			// Plan the trajectory piece by piece. The limits of every piece are converted to the raw Rt with the observed cases predicted
+			// for its start (the prediction of the value chosen for the previous piece).
+			let tail = self.history_tail();
+			let t_now = self.time[self.index];
+			let mut plan: Vec<f64> = Vec::with_capacity(pieces.len());
+			let mut starts = vec![self.N_obs[self.index.min(self.N_obs.len()-1)]];
+			for j in 0..pieces.len() {
+				let (min_raw_Rt, max_raw_Rt, max_raw_slope) = self.raw_limits(mpc, starts[j]);
+				let (previous, gap) = if j == 0 { (R, bin_length) } else { (plan[j-1], pieces[j-1]) };
+				let lower = (previous - max_raw_slope*gap).max(min_raw_Rt).min(max_raw_Rt);
+				let upper = (previous + max_raw_slope*gap).min(max_raw_Rt).max(min_raw_Rt);
+
+				// Trajectory with the candidate for piece j, decreasing as fast as possible afterwards
+				let predict = |candidate: f64, plan: &[f64]| -> Result<Prediction> {
+					let mut planned = plan.to_vec();
+					planned.push(candidate);
+					self.predict(&tail, &pieces, &planned, mpc)
+				};
+
+				let upper_prediction = predict(upper, &plan)?;
+				let (value, prediction) = if upper_prediction.max_ICU <= mpc.ICU_capacity {
+					(upper, upper_prediction)
+				} else {
+					let lower_prediction = predict(lower, &plan)?;
+					if lower_prediction.max_ICU > mpc.ICU_capacity {
+						(lower, lower_prediction)
+					} else {
+						let (mut low, mut high, mut best) = (lower, upper, lower_prediction);
+						while high - low > mpc.tolerance {
+							let middle = 0.5*(low + high);
+							let prediction = predict(middle, &plan)?;
+							if prediction.max_ICU <= mpc.ICU_capacity {
+								low = middle;
+								best = prediction;
+							} else {
+								high = middle;
+							}
+						}
+						(low, best)
+					}
+				};
+				plan.push(value);
+				starts = prediction.N_obs;
+			}
+			let mut t_piece = t_now;
+			mpc.plan = pieces.iter().zip(plan.iter()).map(|(length, R)| { let piece = (t_piece, *R); t_piece += length; piece }).collect();
+
+			// Apply the first piece for one day
+			R = plan[0];
+			self.integrate(bin_length, &mut day_time, &mut day_Rt, &mut day_states, &mut day_N, &self.time, &self.Rt, &self.states, R)?;
+			let bin_index = locate_position(&day_time, t0 + (bin as f64)*bin_length);
+			self.Rt.extend_from_slice(&day_Rt[0..bin_index]);
+			self.states.extend_from(&day_states, 0..bin_index);
+			self.time.extend_from_slice(&day_time[0..bin_index]);
+			self.N.extend_from_slice(&day_N[0..bin_index]);
+			let previous_index = self.index;
+			self.index += bin_index;
+
+			let Nobs = self.observed_cases();
+			self.N_obs.resize(self.index+1, Nobs);
+			Rt_sum += self.model.raw_Rt_to_TTI_corrected(R, Nobs);
+
+			day_time.clear();
+			day_Rt.clear();
+			day_states.clear();
+			day_N.clear();
+
+			if self.handle_events(previous_index, self.index, &mut R) {
+				return Ok(Rt_sum/(bin as f64));
+			}
+		}
+
+		Ok(Rt_sum/(N_bins.max(1) as f64))
+	}
+
+	/// Copies the last part of the history (one delay plus a few points for interpolation, at least the days of the observation kernel of the
+	/// cases) that the predictions need.
+	fn history_tail(&self) -> Tail<M::State> {
+		let one_day = (1./self.dt) as usize;
+		let kernel_days = Target::ObservedCases.observation_delay(&self.observation).len();
+		let length = ((self.model.delay()/self.dt) as usize + 4).max(one_day*(kernel_days + 1) + 1);
+		let first = (self.index+1).saturating_sub(length);
+		let mut states = States::with_capacity(self.initials.len(), self.index+1-first);
+		states.extend_from(&self.states, first..self.index+1);
+		Tail { time: self.time[first..self.index+1].to_vec(), Rt: self.Rt[first..self.index+1].to_vec(), states, N: self.N[first..self.index+1].to_vec() }
+	}
+
+	/// Limits of the raw $R_t$ (minimum, maximum and maximal change per day) for the observed cases _Nobs_
+	fn raw_limits(&self, mpc: &MPC, Nobs: f64) -> (f64, f64, f64) {
+		let min_raw_Rt = self.model.raw_Rt_from_TTI_corrected(mpc.min_Rt, Nobs);
+		let max_raw_Rt = self.model.raw_Rt_from_TTI_corrected(mpc.max_Rt, Nobs);
+		let max_raw_slope = self.model.raw_Rt_from_TTI_corrected(mpc.max_slope, Nobs) - self.model.raw_Rt_from_TTI_corrected(0.0, Nobs);
+		(min_raw_Rt, max_raw_Rt, max_raw_slope)
+	}
+
+	/// Predicts the system for a piecewise-constant $R_t$ trajectory (lengths of the pieces in _pieces_): the first pieces get the raw $R_t$
+	/// values _planned_, all further pieces decrease $R_t$ as fast as the limits at their start allow.
+	fn predict(&self, tail: &Tail<M::State>, pieces: &[f64], planned: &[f64], mpc: &MPC) -> Result<Prediction> {
+		let mut time = tail.time.clone();
+		let mut Rt = tail.Rt.clone();
+		let mut states = tail.states.clone();
+		let mut N = tail.N.clone();
+		let (mut piece_time, mut piece_Rt, mut piece_N) = (Vec::new(), Vec::new(), Vec::new());
+		let mut piece_states = States::with_capacity(self.initials.len(), 0);
+		let mut max_ICU = self.model.ICU_occupancy(&states[states.len()-1]);
+		let mut N_obs: Vec<f64> = Vec::with_capacity(pieces.len());
+		let mut R = 0.0;
+
+		for (k, length) in pieces.iter().enumerate() {
+			N_obs.push(if k == 0 {
+				self.N_obs[self.index.min(self.N_obs.len()-1)]
+			} else {
+				let series = Series { states: &states, N: &N, end: N.len()-1, preview: None, t0: time[0], dt: self.dt };
+				Target::ObservedCases.observe(&self.model, &self.observation, &series, N.len()-1)
+			});
+			R = match planned.get(k) {
+				Some(R) => *R,
+				None => {
+					let (min_raw_Rt, _, max_raw_slope) = self.raw_limits(mpc, N_obs[k]);
+					(R - max_raw_slope*pieces[k-1]).max(min_raw_Rt)
+				}
+			};
+
+			self.integrate_with(mpc.integrator, *length, &mut piece_time, &mut piece_Rt, &mut piece_states, &mut piece_N, &time, &Rt, &states, R)?;
+			for state in piece_states.iter() {
+				max_ICU = max_ICU.max(self.model.ICU_occupancy(state));
+			}
+			time.extend_from_slice(&piece_time);
+			Rt.extend_from_slice(&piece_Rt);
+			states.extend_from(&piece_states, 0..piece_states.len());
+			N.extend_from_slice(&piece_N);
+			piece_time.clear();
+			piece_Rt.clear();
+			piece_states.clear();
+			piece_N.clear();
+		}
+		Ok(Prediction { max_ICU, N_obs })
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::vaccination_model::solver::tests::small_solver;
+
+	#[test]
+	fn keeps_ICU_below_the_capacity_and_Rt_within_the_limits() {
+		let mut solver = small_solver(1e5);
+		solver.initialize().unwrap();
+		let mut mpc = MPC { horizon: 14.0, tolerance: 1e-2, ..MPC::new(12.0, 0.7, 3.0, 0.5) };
+		solver.mpc_run(40.0, &mut mpc).unwrap();
+
+		// Every day the limits are converted with the observed cases at the start of the day
+		let mut Nobs = solver.N_obs[0];
+		for k in 0..solver.index {
+			if k > 0 && solver.Rt[k+1] != solver.Rt[k] {
+				Nobs = solver.N_obs[k];
+			}
+			let Rt = solver.model.raw_Rt_to_TTI_corrected(solver.Rt[k+1], Nobs);
+			assert!(mpc.min_Rt - 1e-9 <= Rt && Rt <= mpc.max_Rt + 1e-9, "Rt {} at t={}", Rt, solver.time[k]);
+		}
+		let max_ICU = solver.states.iter().map(|x| solver.model.ICU_occupancy(x)).fold(0.0, f64::max);
+		assert!(max_ICU <= mpc.ICU_capacity*(1.0 + 1e-3), "maximal ICU occupancy {}", max_ICU);
+		// The capacity is used
+		assert!(max_ICU > 0.9*mpc.ICU_capacity, "maximal ICU occupancy {}", max_ICU);
+	}
+}
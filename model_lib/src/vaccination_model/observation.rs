@@ -0,0 +1,127 @@
+//! Observation model: how the infections, ICU admissions and deaths of the model show up in the reported data.
+//!
+//! Only a fraction of the infections is ascertained (age-specific), and all events are reported with a delay given by a discrete delay distribution
+//! (daily weights, see _discretized\_gamma_). The reported daily numbers are further modulated by weekday effects (e.g. fewer reports on weekends)
+//! and holidays. The observation model is used for $N_{obs}$ (which enters the test-trace-and-isolate correction of $R_t$), for the control
+//! targets (see _target_) and for the observed daily series of _Solver::observed\_daily_.
+//!
+//! The default observation model reproduces the empirical observation kernel with full ascertainment and without weekday effects.
+
+use crate::vaccination_model::delay_system::DelaySystem;
+use crate::vaccination_model::solver::{Solver, OBSERVATION_KERNEL};
+use crate::vaccination_model::target::{Series, Target};
+
+/// Assumed reporting delay of ICU admissions: most are reported the next day.
+pub const ADMISSION_KERNEL: [f64; 4] = [0.0, 0.6, 0.3, 0.1];
+
+/// Assumed reporting delay of deaths: reported over the course of two weeks.
+pub const DEATH_KERNEL: [f64; 14] = [0.0, 0.0, 0.05, 0.1, 0.15, 0.15, 0.12, 0.1, 0.08, 0.07, 0.06, 0.05, 0.04, 0.03];
+
+/// Reports during the days from _start_ to _end_ (exclusive) are multiplied with _factor_ (e.g. 0.5 over Christmas)
+#[derive(Clone, Debug, PartialEq)]
+pub struct Holiday {
+	/// First day of the holiday
+	pub start: f64,
+	/// First day after the holiday
+	pub end: f64,
+	/// Fraction of the usual reports
+	pub factor: f64
+}
+
+/// How the model quantities are observed, see the module documentation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ObservationModel {
+	/// Reporting delay of infections (fraction reported 0, 1, 2, ... days after the infection)
+	pub case_delay: Vec<f64>,
+	/// Reporting delay of ICU admissions
+	pub admission_delay: Vec<f64>,
+	/// Reporting delay of deaths
+	pub death_delay: Vec<f64>,
+	/// Fraction of the infections that is ascertained in every age group (all of them if empty)
+	pub ascertainment: Vec<f64>,
+	/// Factor for the reports of every weekday (Monday first)
+	pub weekday_effects: [f64; 7],
+	/// Weekday of day 0 (0 is Monday)
+	pub first_weekday: usize,
+	/// Holidays with less reports
+	pub holidays: Vec<Holiday>
+}
+
+impl Default for ObservationModel {
+	fn default() -> ObservationModel {
+		ObservationModel {
+			case_delay: OBSERVATION_KERNEL.to_vec(),
+			admission_delay: ADMISSION_KERNEL.to_vec(),
+			death_delay: DEATH_KERNEL.to_vec(),
+			ascertainment: Vec::new(),
+			weekday_effects: [1.0; 7],
+			first_weekday: 0,
+			holidays: Vec::new()
+		}
+	}
+}
+
+impl ObservationModel {
+	/// Fraction of the infections that is ascertained in block (age group) i
+	pub fn ascertained(&self, i: usize) -> f64 {
+		if self.ascertainment.is_empty() { 1.0 } else { self.ascertainment[i] }
+	}
+
+	/// Factor for the reports of the day containing time t (weekday effect and holidays)
+	pub fn reporting_factor(&self, t: f64) -> f64 {
+		let day = t.floor();
+		let weekday = (day as i64 + self.first_weekday as i64).rem_euclid(7) as usize;
+		let mut factor = self.weekday_effects[weekday];
+		for holiday in self.holidays.iter() {
+			if day >= holiday.start && day < holiday.end {
+				factor *= holiday.factor;
+			}
+		}
+		factor
+	}
+
+	/// Checks the delay distributions, ascertainment fractions and reporting factors (non-empty delays, no negative or infinite values)
+	pub fn is_valid(&self) -> bool {
+		let valid = |values: &[f64]| values.iter().all(|x| *x >= 0.0 && x.is_finite());
+		!self.case_delay.is_empty() && !self.admission_delay.is_empty() && !self.death_delay.is_empty()
+			&& valid(&self.case_delay) && valid(&self.admission_delay) && valid(&self.death_delay) && valid(&self.ascertainment)
+			&& valid(&self.weekday_effects) && self.holidays.iter().all(|holiday| valid(&[holiday.factor]) && holiday.start <= holiday.end)
+	}
+}
+
+/// Daily weights of a gamma distributed delay with the given mean and standard deviation (in days), truncated after _days_ days and normalized.
+/// Weight d is the probability of a delay between d and d+1 days.
+pub fn discretized_gamma(mean: f64, sd: f64, days: usize) -> Vec<f64> {
+	let shape = (mean/sd).powi(2);
+	let scale = sd*sd/mean;
+	// The density up to the normalization, integrated over every day with the midpoint rule
+	let steps = 100;
+	let density = |x: f64| x.powf(shape-1.0)*(-x/scale).exp();
+	let mut weights: Vec<f64> = (0..days).map(|day| {
+		(0..steps).map(|j| density(day as f64 + (j as f64 + 0.5)/(steps as f64))).sum::<f64>()/(steps as f64)
+	}).collect();
+	let total: f64 = weights.iter().sum();
+	for weight in weights.iter_mut() {
+		*weight /= total;
+	}
+	weights
+}
+
+impl<M: DelaySystem> Solver<M> {
+	/// Observed daily values of _target_ (with the observation model of the solver) at the beginning of every day of the results, as (t, value).
+	pub fn observed_daily(&self, target: &Target) -> Vec<(f64, f64)> {
+		let one_day = (1./self.dt) as usize;
+		let series = Series { states: &self.states, N: &self.N, end: self.index, preview: None, t0: self.time[0], dt: self.dt };
+		(0..=self.index).step_by(one_day).map(|k| (self.time[k], target.observe(&self.model, &self.observation, &series, k))).collect()
+	}
+
+	/// Observed daily ICU admissions, see _observed\_daily_
+	pub fn observed_ICU_admissions(&self) -> Vec<(f64, f64)> {
+		self.observed_daily(&Target::ICUAdmissions)
+	}
+
+	/// Observed daily deaths, see _observed\_daily_
+	pub fn observed_deaths(&self) -> Vec<(f64, f64)> {
+		self.observed_daily(&Target::Deaths)
+	}
+}
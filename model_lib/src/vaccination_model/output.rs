@@ -0,0 +1,673 @@
+//! Output of results and parameters as tables in different file formats.
+//!
+//! Everything the solver writes (the parameters of the model and the age groups, $R_t$ and the daily infections, the compartments of every
+//! age group) is first collected in a _Table_: a name and named, typed columns of equal length. Parameters are tables with a single row,
+//! time series have one row per written instant. The same tables, with the same column names, are then written by any _Format_:
+//!
+//! - _Tsv_: the original tab separated text files ("tHRt.data", "model.params", ...) with 6 decimals,
+//! - _Csv_: comma separated values (RFC 4180) with full precision,
+//! - _Json_: one object per table with the column names, types and values,
+//! - _Parquet_: the columnar Apache Parquet format (uncompressed, plain encoding, one row group), readable e.g. by pandas, polars or arrow.
+//!
+//! Further formats can be added by implementing _Format_.
+
+use crate::vaccination_model::error::Result;
+use crate::vaccination_model::sink::Sink;
+use std::io::Write;
+
+/// Values of a column
+#[derive(Clone, Debug, PartialEq)]
+pub enum Values {
+	/// Floating point numbers
+	Float(Vec<f64>),
+	/// Integers
+	Integer(Vec<i64>),
+	/// Text, e.g. names
+	Text(Vec<String>)
+}
+
+impl Values {
+	/// Number of values
+	pub fn len(&self) -> usize {
+		match self {
+			Values::Float(values) => values.len(),
+			Values::Integer(values) => values.len(),
+			Values::Text(values) => values.len()
+		}
+	}
+
+	/// Whether there are no values
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	/// Name of the type in the schema ("double", "int64" or "string")
+	pub fn type_name(&self) -> &'static str {
+		match self {
+			Values::Float(_) => "double",
+			Values::Integer(_) => "int64",
+			Values::Text(_) => "string"
+		}
+	}
+}
+
+/// A named column of a table
+#[derive(Clone, Debug, PartialEq)]
+pub struct Column {
+	/// Name of the column
+	pub name: String,
+	/// Values of the column (one per row)
+	pub values: Values
+}
+
+/// Whether a table contains parameters or time series (used for the file names)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TableKind {
+	/// Parameters (usually a single row)
+	Parameters,
+	/// Time series (one row per instant)
+	Series
+}
+
+/// A table with named, typed columns of equal length
+#[derive(Clone, Debug, PartialEq)]
+pub struct Table {
+	/// Name of the table (the file name without extension, e.g. "tHRt" or "model")
+	pub name: String,
+	/// Parameters or time series
+	pub kind: TableKind,
+	/// Remark on the contents (empty if none), e.g. on the meaning of the first row
+	pub description: String,
+	/// The columns
+	pub columns: Vec<Column>
+}
+
+impl Table {
+	/// Empty table
+	pub fn new(name: &str, kind: TableKind) -> Table {
+		Table { name: name.to_string(), kind, description: String::new(), columns: Vec::new() }
+	}
+
+	/// Sets the description
+	pub fn describe(mut self, description: &str) -> Table {
+		self.description = description.to_string();
+		self
+	}
+
+	/// Adds a column of floating point numbers
+	pub fn float(mut self, name: &str, values: Vec<f64>) -> Table {
+		self.columns.push(Column { name: name.to_string(), values: Values::Float(values) });
+		self
+	}
+
+	/// Adds a column of integers
+	pub fn integer(mut self, name: &str, values: Vec<i64>) -> Table {
+		self.columns.push(Column { name: name.to_string(), values: Values::Integer(values) });
+		self
+	}
+
+	/// Adds a column of text
+	pub fn text(mut self, name: &str, values: Vec<String>) -> Table {
+		self.columns.push(Column { name: name.to_string(), values: Values::Text(values) });
+		self
+	}
+
+	/// Builds a table of floating point numbers from rows of values (one per column name)
+	pub fn from_rows(name: &str, kind: TableKind, names: &[String], rows: &[Vec<f64>]) -> Table {
+		let mut table = Table::new(name, kind);
+		for (c, name) in names.iter().enumerate() {
+			table = table.float(name, rows.iter().map(|row| row[c]).collect());
+		}
+		table
+	}
+
+	/// Number of rows (the length of the shortest column)
+	pub fn rows(&self) -> usize {
+		self.columns.iter().map(|column| column.values.len()).min().unwrap_or(0)
+	}
+
+	/// The column with the given name (if any)
+	pub fn column(&self, name: &str) -> Option<&Column> {
+		self.columns.iter().find(|column| column.name == name)
+	}
+}
+
+/// A file format tables can be written in
+pub trait Format {
+	/// File name of a table in this format
+	fn file_name(&self, table: &Table) -> String;
+
+	/// Writes a table
+	fn write(&self, table: &Table, writer: &mut dyn Write) -> std::io::Result<()>;
+}
+
+/// Writes the _tables_ in the given format to the _sink_ (see the _sink_ submodule), one file per table
+pub fn write_tables(sink: &mut dyn Sink, tables: &[Table], format: &dyn Format) -> Result<()> {
+	for table in tables.iter() {
+		sink.write_file(&format.file_name(table), &mut |writer| format.write(table, writer))?;
+	}
+	Ok(())
+}
+
+/// File name "{name}.{extension}", with "\_params" appended to the name of parameter tables
+fn file_name(table: &Table, extension: &str) -> String {
+	match table.kind {
+		TableKind::Parameters => format!("{}_params.{}", table.name, extension),
+		TableKind::Series => format!("{}.{}", table.name, extension)
+	}
+}
+
+/// The original tab separated text files: a header line with the column names (followed by the description in parentheses), then one line
+/// per row with the values separated by " \t ".
+/// Floating point numbers are written with _precision_ decimals. Parameter tables are written to "{name}.params", time series to "{name}.data".
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tsv {
+	/// Decimals of floating point numbers
+	pub precision: usize
+}
+
+impl Default for Tsv {
+	/// 6 decimals
+	fn default() -> Tsv {
+		Tsv { precision: 6 }
+	}
+}
+
+impl Format for Tsv {
+	fn file_name(&self, table: &Table) -> String {
+		match table.kind {
+			TableKind::Parameters => format!("{}.params", table.name),
+			TableKind::Series => format!("{}.data", table.name)
+		}
+	}
+
+	fn write(&self, table: &Table, writer: &mut dyn Write) -> std::io::Result<()> {
+		write!(writer, "{}", table.columns.iter().map(|column| column.name.as_str()).collect::<Vec<&str>>().join(" \t "))?;
+		if table.description.is_empty() {
+			writeln!(writer)?;
+		} else {
+			writeln!(writer, " ({})", table.description)?;
+		}
+		for row in 0..table.rows() {
+			let values: Vec<String> = table.columns.iter().map(|column| match &column.values {
+				Values::Float(values) => format!("{:.1$}", values[row], self.precision),
+				Values::Integer(values) => values[row].to_string(),
+				Values::Text(values) => values[row].clone()
+			}).collect();
+			writeln!(writer, "{}", values.join(" \t "))?;
+		}
+		Ok(())
+	}
+}
+
+/// Comma separated values (RFC 4180): a header line with the column names, then one line per row. Floating point numbers are written
+/// with full precision (shortest representation that reads back exactly), text is quoted if necessary. The description is not written.
+/// Files are named "{name}.csv".
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Csv;
+
+impl Csv {
+	/// Quotes a field if it contains a comma, a quote or a line break
+	fn field(text: &str) -> String {
+		if text.contains([',', '"', '\n', '\r']) {
+			format!("\"{}\"", text.replace('"', "\"\""))
+		} else {
+			text.to_string()
+		}
+	}
+}
+
+impl Format for Csv {
+	fn file_name(&self, table: &Table) -> String {
+		file_name(table, "csv")
+	}
+
+	fn write(&self, table: &Table, writer: &mut dyn Write) -> std::io::Result<()> {
+		write!(writer, "{}\r\n", table.columns.iter().map(|column| Csv::field(&column.name)).collect::<Vec<String>>().join(","))?;
+		for row in 0..table.rows() {
+			let values: Vec<String> = table.columns.iter().map(|column| match &column.values {
+				Values::Float(values) => values[row].to_string(),
+				Values::Integer(values) => values[row].to_string(),
+				Values::Text(values) => Csv::field(&values[row])
+			}).collect();
+			write!(writer, "{}\r\n", values.join(","))?;
+		}
+		Ok(())
+	}
+}
+
+/// JSON: one object per table, `{"name": ..., "kind": "parameters"|"series", "description": ..., "rows": ..., "columns": [{"name": ..., "type": ..., "values": [...]}, ...]}`.
+/// The types are the ones of _Values::type\_name_, non-finite numbers are written as null. Files are named "{name}.json".
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Json;
+
+impl Json {
+	/// String literal with the necessary escapes
+	fn string(text: &str) -> String {
+		let mut escaped = String::with_capacity(text.len() + 2);
+		escaped.push('"');
+		for c in text.chars() {
+			match c {
+				'"' => escaped.push_str("\\\""),
+				'\\' => escaped.push_str("\\\\"),
+				'\n' => escaped.push_str("\\n"),
+				'\r' => escaped.push_str("\\r"),
+				'\t' => escaped.push_str("\\t"),
+				c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+				c => escaped.push(c)
+			}
+		}
+		escaped.push('"');
+		escaped
+	}
+
+	/// Number literal (null if not finite)
+	fn number(x: f64) -> String {
+		if x.is_finite() { format!("{:?}", x) } else { "null".to_string() }
+	}
+}
+
+impl Format for Json {
+	fn file_name(&self, table: &Table) -> String {
+		file_name(table, "json")
+	}
+
+	fn write(&self, table: &Table, writer: &mut dyn Write) -> std::io::Result<()> {
+		let rows = table.rows();
+		let kind = match table.kind { TableKind::Parameters => "parameters", TableKind::Series => "series" };
+		write!(writer, "{{\"name\": {}, \"kind\": \"{}\", \"description\": {}, \"rows\": {}, \"columns\": [", Json::string(&table.name), kind, Json::string(&table.description), rows)?;
+		for (c, column) in table.columns.iter().enumerate() {
+			let values: Vec<String> = match &column.values {
+				Values::Float(values) => values[..rows].iter().map(|x| Json::number(*x)).collect(),
+				Values::Integer(values) => values[..rows].iter().map(|x| x.to_string()).collect(),
+				Values::Text(values) => values[..rows].iter().map(|x| Json::string(x)).collect()
+			};
+			write!(writer, "{}\n  {{\"name\": {}, \"type\": \"{}\", \"values\": [{}]}}", if c == 0 { "" } else { "," }, Json::string(&column.name), column.values.type_name(), values.join(", "))?;
+		}
+		writeln!(writer, "\n]}}")
+	}
+}
+
+/// Apache Parquet: all columns in one row group, one uncompressed data page per column with plain encoding. Floating point numbers are
+/// DOUBLE, integers INT64 and text UTF8 BYTE\_ARRAY columns, all required. The description is stored in the key-value metadata.
+/// Files are named "{name}.parquet".
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Parquet;
+
+impl Format for Parquet {
+	fn file_name(&self, table: &Table) -> String {
+		file_name(table, "parquet")
+	}
+
+	fn write(&self, table: &Table, writer: &mut dyn Write) -> std::io::Result<()> {
+		let rows = table.rows();
+		let mut file: Vec<u8> = b"PAR1".to_vec();
+		let mut chunks = Vec::with_capacity(table.columns.len());
+
+		for column in table.columns.iter() {
+			// Page data: the plain encoded values (no levels, since the columns are required)
+			let mut data: Vec<u8> = Vec::new();
+			let physical_type = match &column.values {
+				Values::Float(values) => {
+					values[..rows].iter().for_each(|x| data.extend_from_slice(&x.to_le_bytes()));
+					5
+				},
+				Values::Integer(values) => {
+					values[..rows].iter().for_each(|x| data.extend_from_slice(&x.to_le_bytes()));
+					2
+				},
+				Values::Text(values) => {
+					for x in values[..rows].iter() {
+						data.extend_from_slice(&(x.len() as u32).to_le_bytes());
+						data.extend_from_slice(x.as_bytes());
+					}
+					6
+				}
+			};
+
+			// Page header: data page (0) with sizes and the data page header (number of values, plain encoding, RLE for the levels)
+			let mut header = Thrift::default();
+			header.i32_field(1, 0);
+			header.i32_field(2, data.len() as i32);
+			header.i32_field(3, data.len() as i32);
+			header.begin_struct(5);
+			header.i32_field(1, rows as i32);
+			header.i32_field(2, 0);
+			header.i32_field(3, 3);
+			header.i32_field(4, 3);
+			header.end_struct();
+			header.stop();
+
+			let offset = file.len();
+			file.extend_from_slice(&header.bytes);
+			file.extend_from_slice(&data);
+			chunks.push((physical_type, offset, header.bytes.len() + data.len()));
+		}
+
+		// File metadata
+		let mut meta = Thrift::default();
+		meta.i32_field(1, 1);
+		meta.begin_list(2, 12, table.columns.len() + 1);
+		meta.begin_element();
+		meta.binary_field(4, "schema".as_bytes());
+		meta.i32_field(5, table.columns.len() as i32);
+		meta.end_struct();
+		for (column, chunk) in table.columns.iter().zip(chunks.iter()) {
+			meta.begin_element();
+			meta.i32_field(1, chunk.0);
+			meta.i32_field(3, 0);
+			meta.binary_field(4, column.name.as_bytes());
+			if chunk.0 == 6 {
+				meta.i32_field(6, 0);
+			}
+			meta.end_struct();
+		}
+		meta.i64_field(3, rows as i64);
+		meta.begin_list(4, 12, 1);
+		meta.begin_element();
+		meta.begin_list(1, 12, table.columns.len());
+		for (column, chunk) in table.columns.iter().zip(chunks.iter()) {
+			meta.begin_element();
+			meta.i64_field(2, chunk.1 as i64);
+			meta.begin_struct(3);
+			meta.i32_field(1, chunk.0);
+			meta.begin_list(2, 5, 2);
+			meta.list_i32(0);
+			meta.list_i32(3);
+			meta.begin_list(3, 8, 1);
+			meta.list_binary(column.name.as_bytes());
+			meta.i32_field(4, 0);
+			meta.i64_field(5, rows as i64);
+			meta.i64_field(6, chunk.2 as i64);
+			meta.i64_field(7, chunk.2 as i64);
+			meta.i64_field(9, chunk.1 as i64);
+			meta.end_struct();
+			meta.end_struct();
+		}
+		meta.i64_field(2, chunks.iter().map(|chunk| chunk.2 as i64).sum());
+		meta.i64_field(3, rows as i64);
+		meta.end_struct();
+		if !table.description.is_empty() {
+			meta.begin_list(5, 12, 1);
+			meta.begin_element();
+			meta.binary_field(1, "description".as_bytes());
+			meta.binary_field(2, table.description.as_bytes());
+			meta.end_struct();
+		}
+		meta.binary_field(6, concat!("covid19_vaccine_model version ", env!("CARGO_PKG_VERSION")).as_bytes());
+		meta.stop();
+
+		file.extend_from_slice(&meta.bytes);
+		file.extend_from_slice(&(meta.bytes.len() as u32).to_le_bytes());
+		file.extend_from_slice(b"PAR1");
+		writer.write_all(&file)
+	}
+}
+
+/// Encoder for the Thrift compact protocol used by the Parquet metadata
+#[derive(Default)]
+struct Thrift {
+	bytes: Vec<u8>,
+	/// Id of the last field of every open struct
+	last_field: Vec<i16>,
+	/// Id of the last field of the current struct
+	field: i16
+}
+
+impl Thrift {
+	fn varint(&mut self, mut x: u64) {
+		while x >= 0x80 {
+			self.bytes.push((x as u8 & 0x7f) | 0x80);
+			x >>= 7;
+		}
+		self.bytes.push(x as u8);
+	}
+
+	fn zigzag(&mut self, x: i64) {
+		self.varint(((x << 1) ^ (x >> 63)) as u64);
+	}
+
+	fn field_header(&mut self, id: i16, compact_type: u8) {
+		let delta = id - self.field;
+		if delta > 0 && delta <= 15 {
+			self.bytes.push(((delta as u8) << 4) | compact_type);
+		} else {
+			self.bytes.push(compact_type);
+			self.zigzag(id as i64);
+		}
+		self.field = id;
+	}
+
+	fn i32_field(&mut self, id: i16, x: i32) {
+		self.field_header(id, 5);
+		self.zigzag(x as i64);
+	}
+
+	fn i64_field(&mut self, id: i16, x: i64) {
+		self.field_header(id, 6);
+		self.zigzag(x);
+	}
+
+	fn binary_field(&mut self, id: i16, x: &[u8]) {
+		self.field_header(id, 8);
+		self.list_binary(x);
+	}
+
+	fn begin_struct(&mut self, id: i16) {
+		self.field_header(id, 12);
+		self.begin_element();
+	}
+
+	/// Starts a struct that is an element of a list
+	fn begin_element(&mut self) {
+		self.last_field.push(self.field);
+		self.field = 0;
+	}
+
+	fn end_struct(&mut self) {
+		self.stop();
+		self.field = self.last_field.pop().unwrap_or(0);
+	}
+
+	fn begin_list(&mut self, id: i16, element_type: u8, size: usize) {
+		self.field_header(id, 9);
+		if size < 15 {
+			self.bytes.push(((size as u8) << 4) | element_type);
+		} else {
+			self.bytes.push(0xf0 | element_type);
+			self.varint(size as u64);
+		}
+	}
+
+	fn list_i32(&mut self, x: i32) {
+		self.zigzag(x as i64);
+	}
+
+	fn list_binary(&mut self, x: &[u8]) {
+		self.varint(x.len() as u64);
+		self.bytes.extend_from_slice(x);
+	}
+
+	fn stop(&mut self) {
+		self.bytes.push(0);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::vaccination_model::sink::Memory;
+	use std::collections::BTreeMap;
+	use std::convert::TryInto;
+
+	/// A value of the Thrift compact protocol (only the types used by the Parquet writer)
+	#[derive(Debug)]
+	enum Thrifted {
+		Integer(i64),
+		Binary(Vec<u8>),
+		List(Vec<Thrifted>),
+		Struct(BTreeMap<i16, Thrifted>)
+	}
+
+	impl Thrifted {
+		fn field(&self, id: i16) -> &Thrifted {
+			match self {
+				Thrifted::Struct(fields) => &fields[&id],
+				_ => panic!("Not a struct: {:?}", self)
+			}
+		}
+
+		fn integer(&self) -> i64 {
+			match self {
+				Thrifted::Integer(x) => *x,
+				_ => panic!("Not an integer: {:?}", self)
+			}
+		}
+
+		fn text(&self) -> String {
+			match self {
+				Thrifted::Binary(x) => String::from_utf8(x.clone()).unwrap(),
+				_ => panic!("Not a binary: {:?}", self)
+			}
+		}
+
+		fn list(&self) -> &[Thrifted] {
+			match self {
+				Thrifted::List(x) => x,
+				_ => panic!("Not a list: {:?}", self)
+			}
+		}
+	}
+
+	/// Decoder for the Thrift compact protocol
+	struct Decoder<'a> {
+		bytes: &'a [u8],
+		position: usize
+	}
+
+	impl<'a> Decoder<'a> {
+		fn take(&mut self, n: usize) -> &'a [u8] {
+			self.position += n;
+			&self.bytes[self.position-n..self.position]
+		}
+
+		fn varint(&mut self) -> u64 {
+			let mut x = 0u64;
+			for shift in (0..64).step_by(7) {
+				let byte = self.take(1)[0];
+				x |= ((byte & 0x7f) as u64) << shift;
+				if byte < 0x80 {
+					break;
+				}
+			}
+			x
+		}
+
+		fn zigzag(&mut self) -> i64 {
+			let x = self.varint();
+			((x >> 1) as i64) ^ -((x & 1) as i64)
+		}
+
+		fn value(&mut self, compact_type: u8) -> Thrifted {
+			match compact_type {
+				5 | 6 => Thrifted::Integer(self.zigzag()),
+				8 => {
+					let length = self.varint() as usize;
+					Thrifted::Binary(self.take(length).to_vec())
+				},
+				9 => {
+					let header = self.take(1)[0];
+					let size = if header >> 4 == 15 { self.varint() as usize } else { (header >> 4) as usize };
+					Thrifted::List((0..size).map(|_| self.value(header & 0x0f)).collect())
+				},
+				12 => {
+					let mut fields = BTreeMap::new();
+					let mut id = 0i16;
+					loop {
+						let header = self.take(1)[0];
+						if header == 0 {
+							break;
+						}
+						id = if header >> 4 == 0 { self.zigzag() as i16 } else { id + (header >> 4) as i16 };
+						fields.insert(id, self.value(header & 0x0f));
+					}
+					Thrifted::Struct(fields)
+				},
+				_ => panic!("Unexpected compact type {}", compact_type)
+			}
+		}
+	}
+
+	/// Reads a Parquet file written by _Parquet_ back into a table (with the given name and kind, which are not stored)
+	fn read_parquet(bytes: &[u8], name: &str, kind: TableKind) -> Table {
+		assert_eq!(&bytes[..4], b"PAR1");
+		assert_eq!(&bytes[bytes.len()-4..], b"PAR1");
+		let length = u32::from_le_bytes(bytes[bytes.len()-8..bytes.len()-4].try_into().unwrap()) as usize;
+		let footer = bytes.len() - 8 - length;
+		let meta = Decoder { bytes: &bytes[..bytes.len()-8], position: footer }.value(12);
+		let rows = meta.field(3).integer() as usize;
+
+		let mut table = Table::new(name, kind);
+		if let Thrifted::Struct(fields) = &meta {
+			if let Some(key_values) = fields.get(&5) {
+				assert_eq!(key_values.list()[0].field(1).text(), "description");
+				table = table.describe(&key_values.list()[0].field(2).text());
+			}
+		}
+		let schema = meta.field(2).list();
+		let row_group = &meta.field(4).list()[0];
+		assert_eq!(row_group.field(3).integer() as usize, rows);
+		for (element, chunk) in schema[1..].iter().zip(row_group.field(1).list()) {
+			let chunk_meta = chunk.field(3);
+			assert_eq!(chunk_meta.field(3).list()[0].text(), element.field(4).text());
+			assert_eq!(chunk_meta.field(5).integer() as usize, rows);
+
+			let mut page = Decoder { bytes, position: chunk_meta.field(9).integer() as usize };
+			let header = page.value(12);
+			assert_eq!(header.field(5).field(1).integer() as usize, rows);
+			let mut data = Decoder { bytes: page.take(header.field(3).integer() as usize), position: 0 };
+			let name = element.field(4).text();
+			table = match element.field(1).integer() {
+				5 => table.float(&name, (0..rows).map(|_| f64::from_le_bytes(data.take(8).try_into().unwrap())).collect()),
+				2 => table.integer(&name, (0..rows).map(|_| i64::from_le_bytes(data.take(8).try_into().unwrap())).collect()),
+				6 => table.text(&name, (0..rows).map(|_| {
+					let length = u32::from_le_bytes(data.take(4).try_into().unwrap()) as usize;
+					String::from_utf8(data.take(length).to_vec()).unwrap()
+				}).collect()),
+				other => panic!("Unexpected physical type {}", other)
+			};
+			assert_eq!(data.position, data.bytes.len());
+		}
+		table
+	}
+
+	fn round_trip(table: &Table) -> Table {
+		let mut memory = Memory::new();
+		write_tables(&mut memory, std::slice::from_ref(table), &Parquet).unwrap();
+		assert_eq!(memory.files.len(), 1);
+		read_parquet(&memory.files[&Parquet.file_name(table)], &table.name, table.kind)
+	}
+
+	#[test]
+	fn parquet_round_trip() {
+		let table = Table::new("mixed", TableKind::Series)
+			.describe("First row: initial values")
+			.float("t", vec![0.0, -0.0, 1e-310, f64::MAX, -1.0/3.0])
+			.integer("run", vec![0, -1, i64::MIN, i64::MAX, 42])
+			.text("name", vec![String::new(), "80+".to_string(), "\u{e9}\u{1F600}".to_string(), "x".repeat(300), "tab\t".to_string()]);
+		let read = round_trip(&table);
+		assert_eq!(read, table);
+		if let (Values::Float(read), Values::Float(written)) = (&read.columns[0].values, &table.columns[0].values) {
+			assert!(read.iter().zip(written).all(|(x, y)| x.to_bits() == y.to_bits()));
+		}
+	}
+
+	#[test]
+	fn parquet_round_trip_wide_table() {
+		// More than 15 columns and field ids need the long forms of the list and field headers
+		let names: Vec<String> = (0..40).map(|c| format!("column_{}", c)).collect();
+		let rows: Vec<Vec<f64>> = (0..3).map(|r| (0..40).map(|c| (r*40 + c) as f64/7.0).collect()).collect();
+		let table = Table::from_rows("wide", TableKind::Parameters, &names, &rows);
+		assert_eq!(round_trip(&table), table);
+	}
+}
@@ -0,0 +1,200 @@
+//! A small seedable pseudo random number generator and the distributions needed by the stochastic parts of the library.
+//!
+//! The generator is xoshiro256** seeded by splitmix64, so a given seed always reproduces the same stream of numbers on every platform.
+
+/// Seedable pseudo random number generator (xoshiro256**)
+#[derive(Clone, Debug)]
+pub struct Rng {
+	/// Internal state of the generator
+	s: [u64; 4],
+	/// Second normal deviate of the last Box-Muller transform (if not used yet)
+	spare_normal: Option<f64>
+}
+
+impl Rng {
+	/// Creates a new generator from a seed. The same seed always yields the same sequence.
+	pub fn new(seed: u64) -> Rng {
+		let mut z = seed;
+		let mut s = [0u64; 4];
+		for entry in s.iter_mut() {
+			// splitmix64 to spread the seed over the whole state
+			z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+			let mut x = z;
+			x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+			x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+			*entry = x ^ (x >> 31);
+		}
+		Rng { s, spare_normal: None }
+	}
+
+	/// Returns the next 64 random bits
+	pub fn next_u64(&mut self) -> u64 {
+		let result = self.s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+		let t = self.s[1] << 17;
+		self.s[2] ^= self.s[0];
+		self.s[3] ^= self.s[1];
+		self.s[1] ^= self.s[2];
+		self.s[0] ^= self.s[3];
+		self.s[2] ^= t;
+		self.s[3] = self.s[3].rotate_left(45);
+		result
+	}
+
+	/// Derives an independent generator, e.g. for one member of an ensemble, without disturbing the reproducibility of this one
+	pub fn split(&mut self) -> Rng {
+		Rng::new(self.next_u64())
+	}
+
+	/// Uniformly distributed number in $\[0, 1)$
+	pub fn uniform(&mut self) -> f64 {
+		(self.next_u64() >> 11) as f64 * (1.0 / (1u64 << 53) as f64)
+	}
+
+	/// Standard normal distributed number (Box-Muller transform)
+	pub fn normal(&mut self) -> f64 {
+		if let Some(z) = self.spare_normal.take() {
+			return z;
+		}
+		let u1 = 1.0 - self.uniform();	// in (0, 1], so the logarithm is finite
+		let u2 = self.uniform();
+		let r = (-2.0*u1.ln()).sqrt();
+		let phi = 2.0*std::f64::consts::PI*u2;
+		self.spare_normal = Some(r*phi.sin());
+		r*phi.cos()
+	}
+
+	/// Poisson distributed number with the given mean. Uses multiplication of uniforms for small means and the
+	/// transformed rejection method PTRS (Hörmann 1993) for large ones.
+	pub fn poisson(&mut self, mean: f64) -> u64 {
+		if mean <= 0.0 {
+			return 0;
+		}
+		if mean < 30.0 {
+			let limit = (-mean).exp();
+			let mut k = 0u64;
+			let mut prod = self.uniform();
+			while prod > limit {
+				k += 1;
+				prod *= self.uniform();
+			}
+			return k;
+		}
+
+		let slam = mean.sqrt();
+		let loglam = mean.ln();
+		let b = 0.931 + 2.53*slam;
+		let a = -0.059 + 0.02483*b;
+		let invalpha = 1.1239 + 1.1328/(b - 3.4);
+		let vr = 0.9277 - 3.6224/(b - 2.0);
+		loop {
+			let u = self.uniform() - 0.5;
+			let v = self.uniform();
+			let us = 0.5 - u.abs();
+			let k = ((2.0*a/us + b)*u + mean + 0.43).floor();
+			if us >= 0.07 && v <= vr {
+				return k as u64;
+			}
+			if k < 0.0 || (us < 0.013 && v > us) {
+				continue;
+			}
+			if v.ln() + invalpha.ln() - (a/(us*us) + b).ln() <= -mean + k*loglam - ln_gamma(k + 1.0) {
+				return k as u64;
+			}
+		}
+	}
+
+	/// Gamma distributed number with the given shape and scale (Marsaglia and Tsang 2000, boosted for shapes below 1)
+	pub fn gamma(&mut self, shape: f64, scale: f64) -> f64 {
+		if shape < 1.0 {
+			let u = 1.0 - self.uniform();
+			return self.gamma(shape + 1.0, scale)*u.powf(1.0/shape);
+		}
+		let d = shape - 1.0/3.0;
+		let c = 1.0/(9.0*d).sqrt();
+		loop {
+			let z = self.normal();
+			let v = (1.0 + c*z).powi(3);
+			if v <= 0.0 {
+				continue;
+			}
+			let u = 1.0 - self.uniform();
+			if u.ln() < 0.5*z*z + d - d*v + d*v.ln() {
+				return d*v*scale;
+			}
+		}
+	}
+
+	/// Negative binomially distributed number with the given mean and dispersion $k$ (variance $\mu + \mu^2/k$), drawn as a gamma-Poisson mixture
+	pub fn negative_binomial(&mut self, mean: f64, dispersion: f64) -> u64 {
+		if mean <= 0.0 {
+			return 0;
+		}
+		let rate = self.gamma(dispersion, mean/dispersion);
+		self.poisson(rate)
+	}
+
+	/// Binomially distributed number of successes in _n_ trials with success probability _p_. Uses inversion if the expected
+	/// number of successes (or failures) is small and a normal approximation otherwise.
+	pub fn binomial(&mut self, n: u64, p: f64) -> u64 {
+		if n == 0 || p <= 0.0 {
+			return 0;
+		}
+		if p >= 1.0 {
+			return n;
+		}
+		if p > 0.5 {
+			return n - self.binomial(n, 1.0 - p);
+		}
+
+		let mean = n as f64*p;
+		if mean < 10.0 {
+			// Inversion by sequential search
+			let q = 1.0 - p;
+			let s = p/q;
+			let a = (n as f64 + 1.0)*s;
+			let mut r = q.powf(n as f64);
+			let mut u = self.uniform();
+			let mut x = 0u64;
+			while u > r && x < n {
+				u -= r;
+				x += 1;
+				r *= a/(x as f64) - s;
+			}
+			return x;
+		}
+
+		let x = (mean + (mean*(1.0 - p)).sqrt()*self.normal()).round();
+		x.clamp(0.0, n as f64) as u64
+	}
+}
+
+/// Natural logarithm of the gamma function for positive arguments (Lanczos approximation)
+pub fn ln_gamma(x: f64) -> f64 {
+	const G: [f64; 9] = [0.999_999_999_999_809_9, 676.520_368_121_885_1, -1_259.139_216_722_402_8, 771.323_428_777_653_1,
+						 -176.615_029_162_140_6, 12.507_343_278_686_905, -0.138_571_095_265_720_12, 9.984_369_578_019_572e-6, 1.505_632_735_149_311_6e-7];
+	if x < 0.5 {
+		// Reflection formula
+		return (std::f64::consts::PI/(std::f64::consts::PI*x).sin()).ln() - ln_gamma(1.0 - x);
+	}
+	let x = x - 1.0;
+	let mut a = G[0];
+	let t = x + 7.5;
+	for (i, g) in G.iter().enumerate().skip(1) {
+		a += g/(x + i as f64);
+	}
+	0.5*(2.0*std::f64::consts::PI).ln() + (x + 0.5)*t.ln() - t + a.ln()
+}
+
+/// Returns the empirical quantile _q_ (between 0 and 1) of a sample using linear interpolation between the order statistics.
+///
+/// The sample gets sorted in place.
+pub fn quantile(sample: &mut [f64], q: f64) -> f64 {
+	if sample.is_empty() {
+		return f64::NAN;
+	}
+	sample.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+	let position = q.clamp(0.0, 1.0)*(sample.len() - 1) as f64;
+	let lower = position.floor() as usize;
+	let upper = position.ceil() as usize;
+	sample[lower] + (position - lower as f64)*(sample[upper] - sample[lower])
+}
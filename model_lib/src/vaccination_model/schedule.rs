@@ -0,0 +1,229 @@
+//! Scenario schedules for _controlled\_run_.
+//!
+//! A _Schedule_ is a sequence of phases. In every phase the control system aims at a set point for one target (e.g. the ICU occupancy, see _target_) with
+//! $R_t$ limited to a range and a maximal slope. A phase ends at a given time or as soon as a condition on the state is met (e.g. when the
+//! 80+ year olds are fully vaccinated), then the next phase starts. The last phase lasts until the end of the run.
+
+use crate::vaccination_model::controller::{Controller, PD};
+use crate::vaccination_model::events::Predicate;
+use crate::vaccination_model::target::Target;
+
+/// When a phase ends
+#[derive(Clone)]
+pub enum Until<S> {
+	/// At the given time
+	Time(f64),
+	/// As soon as the condition is met (checked once a day)
+	Condition(Predicate<S>),
+	/// Never (only for the last phase)
+	Never
+}
+
+impl<S> Until<S> {
+	/// Whether the phase ends at time t with the given state
+	pub fn reached(&self, t: f64, state: &[S]) -> bool {
+		match self {
+			Until::Time(t_end) => t >= *t_end,
+			Until::Condition(condition) => condition(t, state),
+			Until::Never => false
+		}
+	}
+}
+
+/// One phase of a schedule
+#[derive(Clone)]
+pub struct Phase<S> {
+	/// End of the phase
+	pub until: Until<S>,
+	/// Minimal $R_t$ (TTI corrected)
+	pub min_Rt: f64,
+	/// Maximal $R_t$ (TTI corrected)
+	pub max_Rt: f64,
+	/// Maximal change of $R_t$ per day (TTI corrected)
+	pub max_slope: f64,
+	/// Controlled quantity (with its observation delay)
+	pub target: Target,
+	/// Set point for the controlled quantity
+	pub set_point: f64,
+	/// Control law (the PD controllers from the manuscript by default)
+	pub controller: Box<dyn Controller>,
+	/// Integrate the ICU occupancy while the ICUs are at the capacity limit (above 70% of the set point) and stop the run when they are emptying.
+	/// _controlled\_run_ then returns the time at the limit.
+	pub measure_ICU_duration: bool
+}
+
+impl<S> Phase<S> {
+	/// Phase aiming at _set\_point_ for _target_ without limits for $R_t$ that never ends. Use the methods below to set the end and the limits.
+	pub fn new(target: Target, set_point: f64) -> Phase<S> {
+		let controller: Box<dyn Controller> = if target.is_stock() {
+			Box::new(PD::ICU())
+		} else {
+			Box::new(PD::cases())
+		};
+		Phase { until: Until::Never, min_Rt: 0.0, max_Rt: f64::INFINITY, max_slope: f64::INFINITY, target, set_point, controller, measure_ICU_duration: false }
+	}
+
+	/// Ends the phase at time t
+	pub fn until_time(mut self, t: f64) -> Phase<S> {
+		self.until = Until::Time(t);
+		self
+	}
+
+	/// Ends the phase as soon as _condition_ is met
+	pub fn until_condition(mut self, condition: Predicate<S>) -> Phase<S> {
+		self.until = Until::Condition(condition);
+		self
+	}
+
+	/// Limits $R_t$ to the range between _min\_Rt_ and _max\_Rt_
+	pub fn Rt_range(mut self, min_Rt: f64, max_Rt: f64) -> Phase<S> {
+		self.min_Rt = min_Rt;
+		self.max_Rt = max_Rt;
+		self
+	}
+
+	/// Limits the change of $R_t$ per day
+	pub fn max_slope(mut self, max_slope: f64) -> Phase<S> {
+		self.max_slope = max_slope;
+		self
+	}
+
+	/// Uses another control law
+	pub fn controller(mut self, controller: Box<dyn Controller>) -> Phase<S> {
+		self.controller = controller;
+		self
+	}
+
+	/// Measures the time at the ICU capacity limit (see _measure\_ICU\_duration_)
+	pub fn measure_ICU_duration(mut self) -> Phase<S> {
+		self.measure_ICU_duration = true;
+		self
+	}
+}
+
+/// A validated sequence of phases
+#[derive(Clone)]
+pub struct Schedule<S> {
+	phases: Vec<Phase<S>>
+}
+
+/// Reasons why a sequence of phases is not a valid schedule
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScheduleError {
+	/// The schedule has no phases
+	Empty,
+	/// The phase ends at an earlier time than a previous phase
+	Unordered { phase: usize },
+	/// The phase never ends, but is not the last one
+	Unreachable { phase: usize },
+	/// The $R_t$ range or the maximal slope of the phase is invalid
+	InvalidLimits { phase: usize },
+	/// The set point of the phase is not positive
+	InvalidSetPoint { phase: usize },
+	/// The parameters of the target of the phase are invalid (see _Target::is\_valid_)
+	InvalidTarget { phase: usize }
+}
+
+impl std::fmt::Display for ScheduleError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			ScheduleError::Empty => write!(f, "Schedule without phases"),
+			ScheduleError::Unordered { phase } => write!(f, "Phase {} ends before a previous phase", phase),
+			ScheduleError::Unreachable { phase } => write!(f, "Phase {} never ends, the following phases are unreachable", phase),
+			ScheduleError::InvalidLimits { phase } => write!(f, "Invalid Rt range or maximal slope in phase {}", phase),
+			ScheduleError::InvalidSetPoint { phase } => write!(f, "Set point of phase {} is not positive", phase),
+			ScheduleError::InvalidTarget { phase } => write!(f, "Invalid target in phase {}", phase)
+		}
+	}
+}
+
+impl std::error::Error for ScheduleError {}
+
+impl<S> Schedule<S> {
+	/// Creates a schedule from its phases. Checks that phases ending at given times are in chronological order, that only the last phase never
+	/// ends and that the limits and set points are valid.
+	pub fn new(phases: Vec<Phase<S>>) -> Result<Schedule<S>, ScheduleError> {
+		if phases.is_empty() {
+			return Err(ScheduleError::Empty);
+		}
+		let mut last_end = f64::NEG_INFINITY;
+		for (i, phase) in phases.iter().enumerate() {
+			match phase.until {
+				Until::Time(t) => {
+					if t < last_end || t.is_nan() {
+						return Err(ScheduleError::Unordered { phase: i });
+					}
+					last_end = t;
+				},
+				Until::Never if i < phases.len()-1 => return Err(ScheduleError::Unreachable { phase: i }),
+				_ => {}
+			}
+			if phase.min_Rt > phase.max_Rt || phase.min_Rt.is_nan() || phase.max_Rt.is_nan() || phase.max_slope < 0.0 || phase.max_slope.is_nan() {
+				return Err(ScheduleError::InvalidLimits { phase: i });
+			}
+			if !(phase.set_point > 0.0 && phase.set_point.is_finite()) {
+				return Err(ScheduleError::InvalidSetPoint { phase: i });
+			}
+			if !phase.target.is_valid() {
+				return Err(ScheduleError::InvalidTarget { phase: i });
+			}
+		}
+		Ok(Schedule { phases })
+	}
+
+	/// The phases of the schedule
+	pub fn phases(&self) -> &[Phase<S>] {
+		&self.phases
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::sync::Arc;
+
+	fn phase() -> Phase<f64> {
+		Phase::new(Target::ICUOccupancy, 20.0)
+	}
+
+	fn error(phases: Vec<Phase<f64>>) -> Option<ScheduleError> {
+		Schedule::new(phases).err()
+	}
+
+	#[test]
+	fn valid_schedules_are_accepted() {
+		let vaccinated: Predicate<f64> = Arc::new(|_, x| x[0] > 0.8);
+		let schedule = Schedule::new(vec![phase().until_time(10.0), phase().until_condition(vaccinated), phase().until_time(10.0), phase()]).unwrap();
+		assert_eq!(schedule.phases().len(), 4);
+		assert!(Schedule::new(vec![phase().Rt_range(1.0, 1.0).max_slope(0.0)]).is_ok());
+	}
+
+	#[test]
+	fn empty_schedules_are_rejected() {
+		assert_eq!(error(vec![]), Some(ScheduleError::Empty));
+	}
+
+	#[test]
+	fn unsorted_end_times_are_rejected() {
+		assert_eq!(error(vec![phase().until_time(20.0), phase().until_time(10.0), phase()]), Some(ScheduleError::Unordered { phase: 1 }));
+		assert_eq!(error(vec![phase().until_time(f64::NAN), phase()]), Some(ScheduleError::Unordered { phase: 0 }));
+	}
+
+	#[test]
+	fn phases_after_an_endless_phase_are_rejected() {
+		assert_eq!(error(vec![phase().until_time(10.0), phase(), phase()]), Some(ScheduleError::Unreachable { phase: 1 }));
+	}
+
+	#[test]
+	fn invalid_limits_are_rejected() {
+		assert_eq!(error(vec![phase().Rt_range(1.2, 0.8)]), Some(ScheduleError::InvalidLimits { phase: 0 }));
+		assert_eq!(error(vec![phase().until_time(10.0), phase().Rt_range(f64::NAN, 1.0)]), Some(ScheduleError::InvalidLimits { phase: 1 }));
+		assert_eq!(error(vec![phase().max_slope(-0.1)]), Some(ScheduleError::InvalidLimits { phase: 0 }));
+	}
+
+	#[test]
+	fn invalid_set_points_are_rejected() {
+		assert_eq!(error(vec![Phase::new(Target::ObservedCases, 0.0)]), Some(ScheduleError::InvalidSetPoint { phase: 0 }));
+		assert_eq!(error(vec![Phase::new(Target::ObservedCases, f64::INFINITY)]), Some(ScheduleError::InvalidSetPoint { phase: 0 }));
+	}
+}
@@ -0,0 +1,348 @@
+//! Global sensitivity analysis of scenario outputs.
+//!
+//! The analysed function maps the values of the factors (uncertain parameters with a range) to one or more outputs, usually by setting up and
+//! running a scenario and evaluating e.g. the total deaths, the ICU days and the freedom of the run (see _Output_). Two methods are available:
+//!
+//! - the elementary effects method of Morris screens many factors with few runs: each trajectory changes one factor after the other by the step
+//!   $\Delta$ (in units of the range), the mean absolute effect $\mu^*$ ranks the importance and the standard deviation $\sigma$ indicates
+//!   nonlinearities and interactions,
+//! - the variance based Sobol indices quantify the fraction of the output variance due to every factor alone (first order $S_i$) and including all
+//!   interactions (total order $S_{T,i}$). They are estimated with the sampling scheme of Saltelli (2010) and the estimator of Jansen for the
+//!   total order indices, i.e. with $N(k+2)$ runs for k factors.
+//!
+//! All runs are independent and done in parallel (see _sweep_). The results are written as tables with one line per output and factor.
+
+use crate::vaccination_model::delay_system::DelaySystem;
+use crate::vaccination_model::error::Result;
+use crate::vaccination_model::random::Rng;
+use crate::vaccination_model::sink::{Directory, Sink};
+use crate::vaccination_model::solver::Solver;
+use crate::vaccination_model::sweep::Sweep;
+
+/// An uncertain input with the range that is analysed
+#[derive(Clone, Debug, PartialEq)]
+pub struct Factor {
+	/// Name of the factor
+	pub name: String,
+	/// Lower bound of the range
+	pub lower: f64,
+	/// Upper bound of the range
+	pub upper: f64
+}
+
+impl Factor {
+	/// Factor _name_ varying between _lower_ and _upper_
+	pub fn new(name: &str, lower: f64, upper: f64) -> Factor {
+		Factor { name: name.to_string(), lower, upper }
+	}
+
+	/// Value at the position u (between 0 and 1) of the range
+	fn value(&self, u: f64) -> f64 {
+		self.lower + u*(self.upper - self.lower)
+	}
+}
+
+/// Outputs of a finished run commonly used in the analysis
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Output {
+	/// Deaths during the run
+	Deaths,
+	/// ICU occupancy integrated over the run (patient days)
+	ICUDays,
+	/// Freedom, i.e. the time average of the test-trace-and-isolate corrected $R_t$ allowed during the run
+	Freedom
+}
+
+impl Output {
+	/// Name of the output in the tables
+	pub fn name(&self) -> &'static str {
+		match self {
+			Output::Deaths => "deaths",
+			Output::ICUDays => "ICU_days",
+			Output::Freedom => "freedom"
+		}
+	}
+
+	/// Value of the output for the results of _solver_
+	pub fn evaluate<M: DelaySystem>(&self, solver: &Solver<M>) -> f64 {
+		let results = 1..solver.index+1;
+		match self {
+			Output::Deaths => results.map(|j| solver.model.deaths(&solver.states[j])).sum::<f64>()*solver.dt,
+			Output::ICUDays => results.map(|j| solver.model.ICU_occupancy(&solver.states[j])).sum::<f64>()*solver.dt,
+			Output::Freedom => {
+				let steps = solver.index.max(1) as f64;
+				results.map(|j| solver.model.raw_Rt_to_TTI_corrected(solver.Rt[j], solver.N_obs[j])).sum::<f64>()/steps
+			}
+		}
+	}
+}
+
+/// Settings of the elementary effects method
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Morris {
+	/// Number of trajectories (each needs k+1 runs for k factors)
+	pub trajectories: usize,
+	/// Number of levels of the grid in every factor (even, the step is $\Delta = p/(2(p-1))$ for p levels)
+	pub levels: usize,
+	/// Seed of the random number generator
+	pub seed: u64
+}
+
+/// Elementary effects statistics (index order: output, factor). The effects are measured per range of the factor.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MorrisResult {
+	/// Names of the factors
+	pub factors: Vec<String>,
+	/// Names of the outputs
+	pub outputs: Vec<String>,
+	/// Mean elementary effect $\mu$
+	pub mu: Vec<Vec<f64>>,
+	/// Mean absolute elementary effect $\mu^*$
+	pub mu_star: Vec<Vec<f64>>,
+	/// Standard deviation of the elementary effects $\sigma$
+	pub sigma: Vec<Vec<f64>>
+}
+
+/// Settings of the Sobol analysis
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sobol {
+	/// Number of base samples N
+	pub samples: usize,
+	/// Seed of the random number generator
+	pub seed: u64
+}
+
+/// Sobol indices (index order: output, factor)
+#[derive(Clone, Debug, PartialEq)]
+pub struct SobolResult {
+	/// Names of the factors
+	pub factors: Vec<String>,
+	/// Names of the outputs
+	pub outputs: Vec<String>,
+	/// First order indices $S_i$
+	pub first_order: Vec<Vec<f64>>,
+	/// Total order indices $S_{T,i}$
+	pub total_order: Vec<Vec<f64>>,
+	/// Variance of every output
+	pub variance: Vec<f64>
+}
+
+/// One trajectory of the elementary effects method
+struct Trajectory {
+	/// Order in which the factors are changed
+	order: Vec<usize>,
+	/// Step of every change (in units of the range)
+	steps: Vec<f64>,
+	/// The k+1 points of the trajectory in the unit hypercube
+	points: Vec<Vec<f64>>
+}
+
+impl Morris {
+	/// Method with _trajectories_ trajectories on a grid of 4 levels
+	pub fn new(trajectories: usize, seed: u64) -> Morris {
+		Morris { trajectories, levels: 4, seed }
+	}
+
+	/// Computes the elementary effects of the _factors_ on the _outputs_ (names of the values returned by _model_ for the factor values).
+	/// The runs are done in parallel with _sweep_.
+	pub fn analyze(&self, factors: &[Factor], outputs: &[&str], sweep: &Sweep, model: impl Fn(&[f64]) -> Vec<f64> + Sync) -> MorrisResult {
+		let k = factors.len();
+		let p = self.levels.max(2);
+		let delta = p as f64/(2.0*(p as f64 - 1.0));
+		let mut rng = Rng::new(self.seed);
+		let run = |u: &[f64]| model(&factors.iter().zip(u).map(|(factor, u)| factor.value(*u)).collect::<Vec<f64>>());
+
+		// Random start on the grid, random order of the factors and the points along every trajectory
+		let trajectories: Vec<Trajectory> = (0..self.trajectories).map(|_| {
+			let mut u: Vec<f64> = (0..k).map(|_| ((rng.uniform()*(p as f64)) as usize).min(p-1) as f64/(p as f64 - 1.0)).collect();
+			let mut order: Vec<usize> = (0..k).collect();
+			for i in (1..k).rev() {
+				let j = ((rng.uniform()*((i+1) as f64)) as usize).min(i);
+				order.swap(i, j);
+			}
+			let mut points = vec![u.clone()];
+			let steps = order.iter().map(|&i| {
+				let step = if u[i] + delta <= 1.0 + 1e-12 { delta } else { -delta };
+				u[i] += step;
+				points.push(u.clone());
+				step
+			}).collect();
+			Trajectory { order, steps, points }
+		}).collect();
+
+		// All runs in parallel
+		let points: Vec<&Vec<f64>> = trajectories.iter().flat_map(|trajectory| trajectory.points.iter()).collect();
+		let y = sweep.run(&points, |u| run(u));
+
+		// Elementary effects (index order: output, factor, trajectory)
+		let mut effects = vec![vec![Vec::with_capacity(self.trajectories); k]; outputs.len()];
+		for (t, trajectory) in trajectories.iter().enumerate() {
+			let y = &y[t*(k+1)..(t+1)*(k+1)];
+			for (m, (&i, step)) in trajectory.order.iter().zip(trajectory.steps.iter()).enumerate() {
+				for (effects, (new, old)) in effects.iter_mut().zip(y[m+1].iter().zip(y[m].iter())) {
+					effects[i].push((new - old)/step);
+				}
+			}
+		}
+
+		let statistic = |f: &dyn Fn(&[f64]) -> f64| -> Vec<Vec<f64>> {
+			effects.iter().map(|output| output.iter().map(|effects| f(effects)).collect()).collect()
+		};
+		let mean = |x: &[f64]| x.iter().sum::<f64>()/(x.len() as f64);
+		MorrisResult {
+			factors: factors.iter().map(|factor| factor.name.clone()).collect(),
+			outputs: outputs.iter().map(|name| name.to_string()).collect(),
+			mu: statistic(&|x| mean(x)),
+			mu_star: statistic(&|x| x.iter().map(|e| e.abs()).sum::<f64>()/(x.len() as f64)),
+			sigma: statistic(&|x| {
+				let m = mean(x);
+				(x.iter().map(|e| (e - m).powi(2)).sum::<f64>()/((x.len() as f64 - 1.0).max(1.0))).sqrt()
+			})
+		}
+	}
+}
+
+impl MorrisResult {
+	/// Writes the statistics to the file "./data/foldername/morris.data" (one line per output and factor)
+	pub fn write_to_disk(&self, foldername: &str) -> Result<()> {
+		self.write_to(&mut Directory::data(foldername))
+	}
+
+	/// Same as _write\_to\_disk_, but writes "morris.data" to the _sink_ (see the _sink_ submodule)
+	pub fn write_to(&self, sink: &mut dyn Sink) -> Result<()> {
+		let precision = 6;
+		sink.write_file("morris.data", &mut |file| {
+			writeln!(file, "output \t factor \t mu \t mu_star \t sigma")?;
+			for (o, output) in self.outputs.iter().enumerate() {
+				for (i, factor) in self.factors.iter().enumerate() {
+					writeln!(file, "{1} \t {2} \t {3:.0$} \t {4:.0$} \t {5:.0$}", precision, output, factor, self.mu[o][i], self.mu_star[o][i], self.sigma[o][i])?;
+				}
+			}
+			Ok(())
+		})
+	}
+}
+
+impl Sobol {
+	/// Computes the Sobol indices of the _factors_ for the _outputs_ (names of the values returned by _model_ for the factor values).
+	/// The runs are done in parallel with _sweep_.
+	pub fn analyze(&self, factors: &[Factor], outputs: &[&str], sweep: &Sweep, model: impl Fn(&[f64]) -> Vec<f64> + Sync) -> SobolResult {
+		let k = factors.len();
+		let n = self.samples;
+		let mut rng = Rng::new(self.seed);
+		let run = |u: &[f64]| model(&factors.iter().zip(u).map(|(factor, u)| factor.value(*u)).collect::<Vec<f64>>());
+
+		// Two independent sample matrices A and B
+		let A: Vec<Vec<f64>> = (0..n).map(|_| (0..k).map(|_| rng.uniform()).collect()).collect();
+		let B: Vec<Vec<f64>> = (0..n).map(|_| (0..k).map(|_| rng.uniform()).collect()).collect();
+
+		// Runs for A, B and A with column i replaced by the one of B (all in parallel)
+		let mut points: Vec<Vec<f64>> = A.iter().chain(B.iter()).cloned().collect();
+		for i in 0..k {
+			points.extend(A.iter().zip(B.iter()).map(|(a, b)| {
+				let mut u = a.clone();
+				u[i] = b[i];
+				u
+			}));
+		}
+		let y = sweep.run(&points, |u| run(u));
+		let y_A = &y[0..n];
+		let y_B = &y[n..2*n];
+		let y_AB: Vec<&[Vec<f64>]> = (0..k).map(|i| &y[(2+i)*n..(3+i)*n]).collect();
+
+		let mut result = SobolResult {
+			factors: factors.iter().map(|factor| factor.name.clone()).collect(),
+			outputs: outputs.iter().map(|name| name.to_string()).collect(),
+			first_order: Vec::with_capacity(outputs.len()),
+			total_order: Vec::with_capacity(outputs.len()),
+			variance: Vec::with_capacity(outputs.len())
+		};
+		for o in 0..outputs.len() {
+			let all = y_A.iter().chain(y_B.iter()).map(|y| y[o]);
+			let mean = all.clone().sum::<f64>()/(2*n) as f64;
+			let variance = all.map(|y| (y - mean).powi(2)).sum::<f64>()/((2*n) as f64 - 1.0);
+			result.first_order.push(y_AB.iter().map(|y_ABi| {
+				(0..n).map(|j| y_B[j][o]*(y_ABi[j][o] - y_A[j][o])).sum::<f64>()/(n as f64)/variance
+			}).collect());
+			result.total_order.push(y_AB.iter().map(|y_ABi| {
+				(0..n).map(|j| (y_A[j][o] - y_ABi[j][o]).powi(2)).sum::<f64>()/(2.0*n as f64)/variance
+			}).collect());
+			result.variance.push(variance);
+		}
+		result
+	}
+}
+
+impl SobolResult {
+	/// Writes the indices to the file "./data/foldername/sobol.data" (one line per output and factor)
+	pub fn write_to_disk(&self, foldername: &str) -> Result<()> {
+		self.write_to(&mut Directory::data(foldername))
+	}
+
+	/// Same as _write\_to\_disk_, but writes "sobol.data" to the _sink_ (see the _sink_ submodule)
+	pub fn write_to(&self, sink: &mut dyn Sink) -> Result<()> {
+		let precision = 6;
+		sink.write_file("sobol.data", &mut |file| {
+			writeln!(file, "output \t factor \t first_order \t total_order \t variance")?;
+			for (o, output) in self.outputs.iter().enumerate() {
+				for (i, factor) in self.factors.iter().enumerate() {
+					writeln!(file, "{1} \t {2} \t {3:.0$} \t {4:.0$} \t {5:.0$}", precision, output, factor, self.first_order[o][i], self.total_order[o][i], self.variance[o])?;
+				}
+			}
+			Ok(())
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::f64::consts::PI;
+
+	/// The Ishigami function $\sin x_1 + 7\sin^2 x_2 + 0.1 x_3^4 \sin x_1$ on $[-\pi, \pi]^3$
+	fn ishigami(x: &[f64]) -> Vec<f64> {
+		vec![x[0].sin() + 7.0*x[1].sin().powi(2) + 0.1*x[2].powi(4)*x[0].sin()]
+	}
+
+	fn factors() -> Vec<Factor> {
+		vec![Factor::new("x1", -PI, PI), Factor::new("x2", -PI, PI), Factor::new("x3", -PI, PI)]
+	}
+
+	#[test]
+	fn Sobol_indices_of_the_Ishigami_function() {
+		// Analytic indices with the variance $V = 49/8 + 0.1\pi^4/5 + 0.01\pi^8/18 + 1/2$
+		let V = 49.0/8.0 + 0.1*PI.powi(4)/5.0 + 0.01*PI.powi(8)/18.0 + 0.5;
+		let V1 = 0.5*(1.0 + 0.1*PI.powi(4)/5.0).powi(2);
+		let V2 = 49.0/8.0;
+		let V13 = 0.01*PI.powi(8)*(1.0/18.0 - 1.0/50.0);
+		let first_order = [V1/V, V2/V, 0.0];
+		let total_order = [(V1 + V13)/V, V2/V, V13/V];
+
+		let result = Sobol { samples: 20000, seed: 1 }.analyze(&factors(), &["y"], &Sweep::new(), ishigami);
+		assert!((result.variance[0] - V).abs() < 0.02*V);
+		for i in 0..3 {
+			assert!((result.first_order[0][i] - first_order[i]).abs() < 0.03, "S_{}: {} instead of {}", i+1, result.first_order[0][i], first_order[i]);
+			assert!((result.total_order[0][i] - total_order[i]).abs() < 0.03, "S_T{}: {} instead of {}", i+1, result.total_order[0][i], total_order[i]);
+		}
+	}
+
+	#[test]
+	fn Morris_effects_of_the_Ishigami_function() {
+		// On the grid of 4 levels ($x = \pm\pi, \pm\pi/3$) every step of $\Delta = 2/3$ changes $\sin^2 x_2$ by $\pm 3/4$ and $\sin x_1$ by
+		// $+\sqrt{3}/2$ (the effects are measured per range, i.e. divided by $\Delta$). $x_3^4$ changes by $\pm 80/81\pi^4$, which has an effect
+		// only if $\sin x_1 \neq 0$ (half of the levels).
+		let delta = 2.0/3.0;
+		let mu_star = [
+			3f64.sqrt()/2.0*(1.0 + 0.1*PI.powi(4)*(1.0 + 1.0/81.0)/2.0)/delta,
+			7.0*0.75/delta,
+			0.1*PI.powi(4)*80.0/81.0*3f64.sqrt()/4.0/delta
+		];
+
+		let result = Morris::new(200, 1).analyze(&factors(), &["y"], &Sweep::new(), ishigami);
+		assert!((result.mu_star[0][1] - mu_star[1]).abs() < 1e-12);
+		assert!((result.mu[0][0] - result.mu_star[0][0]).abs() < 1e-12);
+		for i in [0, 2] {
+			assert!((result.mu_star[0][i] - mu_star[i]).abs() < 0.1*mu_star[i], "mu*_{}: {} instead of {}", i+1, result.mu_star[0][i], mu_star[i]);
+		}
+	}
+}
@@ -0,0 +1,187 @@
+//! Destinations for the output files.
+//!
+//! Everything that writes results (_Solver::write\_to_, the ensembles, the posterior, ...) hands its files to a _Sink_ by name, e.g.
+//! "tHRt.data". Where they end up is up to the sink:
+//!
+//! - _Directory_: a directory anywhere on disk, created if needed, with a template for the file names (e.g. "run3\_{name}") and a policy
+//!   for existing files (_Overwrite_). The traditional "./data/foldername/" is _Directory::data_.
+//! - _Memory_: an in-memory buffer per file, e.g. to compare results in tests without touching the disk.
+
+use crate::vaccination_model::error::{with_path, Result};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// A destination for named output files
+pub trait Sink {
+	/// Writes the file _name_ with the contents produced by _contents_
+	fn write_file(&mut self, name: &str, contents: &mut dyn FnMut(&mut dyn Write) -> std::io::Result<()>) -> Result<()>;
+}
+
+/// What a _Directory_ does with files that exist already
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Overwrite {
+	/// Replace the existing file
+	Replace,
+	/// Keep the existing file and silently skip writing it
+	Skip,
+	/// Fail with an error (of kind _AlreadyExists_)
+	Fail
+}
+
+/// Writes the files into a directory, which is created (with all parents) when the first file is written.
+#[derive(Clone, Debug)]
+pub struct Directory {
+	/// The directory
+	pub path: PathBuf,
+	/// Template for the file names: "{name}" is replaced by the full name of the file, "{stem}" by the name without the extension
+	/// and "{extension}" by the extension (e.g. "tHRt" and "data" for "tHRt.data"). It may contain subdirectories (e.g. "{stem}/{name}"),
+	/// they are created as needed.
+	pub template: String,
+	/// What to do with existing files
+	pub overwrite: Overwrite
+}
+
+impl Directory {
+	/// Writes into the directory _path_ with unchanged file names, replacing existing files
+	pub fn new(path: impl AsRef<Path>) -> Directory {
+		Directory { path: path.as_ref().to_path_buf(), template: "{name}".to_string(), overwrite: Overwrite::Replace }
+	}
+
+	/// Writes into "./data/foldername/", where the tools and the python scripts expect the results
+	pub fn data(foldername: &str) -> Directory {
+		Directory::new(Path::new("data").join(foldername))
+	}
+
+	/// Sets the template for the file names
+	pub fn template(mut self, template: &str) -> Directory {
+		self.template = template.to_string();
+		self
+	}
+
+	/// Sets the policy for existing files
+	pub fn overwrite(mut self, overwrite: Overwrite) -> Directory {
+		self.overwrite = overwrite;
+		self
+	}
+
+	/// Path of the file _name_ after applying the template
+	pub fn file_path(&self, name: &str) -> PathBuf {
+		let (stem, extension) = match name.rfind('.') {
+			Some(dot) => (&name[..dot], &name[dot+1..]),
+			None => (name, "")
+		};
+		self.path.join(self.template.replace("{name}", name).replace("{stem}", stem).replace("{extension}", extension))
+	}
+}
+
+impl Sink for Directory {
+	fn write_file(&mut self, name: &str, contents: &mut dyn FnMut(&mut dyn Write) -> std::io::Result<()>) -> Result<()> {
+		let path = self.file_path(name);
+		let display = path.display().to_string();
+		let directory = path.parent().unwrap_or(&self.path);
+		std::fs::create_dir_all(directory).map_err(with_path(&directory.display().to_string()))?;
+
+		let mut options = std::fs::OpenOptions::new();
+		options.write(true);
+		match self.overwrite {
+			Overwrite::Replace => { options.create(true).truncate(true); },
+			Overwrite::Skip if path.exists() => return Ok(()),
+			Overwrite::Skip | Overwrite::Fail => { options.create_new(true); }
+		}
+		let mut writer = std::io::BufWriter::new(options.open(&path).map_err(with_path(&display))?);
+		contents(&mut writer).map_err(with_path(&display))?;
+		writer.flush().map_err(with_path(&display))?;
+		Ok(())
+	}
+}
+
+/// Keeps the files in memory (ordered by name). Writing a file again replaces it.
+#[derive(Clone, Debug, Default)]
+pub struct Memory {
+	/// Contents of the files by name
+	pub files: BTreeMap<String, Vec<u8>>
+}
+
+impl Memory {
+	/// Empty buffer
+	pub fn new() -> Memory {
+		Memory::default()
+	}
+
+	/// Contents of the file _name_ as text (None if it was not written or is not UTF-8, e.g. Parquet files)
+	pub fn text(&self, name: &str) -> Option<&str> {
+		self.files.get(name).and_then(|bytes| std::str::from_utf8(bytes).ok())
+	}
+}
+
+impl Sink for Memory {
+	fn write_file(&mut self, name: &str, contents: &mut dyn FnMut(&mut dyn Write) -> std::io::Result<()>) -> Result<()> {
+		let mut buffer = Vec::new();
+		contents(&mut buffer)?;
+		self.files.insert(name.to_string(), buffer);
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::vaccination_model::error::Error;
+
+	/// Empty directory for a test in the temporary directory
+	fn temporary(test: &str) -> PathBuf {
+		let path = std::env::temp_dir().join(format!("covid19_vaccine_model_sink_{}_{}", test, std::process::id()));
+		let _ = std::fs::remove_dir_all(&path);
+		path
+	}
+
+	fn write(sink: &mut dyn Sink, name: &str, text: &str) -> Result<()> {
+		sink.write_file(name, &mut |writer| writer.write_all(text.as_bytes()))
+	}
+
+	#[test]
+	fn directories_are_created_with_the_templated_subdirectories() {
+		let path = temporary("template");
+		let mut directory = Directory::new(path.join("nested")).template("{stem}/run3.{extension}");
+		assert_eq!(directory.file_path("tHRt.data"), path.join("nested").join("tHRt").join("run3.data"));
+
+		write(&mut directory, "tHRt.data", "t \t Rt").unwrap();
+		assert_eq!(std::fs::read_to_string(path.join("nested/tHRt/run3.data")).unwrap(), "t \t Rt");
+		std::fs::remove_dir_all(&path).unwrap();
+	}
+
+	#[test]
+	fn existing_files_are_replaced_skipped_or_rejected() {
+		let path = temporary("overwrite");
+		let file = path.join("model.params");
+		write(&mut Directory::new(&path), "model.params", "first").unwrap();
+		write(&mut Directory::new(&path), "model.params", "second").unwrap();
+		assert_eq!(std::fs::read_to_string(&file).unwrap(), "second");
+
+		write(&mut Directory::new(&path).overwrite(Overwrite::Skip), "model.params", "third").unwrap();
+		assert_eq!(std::fs::read_to_string(&file).unwrap(), "second");
+
+		match write(&mut Directory::new(&path).overwrite(Overwrite::Fail), "model.params", "third") {
+			Err(Error::Io(error)) => assert_eq!(error.kind(), std::io::ErrorKind::AlreadyExists),
+			other => panic!("Unexpected result {:?}", other)
+		}
+		assert_eq!(std::fs::read_to_string(&file).unwrap(), "second");
+
+		// New files are written with every policy
+		write(&mut Directory::new(&path).overwrite(Overwrite::Skip), "a.data", "a").unwrap();
+		write(&mut Directory::new(&path).overwrite(Overwrite::Fail), "b.data", "b").unwrap();
+		assert_eq!(std::fs::read_to_string(path.join("a.data")).unwrap() + &std::fs::read_to_string(path.join("b.data")).unwrap(), "ab");
+		std::fs::remove_dir_all(&path).unwrap();
+	}
+
+	#[test]
+	fn memory_keeps_the_last_version() {
+		let mut memory = Memory::new();
+		write(&mut memory, "tHRt.data", "first").unwrap();
+		write(&mut memory, "tHRt.data", "second").unwrap();
+		assert_eq!(memory.files.len(), 1);
+		assert_eq!(memory.text("tHRt.data"), Some("second"));
+		assert_eq!(memory.text("other.data"), None);
+	}
+}
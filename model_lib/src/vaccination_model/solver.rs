@@ -1,25 +1,58 @@
 //! Runge-Kutta 4 solver and PD control system for the model.
-
-use crate::vaccination_model::age_group_state_vector::add_vec;
-use crate::vaccination_model::age_group_state_vector::mul_vec;
-use crate::vaccination_model::age_group_state_vector::AgeGroupStateVector;
+//!
+//! The solver works with any model implementing _DelaySystem_, the vaccination model _Model_ is the default.
+
+use crate::vaccination_model::age_group_state_vector::{axpy, rk4_update};
+use crate::vaccination_model::age_group_state_vector::States;
+use crate::vaccination_model::controller::Controller;
+use crate::vaccination_model::delay_system::DelaySystem;
+use crate::vaccination_model::error::{Error, Result};
+use crate::vaccination_model::events::{Event, EventRecord};
 use crate::vaccination_model::model::Model;
-use std::io::Write;
+use crate::vaccination_model::observation::ObservationModel;
+use crate::vaccination_model::output::{write_tables, Format, Table, TableKind, Tsv};
+use crate::vaccination_model::schedule::Schedule;
+use crate::vaccination_model::sink::{Directory, Sink};
+use crate::vaccination_model::target::{Series, Target};
+
+/// Empirical observation kernel, i.e. the fraction of the infections from 0, 1, 2, ... days ago that is reported today.
+pub const OBSERVATION_KERNEL: [f64; 6] = [0.0, 0.0, 0.5, 0.3, 0.1, 0.1];
+
+/// Numerical method used to integrate the delay dif. eqs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Integrator {
+	/// Classical Runge-Kutta 4 with the fixed step size dt, looking up the delayed state at the closest grid point
+	RK4,
+	/// Adaptive Bogacki-Shampine 3(2) solver with dense output (see _run\_adaptive_), writing the results on the grid dt.
+	/// Tolerances are relative and absolute (in individuals).
+	Adaptive { rtol: f64, atol: f64 }
+}
 
+impl Integrator {
+	/// Adaptive solver with default tolerances that reproduce the RK4 results with dt=1e-2 well within plotting accuracy
+	pub fn adaptive() -> Integrator {
+		Integrator::Adaptive { rtol: 1e-6, atol: 1e-6 }
+	}
+}
 
-/// Runge-Kutta 4 solver and PD control system for the model.
-pub struct Solver {
+/// Runge-Kutta 4 solver and PD control system for a model (by default the vaccination model).
+#[derive(Clone)]
+pub struct Solver<M: DelaySystem = Model> {
 	/// Model parameters
-	pub model: Model,
+	pub model: M,
 
-	/// Step size for the RK4 solver
+	/// Step size for the RK4 solver (output grid spacing for the adaptive solver)
 	pub dt : f64,
+	/// Numerical method used by _controlled\_run_
+	pub integrator: Integrator,
+	/// How infections, ICU admissions and deaths are reported (used for N_obs and the control targets)
+	pub observation: ObservationModel,
 
 	// Initials
 	/// Initial time (t0=0 indicates the start of the vaccination programe, i.e. end of December 2020)
 	pub t0: f64,
 	/// Initial values for the system state (all compartments)
-	pub initials: Vec<AgeGroupStateVector>,
+	pub initials: Vec<M::State>,
 	/// Initial $R_t$ value
 	pub Rt_initial: f64,
 
@@ -37,182 +70,166 @@ pub struct Solver {
 	/// Result vector for the raw $R_t$ used in the dif. eqs. (not test-trace-and-isolate (TTI) corrected)
 	pub Rt: Vec<f64>,
 	/// Result vector for the system states (compartments)
-	pub states: Vec<Vec<AgeGroupStateVector>>,
+	pub states: States<M::State>,
 	/// Result vector for the total daily infections (not age resolved, not delayed)
 	pub N: Vec<f64>,
-	/// Result vector for the total daily infections (not age resolved, delayed by observation kernel K=\[0.0,0.0,0.5,0.3,0.1,0.1\])
+	/// Result vector for the observed daily infections (not age resolved, see _observation_; by default delayed by the observation kernel K=\[0.0,0.0,0.5,0.3,0.1,0.1\])
 	pub N_obs: Vec<f64>,
 
 	/// Current index (where we are in the result vectors)
-	pub index: usize
+	pub index: usize,
 
+	// Events
+	/// Events that are checked during _controlled\_run_ (see the _events_ submodule)
+	pub events: Vec<Event<M::State>>,
+	/// All occurrences of events so far
+	pub event_log: Vec<EventRecord<M::State>>
 }
 
-impl Solver {
+impl<M: DelaySystem> Solver<M> {
 	/// Initializes the solver. Clears all result arrays and writes initial values into them, sets index to 0. 
-	pub fn initialize(&mut self) {
+	///
+	/// Fails if the step size is not positive or the initial time lies after the end of the model inputs (see _check\_horizon_).
+	pub fn initialize(&mut self) -> Result<()> {
+		if !(self.dt > 0.0 && self.dt.is_finite()) {
+			return Err(Error::invalid("dt", self.dt, "a positive step size"));
+		}
+		self.check_until(self.t0)?;
 		self.time.clear();
 		self.time.push(self.t0);
 		self.states.clear();
-		self.states.push(self.initials.clone());
+		self.states.push(&self.initials);
 		self.Rt.clear();
 		self.Rt.push(self.Rt_initial);
-		for age_group_index in 0..self.initials.len() {
-			self.initials[age_group_index].h = self.Rt_initial*self.model.I_eff(age_group_index, &self.initials)*self.model.tau;
-		}
+		self.model.prepare_initials(self.Rt_initial, &mut self.initials);
 		self.N.clear();
 		self.N.push(self.model.N(&self.initials));
 		self.N_obs.clear();
 		self.N_obs.push(self.model.N(&self.initials));
 		self.index = 0;
+		self.event_log.clear();
+		Ok(())
+	}
+
+	/// Checks that the model inputs (e.g. the prepared vaccination rates of the vaccination model, see _DelaySystem::horizon_) suffice to
+	/// continue the current solution for a timespan T.
+	pub fn check_horizon(&self, T: f64) -> Result<()> {
+		self.check_until(self.time[self.index] + T)
 	}
 
-	/// Runs the simulation for a timespan T. Recieves a series of control problems seperated by change points.
+	/// Fails if the model inputs end before the time t\_end
+	pub(crate) fn check_until(&self, t_end: f64) -> Result<()> {
+		let end = self.model.horizon();
+		if t_end < end {
+			Ok(())
+		} else {
+			Err(Error::VaccinationsExhausted { t: t_end, end })
+		}
+	}
+
+	/// Runs the simulation for a timespan T following a _Schedule_ of control phases.
 	/// 
 	/// # How to use
-	/// - solver.controlled_run(T, &[(t0, min0, max0, max_slope0, control0, aim0), (t1, min1, max1, max_slope1, control1, aim1), ...]);
+	/// - let phase0 = Phase::new(Target::ObservedCases, aim0).until_time(t0).Rt_range(min0, max0).max_slope(max_slope0);
+	/// - let phase1 = Phase::new(Target::ICUOccupancy, aim1).Rt_range(min1, max1).max_slope(max_slope1);
+	/// - solver.controlled_run(T, &Schedule::new(vec![phase0, phase1])?);
 	///
-	/// runs the solver for a timespan _T_. In the beginning the control approach _control0_ is chosen to aim at set point _aim0_. With a minimal ((test-trace-and-isolate (TTI) corrected) $R_t$
-	/// _min0_ and a maximal value _max0_. $R_t$ is allowed to increase only with a maximal slope _max\_slope0_. The system is run until time _t0_ is reached and the parameters are updated
-	/// to _min1, max1, max_slope1, control1, aim1_.
-	/// 
-	/// - control=0 means we aim at stable daily infections given by the set target value aim.
-	/// - control=1 means we aim at stable ICU occupancy given by the set target value aim.
-	/// - control=2 is the same as control=1 but it integrates the time where ICU is at the capacity limit (i.e. 70% close to aim) and returns that time.
-	pub fn controlled_run(&mut self, T: f64, change_points: &[(f64, f64, f64, f64, usize, f64)]) -> f64 {
+	/// runs the solver for a timespan _T_. In the beginning the control system aims at daily (observed) infections _aim0_ with a minimal (test-trace-and-isolate (TTI) corrected) $R_t$
+	/// _min0_ and a maximal value _max0_. $R_t$ is allowed to change only with a maximal slope _max\_slope0_. The phase ends at time _t0_ (or when its condition is met,
+	/// see _Phase::until\_condition_) and the next phase aims at the ICU occupancy _aim1_ until the end of the run.
+	///
+	/// If the active phase measures the ICU duration (see _Phase::measure\_ICU\_duration_), the time where ICU is at the capacity limit (i.e. 70% close to the set point)
+	/// is integrated and returned, the run stops when the ICUs are emptying.
+	///
+	/// After every day the registered _events_ are checked, they can stop the run early or alter the state and $R_t$.
+	///
+	/// Fails if the run (including the previews of the controllers) reaches beyond the model inputs (see _check\_horizon_) or if the controller
+	/// drives $R_t$ to a non-finite value, the results up to the failing day are kept.
+	pub fn controlled_run(&mut self, T: f64, schedule: &Schedule<M::State>) -> Result<f64> {
+		let phases = schedule.phases();
+		let mut controllers: Vec<Box<dyn Controller>> = phases.iter().map(|phase| phase.controller.clone()).collect();
 		let bin_length = 1.0;
 		let N_bins = (T/bin_length) as usize;
-		let preview_length = 14.0;
-		let mut kp: f64;
-		let mut kd: f64;
-
-		let kernel:[f64; 6] = [0.0, 0.0, 0.5, 0.3, 0.1, 0.1];
 
 		let t0 = self.time[self.index];
 		let mut t = t0;
 
-		let N = (preview_length/self.dt) as usize;
+		let mut N = (controllers[0].preview_length()/self.dt) as usize;
 		let mut preview_time: Vec<f64> = Vec::with_capacity(N);
 		let mut preview_Rt: Vec<f64> = Vec::with_capacity(N);
-		let mut preview_states: Vec<Vec<AgeGroupStateVector>> = Vec::with_capacity(N);
+		let mut preview_states = States::with_capacity(self.initials.len(), N);
 		let mut preview_N: Vec<f64> = Vec::with_capacity(N);
 		let mut R = self.Rt[self.index];
 
 		let mut change_index = 0;
-		let (mut t_change, mut min_Rt, mut max_Rt, mut max_slope, mut control, mut aim) = change_points[change_index];
+		let mut phase = &phases[change_index];
 
 		let mut ICU_integral:f64 = 0.0;
 
-		// Run the simulation for every day with PD control systems in place.
+		// Run the simulation for every day with control systems in place.
 		for bin in 1..N_bins+1 {
 
 			// Run for the preview length
-			self.run_rk4(preview_length, &mut preview_time, &mut preview_Rt, &mut preview_states, &mut preview_N,
-										&self.time, &self.Rt, &self.states, R);
+			let preview_length = controllers[change_index].preview_length();
+			N = (preview_length/self.dt) as usize;
+			self.integrate(preview_length, &mut preview_time, &mut preview_Rt, &mut preview_states, &mut preview_N,
+										&self.time, &self.Rt, &self.states, R)?;
 
 			// Append relevant slices
 			let bin_index = locate_position(&preview_time, t0 + (bin as f64)*bin_length);
 			self.Rt.extend_from_slice(&preview_Rt[0..bin_index]);
-			self.states.extend_from_slice(&preview_states[0..bin_index]);
+			self.states.extend_from(&preview_states, 0..bin_index);
 			self.time.extend_from_slice(&preview_time[0..bin_index]);
 			self.N.extend_from_slice(&preview_N[0..bin_index]);
+			let previous_index = self.index;
 			self.index += bin_index;
 
 			// Calculate N_obs
-			let mut Nobs:f64 = 0.0;
-			let one_day = (1./self.dt) as usize;
-			for day in 0..kernel.len() {
-				if self.index >= one_day*(day+1) {
-					Nobs += &self.N[self.index-one_day*(day+1)..self.index-one_day*day].iter().sum::<f64>()*self.dt * kernel[day];
-				} else {
-					Nobs += &self.N[0] * kernel[day];
-				}
-			}
-			self.N_obs.extend_from_slice(&vec![Nobs; one_day]);
+			let Nobs = self.observed_cases();
+			self.N_obs.resize((self.N_obs.len() + (1./self.dt) as usize).max(self.index+1), Nobs);
 
-			// Determine errors (\Delta in the manuscript), error changes and control parameters k_d and k_p for the given control approach. 
+			// Determine errors (\Delta in the manuscript) and error changes for the given control approach.
 			let (error, error_change): (f64, f64);
 
-			if control == 1 || control == 2 {
-				error = (self.model.ICU_occupancy(&preview_states[N-1])-aim)/aim;
-				error_change = (error - (self.model.ICU_occupancy(&preview_states[N-1-1])-aim)/aim)/(preview_time[N-1]-preview_time[N-1-1]);
-
-
-				let error_tolerance = 0.1;
-				let change_tolerance = 0.1;
-				if error.abs() < error_tolerance && error_change.abs() < change_tolerance {
-					//preview_length = 10.0;
-					kp = 0.3e-0;
-					kd = 1.5e1;
-				} else {
-					//preview_length = 10.0;
-					kp = 0.3e-0;
-					kd = 0.9e1;
-				}
-			} else {
-				// Calculate the preview N_obs
-				let mut Nobs:f64 = 0.0;
-				let one_day = (1./self.dt) as usize;
-				for day in 0..kernel.len() {
-					if N-1 >= one_day*(day+1) {
-						Nobs += &preview_N[N-1-one_day*(day+1)..N-1-one_day*day].iter().sum::<f64>()*self.dt * kernel[day];
-					} else {
-						Nobs += &preview_N[0] * kernel[day];	// not quite correct, since we have the history in self.N
-					}
-				}
-				let mut Nobs_before:f64 = 0.0;
-				let one_day = (1./self.dt) as usize;
-				for day in 0..kernel.len() {
-					if N-1-1 >= one_day*(day+1) {
-						Nobs_before += &preview_N[N-1-1-one_day*(day+1)..N-1-1-one_day*day].iter().sum::<f64>()*self.dt * kernel[day];
-					} else {
-						Nobs_before += &preview_N[0] * kernel[day];	// not quite correct, since we have the history in self.N
-					}
-				}
-				error = (Nobs - aim)/aim;
-				error_change = (error - (Nobs_before - aim)/aim)/(preview_time[N-1]-preview_time[N-1-1]);
-
-				let error_tolerance = 0.05;
-				let change_tolerance = 0.1;
-				if error.abs() < error_tolerance && error_change.abs() < change_tolerance {
-					//preview_length = 10.0;
-					kp = 6e-2;
-					kd = 3e0;
-				} else {
-					//preview_length = 10.0;
-					kp = 6e-2;
-					kd = 1.2e0;
-				}
-
-			}
+			// The observables are computed from the results up to the start of the preview continued by the preview
+			let aim = phase.set_point;
+			let series = Series { states: &self.states, N: &self.N, end: previous_index, preview: Some((&preview_states, &preview_N)), t0: self.time[0], dt: self.dt };
+			let observed = phase.target.observe(&self.model, &self.observation, &series, previous_index+N);
+			let observed_before = phase.target.observe(&self.model, &self.observation, &series, previous_index+N-1);
+			error = (observed - aim)/aim;
+			error_change = (error - (observed_before - aim)/aim)/(preview_time[N-1]-preview_time[N-1-1]);
 
 			// Convert the minimal, maximal and maximal slope values for changing Rt from the test-trace-and-isolate (TTI) corrected to the raw Rt
-			let min_raw_Rt = self.model.raw_Rt_from_TTI_corrected(min_Rt, self.N_obs[self.index]);
-			let max_raw_Rt = self.model.raw_Rt_from_TTI_corrected(max_Rt, self.N_obs[self.index]);
-			let max_raw_slope = self.model.raw_Rt_from_TTI_corrected(max_slope, self.N_obs[self.index]) - self.model.raw_Rt_from_TTI_corrected(0.0, self.N_obs[self.index]);
+			let min_raw_Rt = self.model.raw_Rt_from_TTI_corrected(phase.min_Rt, self.N_obs[self.index]);
+			let max_raw_Rt = self.model.raw_Rt_from_TTI_corrected(phase.max_Rt, self.N_obs[self.index]);
+			let max_raw_slope = self.model.raw_Rt_from_TTI_corrected(phase.max_slope, self.N_obs[self.index]) - self.model.raw_Rt_from_TTI_corrected(0.0, self.N_obs[self.index]);
 			
 			// Adjust Rt
-			R = (R- (bin_length*(kp*error + kd*error_change)).min(max_raw_slope).max(-max_raw_slope)).max(min_raw_Rt).min(max_raw_Rt);
+			let adjustment = controllers[change_index].adjustment(error, error_change, bin_length);
+			R = (R- adjustment.min(max_raw_slope).max(-max_raw_slope)).max(min_raw_Rt).min(max_raw_Rt);
+			if !R.is_finite() {
+				return Err(Error::invalid("Rt", R, "a finite value (limit the adjustments of the controller or the Rt range of the phase)"));
+			}
 
-			// Check if we reached a change point where we change the control appproach
+			// Handle events in the new results
+			if self.handle_events(previous_index, self.index, &mut R) {
+				return Ok(ICU_integral/phase.set_point);
+			}
+
+			// Check if the phase ended and we change the control appproach
 			t += bin_length;
-			if t >= t_change && change_index < change_points.len()-1 {
+			if change_index < phases.len()-1 && phase.until.reached(t, &self.states[self.index]) {
 				change_index += 1;
-				t_change = change_points[change_index].0;
-				min_Rt = change_points[change_index].1;
-				max_Rt = change_points[change_index].2;
-				max_slope = change_points[change_index].3;
-				control = change_points[change_index].4;
-				aim = change_points[change_index].5;
-			} 
-
-			// Control approach 2 is identical to 1 (fix ICU occupancy), but it stops the simulation if the ICUs are emptying to save computation time.
-			if control == 2 {
+				phase = &phases[change_index];
+			}
+
+			// Measuring the ICU duration does not change the control, but it stops the simulation if the ICUs are emptying to save computation time.
+			if phase.measure_ICU_duration {
 				let occupancy_now = self.model.ICU_occupancy(&self.states[self.index]);
-				if occupancy_now > 0.7*aim {
+				if occupancy_now > 0.7*phase.set_point {
 					ICU_integral += occupancy_now;
 				} else if ICU_integral > 0.0 {
-					return ICU_integral/aim;
+					return Ok(ICU_integral/phase.set_point);
 				}
 			}
 
@@ -223,23 +240,87 @@ impl Solver {
 			preview_N.clear();
 		}
 
-		return ICU_integral/aim
+		Ok(ICU_integral/phase.set_point)
+	}
+
+	/// Continues the current solution for a timespan T (in whole days) at the constant raw $R_t$ R without control and events,
+	/// and appends the results to the result vectors like _controlled\_run_ does. Fails like _controlled\_run_.
+	pub fn run_constant(&mut self, T: f64, R: f64) -> Result<()> {
+		let N = (1./self.dt) as usize;
+		let mut day_time: Vec<f64> = Vec::with_capacity(N);
+		let mut day_Rt: Vec<f64> = Vec::with_capacity(N);
+		let mut day_states = States::with_capacity(self.initials.len(), N);
+		let mut day_N: Vec<f64> = Vec::with_capacity(N);
+
+		for _day in 0..T as usize {
+			self.integrate(1.0, &mut day_time, &mut day_Rt, &mut day_states, &mut day_N, &self.time, &self.Rt, &self.states, R)?;
+			self.time.extend_from_slice(&day_time);
+			self.Rt.extend_from_slice(&day_Rt);
+			self.states.extend_from(&day_states, 0..day_states.len());
+			self.N.extend_from_slice(&day_N);
+			self.index += day_time.len();
+
+			let Nobs = self.observed_cases();
+			self.N_obs.resize(self.index+1, Nobs);
+
+			day_time.clear();
+			day_Rt.clear();
+			day_states.clear();
+			day_N.clear();
+		}
+		Ok(())
+	}
+
+	/// Applies the observation model to the daily infections in the history up to the current index and returns the observed daily cases.
+	pub(crate) fn observed_cases(&self) -> f64 {
+		let series = Series { states: &self.states, N: &self.N, end: self.index, preview: None, t0: self.time[0], dt: self.dt };
+		Target::ObservedCases.observe(&self.model, &self.observation, &series, self.index)
+	}
+
+	/// Solves the system of delay diff. eqs. for a timespan T with the integrator selected in _integrator_ (see _run\_rk4_ and _run\_adaptive_).
+	#[allow(clippy::too_many_arguments)]
+	pub fn integrate(&self, T: f64, time: &mut Vec<f64>, Rt: &mut Vec<f64>, states: &mut States<M::State>, N: &mut Vec<f64>,
+									  time_history: &[f64], Rt_history: &[f64], states_history: &States<M::State>, R: f64) -> Result<usize> {
+		self.integrate_with(self.integrator, T, time, Rt, states, N, time_history, Rt_history, states_history, R)
+	}
+
+	/// Same as _integrate_, but with the given integrator instead of the selected one (e.g. a cheaper one for predictions).
+	#[allow(clippy::too_many_arguments)]
+	pub fn integrate_with(&self, integrator: Integrator, T: f64, time: &mut Vec<f64>, Rt: &mut Vec<f64>, states: &mut States<M::State>, N: &mut Vec<f64>,
+									  time_history: &[f64], Rt_history: &[f64], states_history: &States<M::State>, R: f64) -> Result<usize> {
+		match integrator {
+			Integrator::RK4 => self.run_rk4(T, time, Rt, states, N, time_history, Rt_history, states_history, R),
+			Integrator::Adaptive { rtol, atol } => self.run_adaptive(T, time, Rt, states, N, time_history, Rt_history, states_history, R, rtol, atol)
+		}
 	}
 
 	/// Solves the system of delay diff. eqs. for a timespan T using Runge-Kutta 4. Saves the results in time, H, Rt, states and N. Uses the respective history arrays if the delays reach out of the current simulation.
 	/// 
-	/// Returns the index in the result arrays in the end for easy access.
-	pub fn run_rk4(&self, T: f64, time: &mut Vec<f64>, Rt: &mut Vec<f64>, states: &mut Vec<Vec<AgeGroupStateVector>>, N: &mut Vec<f64>,
-									  time_history: &Vec<f64>, Rt_history: &Vec<f64>, states_history: &Vec<Vec<AgeGroupStateVector>>, R: f64) -> usize {
+	/// All intermediate states and slopes live in a few scratch vectors allocated once per call, so the integration loop itself does not allocate
+	/// (apart from appending to the result vectors).
+	///
+	/// Returns the index in the result arrays in the end for easy access. Fails if the timespan reaches beyond the model inputs (see _check\_horizon_).
+	#[allow(clippy::too_many_arguments)]
+	pub fn run_rk4(&self, T: f64, time: &mut Vec<f64>, Rt: &mut Vec<f64>, states: &mut States<M::State>, N: &mut Vec<f64>,
+									  time_history: &[f64], Rt_history: &[f64], states_history: &States<M::State>, R: f64) -> Result<usize> {
 
 		// Preparations, initialise running variables and indices
 		let history_index = time_history.len()-1;
 		let t0 = time_history[history_index];
+		self.check_until(t0 + T)?;
 		let mut t = t0;
-		let mut state = states_history[history_index].clone();
-
-		let index_delay:usize = (self.model.tau/self.dt) as usize;
-		let mut delayed_state: &Vec<AgeGroupStateVector>;
+		let mut state = states_history[history_index].to_vec();
+
+		// Scratch space for the slopes and the intermediate states
+		let mut s1 = vec![M::State::default(); state.len()];
+		let mut s2 = s1.clone();
+		let mut s3 = s1.clone();
+		let mut s4 = s1.clone();
+		let mut intermediate = s1.clone();
+		let mut delayed = M::Delayed::default();
+
+		let index_delay:usize = (self.model.delay()/self.dt) as usize;
+		let mut delayed_state: &[M::State];
 		let mut delayed_R: f64;
 
 		// Run for a time T
@@ -258,18 +339,22 @@ impl Solver {
 				delayed_R = Rt[index-index_delay];
 			}
 
-			// Runge Kutta 4
-			let s1 = self.model.slopes(t, R, &state, delayed_R, delayed_state);
-			let s2 = self.model.slopes(t+0.5*self.dt, R, &add_vec(mul_vec(&s1, 0.5*self.dt), &state), delayed_R, delayed_state);
-			let s3 = self.model.slopes(t+0.5*self.dt, R, &add_vec(mul_vec(&s2, 0.5*self.dt), &state), delayed_R, delayed_state);
-			let s4 = self.model.slopes(t+self.dt, R, &add_vec(mul_vec(&s3, self.dt), &state), delayed_R, delayed_state);
-
-			state = add_vec(state, &mul_vec(&add_vec(add_vec(s1, &s4), &mul_vec(&add_vec(s2, &s3), 2.0)), self.dt/6.0));
+			// Runge Kutta 4 (the delayed state is the same for all four slopes)
+			self.model.delayed_terms(delayed_R, delayed_state, &mut delayed);
+			self.model.slopes_into(t, R, &state, delayed_state, &delayed, &mut s1);
+			axpy(&mut intermediate, &state, 0.5*self.dt, &s1);
+			self.model.slopes_into(t+0.5*self.dt, R, &intermediate, delayed_state, &delayed, &mut s2);
+			axpy(&mut intermediate, &state, 0.5*self.dt, &s2);
+			self.model.slopes_into(t+0.5*self.dt, R, &intermediate, delayed_state, &delayed, &mut s3);
+			axpy(&mut intermediate, &state, self.dt, &s3);
+			self.model.slopes_into(t+self.dt, R, &intermediate, delayed_state, &delayed, &mut s4);
+
+			rk4_update(&mut state, &s1, &s2, &s3, &s4, self.dt);
 			t += self.dt;
 
 			// Save results
 			Rt.push(R);
-			states.push(state.clone());
+			states.push(&state);
 			time.push(t);
 
 			// Calculate the daily case numbers
@@ -277,77 +362,150 @@ impl Solver {
 		}
 
 		// Return new end index
-		(T/self.dt) as usize - 1
+		Ok((T/self.dt) as usize - 1)
 	}
 
-	/// Writes the results to a folder "./data/foldername/". To reduce file size it only writes every _write\_every_ value of the results.
-	///
-	/// Panics if write or file creation failed somewhere, i.e. if the directory does not exist.
-	pub fn write_to_disk(&self, foldername: &str, write_every: usize) -> std::io::Result<()>{
-		let precision = 6;
-		// Write model parameters
-		let mut filename = format!("data/{}/model.params", foldername);
-		let mut file = std::fs::File::create(filename).expect("create failed");
-
-		file.write_all("M \t beta \t tau \t tau_vacc \t random_vacc \t ICU_capacity \t TTI_capacity \t N_TTI \t N_test_eff \t N_test_ineff \t N_no_test\n".as_bytes()).expect("write failed");
-		file.write_all(format!("{1:.0$} \t {2:.0$} \t {3:.0$} \t {4:.0$} \t {5:.0$} \t {6:.0$} \t {7:.0$} \t {8:.0$} \t {9:.0$} \t {10:.0$} \t {11:.0$} \t {12:.0$} \t {13:.0$}\n", 
-					precision, self.model.M, self.model.eta0, self.model.tau, self.model.tau_vacc, self.model.random_vacc, self.model.kappa0, self.model.N_TTI, self.model.N_test_eff, self.model.N_test_ineff, self.model.N_no_test, self.model.sigma[0], self.model.sigma[1], self.model.sigma[2]).as_bytes()).expect("write failed");
-
-
-		// Write age group parameters 
-		filename = format!("data/{}/age_groups.params", foldername);
-		file = std::fs::File::create(filename).expect("create failed");
-
-		// (ignore chi and phi columns, parameters removed from the final model)
-		file.write_all("name \t M \t influx \t chi \t phi0 \t phi1 \t phi2 \t rho \t gamma0 \t gamma1 \t gamma2 \t gamma^ICU0 \t gamma^ICU1 \t gamma^ICU2 \t alpha0 \t alpha1 \t alpha2 \t delta 0\t delta1 \t delta2 \t delta^ICU0 \t delta^ICU1 \t delta^ICU2 \t compliance \t vacc_phase \n".as_bytes()).expect("write failed");
-		for ag in self.model.age_groups.iter() {
-			file.write_all(format!("{1} \t {2:.0$} \t {3:.0$} \t {4:.0$} \t {5:.0$} \t {6:.0$} \t {7:.0$} \t {8:.0$} \t {9:.0$} \t {10:.0$} \t {11:.0$} \t {12:.0$} \t {13:.0$} \t {14:.0$} \t {15:.0$} \t {16:.0$} \t {17:.0$} \t {18:.0$} \t {19:.0$} \t {20:.0$} \t {21:.0$} \t {22:.0$} \t {23:.0$}\n", 
-					precision, ag.name, ag.M, ag.influx, 0.0/*ag.chi*/, 0.0/*ag.phi[0]*/, 0.0/*ag.phi[1]*/, 0.0/*ag.phi[2]*/, ag.rho, ag.gamma_I[0], ag.gamma_I[1], ag.gamma_I[2], 
-					ag.gamma_ICU[0], ag.gamma_ICU[1], ag.gamma_ICU[2], ag.alpha[0], ag.alpha[1], ag.alpha[2], ag.delta_I[0], ag.delta_I[1], ag.delta_I[2], ag.delta_ICU[0], ag.delta_ICU[1], ag.delta_ICU[2]).as_bytes()).expect("write failed");
-		}
+	/// Writes the results to a folder "./data/foldername/" in the original tab separated format (see _write\_to\_disk\_as_).
+	pub fn write_to_disk(&self, foldername: &str, write_every: usize) -> Result<()>{
+		self.write_to_disk_as(foldername, write_every, &Tsv::default())
+	}
+
+	/// Writes the results (see _tables_) in the given format to a folder "./data/foldername/" (created if needed), one file per table.
+	pub fn write_to_disk_as(&self, foldername: &str, write_every: usize, format: &dyn Format) -> Result<()> {
+		self.write_to(&mut Directory::data(foldername), write_every, format)
+	}
+
+	/// Writes the results (see _tables_) in the given format to the _sink_, e.g. a _Directory_ or a _Memory_ buffer, one file per table.
+	pub fn write_to(&self, sink: &mut dyn Sink, write_every: usize, format: &dyn Format) -> Result<()> {
+		write_tables(sink, &self.tables(write_every), format)
+	}
+
+	/// The results as tables (see _output_): the model parameters (provided by the model), "tHRt" with the time, $R_t$, N, $N_{obs}$ and the TTI
+	/// corrected $R_t$ and "{name}\_age\_group" with the columns provided by the model (see _DelaySystem_) for every block. To reduce the size
+	/// only every _write\_every_ value of the results is kept. The first row of the block tables contains the initial values, i.e. it precedes
+	/// the first row of the results (see _DelaySystem::state\_description_).
+	pub fn tables(&self, write_every: usize) -> Vec<Table> {
+		let mut tables = self.model.parameter_tables();
 
-		// Write time, H, Rt data
 		let Rt_TTI_corrected: Vec<f64> = self.Rt.iter().zip(self.N_obs.iter()).map(|n| self.model.raw_Rt_to_TTI_corrected(*n.0, *n.1)).collect();
-		let to_write = self.time.iter().step_by(write_every).zip(self.Rt.iter().step_by(write_every)).zip(self.N.iter().step_by(write_every)).zip(self.N_obs.iter().step_by(write_every)).zip(Rt_TTI_corrected.iter().step_by(write_every)).map(|n| format!("{1:.0$} \t {2:.0$} \t {3:.0$} \t {4:.0$} \t {5:.0$}", precision, n.0.0.0.0, n.0.0.0.1, n.0.0.1, n.0.1, n.1)).collect::<Vec<String>>().join("\n");
-		filename = format!("data/{}/tHRt.data", foldername);
-		file = std::fs::File::create(filename).expect("create failed");
-		file.write_all("t \t Rt \t N \t N_obs \t Rt_TTI_corrected\n".as_bytes()).expect("write failed");
-		writeln!(file, "{}", to_write)?;
-		
-		// Write age group state vector data
-		let N_age_groups = self.model.age_groups.len();
-		let N = self.states.len();
-		let mut data: Vec<Vec<String>> = Vec::with_capacity(N_age_groups);
-
-		for i in 0..N_age_groups {
-			data.push(Vec::with_capacity(N));
-
-			let (vaccinated1, vaccinated2) = self.model.vaccinated_between(0.0, self.time[0], i);
-			data[i].push(format!("{1} \t {2:.0$} \t {3:.0$}", precision, self.states[0][i], vaccinated1, vaccinated2));
-			
-			for j in (0..N).step_by(write_every) {
-				//data[i].push(format!("{}", self.states[j][i]));
-			
-				data[i].push(format!("{1} \t {2:.0$} \t {3:.0$}", precision, self.states[j][i], self.model.vaccinations_per_week_dose1[(self.time[j]/7.0).floor() as usize][i]/7., self.model.vaccinations_per_week_dose2[(self.time[j]/7.0).floor() as usize][i]/7.));
-			}
+		let every = |x: &[f64]| x.iter().step_by(write_every).copied().collect();
+		tables.push(Table::new("tHRt", TableKind::Series)
+			.float("t", every(&self.time))
+			.float("Rt", every(&self.Rt))
+			.float("N", every(&self.N))
+			.float("N_obs", every(&self.N_obs))
+			.float("Rt_TTI_corrected", every(&Rt_TTI_corrected)));
+
+		let columns = self.model.state_columns();
+		for (i, name) in self.model.block_names().iter().enumerate() {
+			let mut rows = vec![self.model.state_values(self.time[0], i, &self.states[0][i], true)];
+			rows.extend((0..self.states.len()).step_by(write_every).map(|j| self.model.state_values(self.time[j], i, &self.states[j][i], false)));
+			tables.push(Table::from_rows(&format!("{}_age_group", name), TableKind::Series, &columns, &rows).describe(&self.model.state_description()));
 		}
-		for i in 0..N_age_groups {
-			filename = format!("data/{}/{}_age_group.data", foldername, self.model.age_groups[i].name);
-			file = std::fs::File::create(filename).expect("create failed");
-			file.write_all("S0 \t S1 \t S2 \t V1 \t V2 \t E0 \t E1 \t E2 \t I0 \t I1 \t I2 \t ICU0 \t ICU1 \t ICU2 \t D \t R0 \t R1 \t R2 \t h \t f1 \t f2 (first line is initial values + initially vaccianted)\n".as_bytes()).expect("write failed");
-			writeln!(file, "{}", data[i].join("\n"))?;
-		}
-		Ok(())
+		tables
 	}
 
 }
 
 /// Locates the largest non-negative integer i with x[i] <= x0. If x0 < x[j] for all j, it outputs i=0 anyway. Assumes x is sorted.
-fn locate_position(x:&Vec<f64>, x0: f64) -> usize {
-	let len = x.len();
-	if len == 0 {println!("help")}
-	let mut i:usize = len-1;
-	while i > 0 && x[i] > x0 {i -= 1;}
-	i
+pub(crate) fn locate_position(x:&[f64], x0: f64) -> usize {
+	x.iter().rposition(|&x| x <= x0).unwrap_or(0)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+	use super::*;
+	use crate::vaccination_model::age_group_state_vector::AgeGroupStateVector;
+	use crate::vaccination_model::model::AgeGroup;
+
+	fn age_group(name: &str, M: f64, phase: i32) -> AgeGroup {
+		AgeGroup {
+			name: name.to_string(),
+			M,
+			influx: 1e-5*M,
+			rho: 0.25,
+			gamma_I: [0.09, 0., 0.],
+			gamma_ICU: [0.085, 0., 0.],
+			alpha: [0.005, 0., 0.],
+			delta_I: [0.002, 0., 0.],
+			delta_ICU: [0.05, 0., 0.],
+			eligible_fraction: 1.0,
+			min_uptake: 0.5,
+			max_uptake: 0.9,
+			phase
+		}
+	}
+
+	/// Solver (not yet initialized) for a small model with two age groups and a total population M, with vaccination rates for 30 weeks
+	pub(crate) fn small_solver(M: f64) -> Solver {
+		let mut model = Model {
+			age_groups: Vec::new(),
+			M: 0.0,
+			tau: 7.0,
+			eta0: 0.5,
+			sigma: [1.0, 0.5, 0.5],
+			kappa0: 0.8,
+			tau_vacc: 4,
+			vaccinations_per_week_dose1: Vec::new(),
+			vaccinations_per_week_dose2: Vec::new(),
+			random_vacc: 0.35,
+			N_TTI: 20e-6*M,
+			N_test_eff: 100e-6*M,
+			N_test_ineff: 500e-6*M,
+			N_no_test: 1e-2*M,
+			contacts: vec![vec![0.6, 0.2], vec![0.2, 0.4]]
+		};
+		model.add_age_group(age_group("60+", 0.3*M, 0));
+		model.add_age_group(age_group("0-59", 0.7*M, 1));
+		model.initialize().unwrap();
+		model.prepare_vaccination_rates(30, 0.7).unwrap();
+
+		let initials = model.age_groups.iter()
+			.map(|group| AgeGroupStateVector::create_initial(group.M, 0.1, 0.0, 0.0, model.eta0, 0.0, 0.0, 2e-3*group.M, 1e-4*group.M))
+			.collect();
+		Solver {
+			model,
+			dt: 0.01,
+			integrator: Integrator::RK4,
+			observation: ObservationModel::default(),
+			t0: 0.0,
+			initials,
+			Rt_initial: 1.0,
+			time: Vec::new(),
+			Rt: Vec::new(),
+			states: States::with_capacity(2, 0),
+			N: Vec::new(),
+			N_obs: Vec::new(),
+			index: 0,
+			events: Vec::new(),
+			event_log: Vec::new()
+		}
+	}
 
+	/// Daily infections N of 60 days at $R_t$=1.3 followed by 60 days at $R_t$=0.8, starting from the initial state
+	fn wave(solver: &Solver) -> Vec<f64> {
+		let (mut time, mut Rt, mut states, mut N) = (solver.time.clone(), solver.Rt.clone(), solver.states.clone(), solver.N.clone());
+		for &R in [1.3, 0.8].iter() {
+			let (mut new_time, mut new_Rt, mut new_states, mut new_N) = (Vec::new(), Vec::new(), States::with_capacity(2, 0), Vec::new());
+			solver.integrate(60.0, &mut new_time, &mut new_Rt, &mut new_states, &mut new_N, &time, &Rt, &states, R).unwrap();
+			time.extend(new_time);
+			Rt.extend(new_Rt);
+			states.extend_from(&new_states, 0..new_states.len());
+			N.extend(new_N);
+		}
+		N
+	}
+
+	#[test]
+	fn adaptive_agrees_with_rk4() {
+		let mut solver = small_solver(1e6);
+		solver.initialize().unwrap();
+		let rk4 = wave(&solver);
+		solver.integrator = Integrator::adaptive();
+		let adaptive = wave(&solver);
+
+		assert_eq!(rk4.len(), adaptive.len());
+		let max_relative_error = rk4.iter().zip(&adaptive).map(|(a, b)| ((a - b)/a).abs()).fold(0.0, f64::max);
+		assert!(max_relative_error < 1e-5, "relative deviation {}", max_relative_error);
+	}
 }
@@ -0,0 +1,338 @@
+//! Stochastic (tau-leaping) counterpart of the deterministic model and solver.
+//!
+//! In small populations (regions, care homes, schools) chance events such as the extinction of an outbreak or its fade-out between
+//! two introductions cannot be captured by the deterministic dif. eqs. Here every flow of the model (infection, influx, vaccination,
+//! immunisation, progression, ICU admission, recovery and death) is treated as a stochastic transition of whole individuals.
+//! In a leap of length dt the number of individuals leaving a compartment is drawn from a binomial distribution with the total hazard of
+//! all outgoing flows and is then split multinomially between the flows. This keeps all compartments non-negative. The helper variable h
+//! is still integrated deterministically.
+//!
+//! The stochastic runs use the same _Model_ parameters and append to the same result vectors of the _Solver_, so they can be
+//! written with _Solver::write\_to\_disk_. Ensembles of runs are summarised by pointwise quantiles.
+
+use crate::vaccination_model::age_group_state_vector::AgeGroupStateVector;
+use crate::vaccination_model::error::Result;
+use crate::vaccination_model::model::Model;
+use crate::vaccination_model::random::{quantile, Rng};
+use crate::vaccination_model::sink::{Directory, Sink};
+use crate::vaccination_model::solver::Solver;
+
+impl Model {
+	/// Stochastic counterpart of _slopes_. Performs one tau-leaping step of length dt starting from the current state, using the same
+	/// arguments as _slopes_ (current time, $R_t$, current state, delayed $R_{t-\tau}$ and delayed state).
+	///
+	/// Returns: (state after the step, number of individuals that became infectious during the step). Fails if the vaccination rates are not
+	/// prepared for $t$.
+	#[allow(clippy::too_many_arguments)]
+	pub fn tau_leap(&self, t: f64, dt: f64, R: f64, state: &[AgeGroupStateVector], delayed_R: f64, delayed_state: &[AgeGroupStateVector], rng: &mut Rng) -> Result<(Vec<AgeGroupStateVector>, f64)> {
+		self.check_time(t)?;
+		let week = (t/7.0).floor() as usize;							// current week at t
+		let delayed_week = ((t-self.tau)/7.0).floor() as usize;			// week at t-tau
+
+		let mut new_state = state.to_vec();
+		let mut new_infectious = 0.0f64;
+
+		for age_group_index in 0..self.age_groups.len() {
+			let i = &self.age_groups[age_group_index];
+			let ipm = R*self.I_eff(age_group_index, state);
+			let delayed_ipm = delayed_R*self.I_eff(age_group_index, delayed_state);
+
+			let s = state[age_group_index];
+			let s_delayed = delayed_state[age_group_index];
+
+			// daily vaccination rates and fractions where to deliver them (as in the dif. eqs.)
+			let f1 = self.vaccinations_per_week_dose1[week][age_group_index]/7.0;
+			let f2 = self.vaccinations_per_week_dose2[week][age_group_index]/7.0;
+			let f1_delayed = self.vaccinations_per_week_dose1[delayed_week][age_group_index]/7.0;
+			let f2_delayed = self.vaccinations_per_week_dose2[delayed_week][age_group_index]/7.0;
+			let frac0 = (s.S[0]/(s.S[0] + s.R[0])).clamp(0.0, 1.0);
+			let frac1 = (s.S[1]/(s.S[1] + s.R[1])).clamp(0.0, 1.0);
+			let frac0_delayed = (s_delayed.S[0]/(s_delayed.S[0] + s_delayed.R[0])).clamp(0.0, 1.0);
+			let frac1_delayed = (s_delayed.S[1]/(s_delayed.S[1] + s_delayed.R[1])).clamp(0.0, 1.0);
+			let pi = 1.0 - (-s.h - i.influx*self.tau/i.M).exp();
+			let immunised = [f1_delayed*frac0_delayed*(1.-pi), f2_delayed*frac1_delayed*(1.-pi)];
+
+			let n = &mut new_state[age_group_index];
+
+			// S^0 -> E^0, I^0, V^0; S^1 -> E^1, I^1, V^1; S^2 -> E^2, I^2
+			let dose_rates = [f1*frac0, f2*frac1, 0.0];
+			let recovered_dose_rates = [f1*(1.-frac0), f2*(1.-frac1)];
+			for (vacc, &dose_rate) in dose_rates.iter().enumerate() {
+				let [to_E, to_I, to_V] = draw_outflows(rng, s.S[vacc], [s.S[vacc]*ipm, s.S[vacc]/i.M*i.influx, dose_rate], dt);
+				n.S[vacc] -= to_E + to_I + to_V;
+				n.E[vacc] += to_E;
+				n.I[vacc] += to_I;
+				if vacc < 2 {
+					n.V[vacc] += to_V;
+				}
+				new_infectious += to_I;
+			}
+
+			// V^k -> E^k, I^k and after the delay tau to S^{k+1} (vaccine failed) or R^{k+1} (immunised)
+			for (dose, &immunised_rate) in immunised.iter().enumerate() {
+				let [to_E, to_I, to_S, to_R] = draw_outflows(rng, s.V[dose], [s.V[dose]*ipm, s.V[dose]/i.M*i.influx, (1.-self.eta0)*immunised_rate, self.eta0*immunised_rate], dt);
+				n.V[dose] -= to_E + to_I + to_S + to_R;
+				n.E[dose] += to_E;
+				n.I[dose] += to_I;
+				n.S[dose+1] += to_S;
+				n.R[dose+1] += to_R;
+				new_infectious += to_I;
+			}
+
+			// E -> I, I -> ICU, R, D and ICU -> R, D
+			for vacc in 0..3 {
+				let [to_I] = draw_outflows(rng, s.E[vacc], [i.rho*s.E[vacc]], dt);
+				n.E[vacc] -= to_I;
+				n.I[vacc] += to_I;
+				new_infectious += to_I;
+
+				let [to_ICU, to_R, to_D] = draw_outflows(rng, s.I[vacc], [i.alpha[vacc]*s.I[vacc], i.gamma_I[vacc]*s.I[vacc], i.delta_I[vacc]*s.I[vacc]], dt);
+				n.I[vacc] -= to_ICU + to_R + to_D;
+				n.ICU[vacc] += to_ICU;
+				n.R[vacc] += to_R;
+				n.D += to_D;
+
+				let [to_R, to_D] = draw_outflows(rng, s.ICU[vacc], [i.gamma_ICU[vacc]*s.ICU[vacc], i.delta_ICU[vacc]*s.ICU[vacc]], dt);
+				n.ICU[vacc] -= to_R + to_D;
+				n.R[vacc] += to_R;
+				n.D += to_D;
+			}
+
+			// Vaccination of recovered people: R^0 -> R^1, R^1 -> R^2
+			for (dose, &dose_rate) in recovered_dose_rates.iter().enumerate() {
+				let [to_R] = draw_outflows(rng, s.R[dose], [dose_rate], dt);
+				n.R[dose] -= to_R;
+				n.R[dose+1] += to_R;
+			}
+
+			// The helper variable h is integrated deterministically
+			n.h += dt*(ipm - delayed_ipm);
+		}
+
+		Ok((new_state, new_infectious))
+	}
+}
+
+/// Draws how many of the _size_ individuals of a compartment leave it during dt via each of the outgoing flows with the given total
+/// rates (individuals per day). The total number leaving is binomial with the probability $1-\exp(-dt\sum_k r_k/size)$ and is split
+/// multinomially between the flows. Flows with a rate of zero (or below) get nobody.
+fn draw_outflows<const K: usize>(rng: &mut Rng, size: f64, rates: [f64; K], dt: f64) -> [f64; K] {
+	let mut result = [0.0f64; K];
+	let size = size.max(0.0).round() as u64;
+	let mut remaining_rate: f64 = rates.iter().map(|r| r.max(0.0)).sum();
+	if size == 0 || remaining_rate <= 0.0 {
+		return result;
+	}
+
+	// The last flow with a positive rate gets all individuals left over
+	let last = rates.iter().rposition(|r| *r > 0.0).unwrap_or(0);
+	let mut remaining = rng.binomial(size, 1.0 - (-remaining_rate/(size as f64)*dt).exp());
+	for k in 0..K {
+		if remaining == 0 {
+			break;
+		}
+		let rate = rates[k].max(0.0);
+		if rate <= 0.0 {
+			continue;
+		}
+		let drawn = if k == last { remaining } else { rng.binomial(remaining, (rate/remaining_rate).min(1.0)) };
+		result[k] = drawn as f64;
+		remaining -= drawn;
+		remaining_rate -= rate;
+	}
+	result
+}
+
+/// Rounds all compartments of a state to whole individuals (h is left untouched).
+fn rounded(state: &AgeGroupStateVector) -> AgeGroupStateVector {
+	let round = |x: &f64| x.max(0.0).round();
+	AgeGroupStateVector {
+		S: [round(&state.S[0]), round(&state.S[1]), round(&state.S[2])],
+		V: [round(&state.V[0]), round(&state.V[1])],
+		E: [round(&state.E[0]), round(&state.E[1]), round(&state.E[2])],
+		I: [round(&state.I[0]), round(&state.I[1]), round(&state.I[2])],
+		ICU: [round(&state.ICU[0]), round(&state.ICU[1]), round(&state.ICU[2])],
+		D: round(&state.D),
+		R: [round(&state.R[0]), round(&state.R[1]), round(&state.R[2])],
+		h: state.h
+	}
+}
+
+impl Solver {
+	/// Stochastic counterpart of a run at a constant raw $R_t$. Continues the current solution for a timespan T (in whole days) with
+	/// tau-leaping steps of length dt and appends the results to the result vectors, just like _controlled\_run_ does.
+	/// The compartments are rounded to whole individuals first. Fails if the vaccination rates are not prepared for the whole timespan.
+	pub fn stochastic_run(&mut self, T: f64, R: f64, rng: &mut Rng) -> Result<()> {
+		self.check_horizon(T)?;
+		let one_day = (1./self.dt) as usize;
+		let index_delay:usize = (self.model.tau/self.dt) as usize;
+
+		let mut t = self.time[self.index];
+		let mut state: Vec<AgeGroupStateVector> = self.states[self.index].iter().map(rounded).collect();
+
+		for _day in 0..T as usize {
+			for _step in 0..one_day {
+				// Get delayed system state variables (the initial state is used before the start of the history)
+				let delayed_index = self.index.saturating_sub(index_delay);
+
+				let (new_state, new_infectious) = self.model.tau_leap(t, self.dt, R, &state, self.Rt[delayed_index], &self.states[delayed_index], rng)?;
+				state = new_state;
+				t += self.dt;
+
+				self.time.push(t);
+				self.Rt.push(R);
+				self.states.push(&state);
+				self.N.push(new_infectious/self.dt);
+				self.index += 1;
+			}
+
+			let Nobs = self.observed_cases();
+			self.N_obs.extend_from_slice(&vec![Nobs; one_day]);
+		}
+		Ok(())
+	}
+
+	/// Runs an ensemble of _runs_ independent stochastic continuations of the current solution for a timespan T at a constant raw $R_t$.
+	/// The solver itself is left unchanged. The seed makes the whole ensemble reproducible.
+	pub fn stochastic_ensemble(&self, T: f64, R: f64, runs: usize, seed: u64) -> Result<StochasticEnsemble> {
+		let mut rng = Rng::new(seed);
+		let one_day = (1./self.dt) as usize;
+		let days = T as usize;
+
+		let mut ensemble = StochasticEnsemble {
+			time: (0..days+1).map(|day| self.time[self.index] + day as f64).collect(),
+			N: Vec::with_capacity(runs),
+			N_obs: Vec::with_capacity(runs),
+			ICU: Vec::with_capacity(runs),
+			D: Vec::with_capacity(runs),
+			active: Vec::with_capacity(runs)
+		};
+
+		for _run in 0..runs {
+			let mut run_rng = rng.split();
+			let mut solver = self.clone();
+			solver.stochastic_run(T, R, &mut run_rng)?;
+
+			let start = self.index;
+			let daily_indices: Vec<usize> = (0..days+1).map(|day| start + day*one_day).collect();
+			ensemble.N.push(daily_indices.iter().map(|&j| solver.N[j]).collect());
+			ensemble.N_obs.push(daily_indices.iter().map(|&j| solver.N_obs[j]).collect());
+			ensemble.ICU.push(daily_indices.iter().map(|&j| solver.model.ICU_occupancy(&solver.states[j])).collect());
+			ensemble.D.push(daily_indices.iter().map(|&j| solver.states[j].iter().map(|ag| ag.D).sum()).collect());
+			ensemble.active.push(daily_indices.iter().map(|&j| solver.states[j].iter().map(|ag| ag.E.iter().sum::<f64>() + ag.I.iter().sum::<f64>()).sum()).collect());
+		}
+		Ok(ensemble)
+	}
+}
+
+/// Daily time series of an ensemble of stochastic runs (outer index: run, inner index: day)
+pub struct StochasticEnsemble {
+	/// Times (one per day, starting at the start of the ensemble runs)
+	pub time: Vec<f64>,
+	/// Daily new infections
+	pub N: Vec<Vec<f64>>,
+	/// Observed daily new infections (delayed by the observation kernel)
+	pub N_obs: Vec<Vec<f64>>,
+	/// ICU occupancy
+	pub ICU: Vec<Vec<f64>>,
+	/// Cumulative deaths
+	pub D: Vec<Vec<f64>>,
+	/// Active infections (all E and I compartments)
+	pub active: Vec<Vec<f64>>
+}
+
+impl StochasticEnsemble {
+	/// Returns the pointwise quantile _q_ (between 0 and 1) of one of the ensemble time series, e.g. `ensemble.quantile(&ensemble.ICU, 0.95)`.
+	pub fn quantile(&self, series: &[Vec<f64>], q: f64) -> Vec<f64> {
+		let mut sample = vec![0.0f64; series.len()];
+		(0..self.time.len()).map(|day| {
+			for (run, values) in series.iter().enumerate() {
+				sample[run] = values[day];
+			}
+			quantile(&mut sample, q)
+		}).collect()
+	}
+
+	/// Fraction of the runs in which the epidemic went extinct (no individuals left in any E or I compartment) by the end.
+	pub fn extinction_probability(&self) -> f64 {
+		if self.active.is_empty() {
+			return 0.0;
+		}
+		self.active.iter().filter(|run| run.last().is_none_or(|&a| a == 0.0)).count() as f64/self.active.len() as f64
+	}
+
+	/// Writes the pointwise quantiles of all time series to "./data/foldername/ensemble.data", one column per series and quantile.
+	pub fn write_to_disk(&self, foldername: &str, quantiles: &[f64]) -> Result<()> {
+		self.write_to(&mut Directory::data(foldername), quantiles)
+	}
+
+	/// Same as _write\_to\_disk_, but writes "ensemble.data" to the _sink_ (see the _sink_ submodule)
+	pub fn write_to(&self, sink: &mut dyn Sink, quantiles: &[f64]) -> Result<()> {
+		let precision = 6;
+		let series: [(&str, &Vec<Vec<f64>>); 5] = [("N", &self.N), ("N_obs", &self.N_obs), ("ICU", &self.ICU), ("D", &self.D), ("active", &self.active)];
+
+		let mut header = vec!["t".to_string()];
+		let mut columns: Vec<Vec<f64>> = vec![self.time.clone()];
+		for (name, values) in series.iter() {
+			for q in quantiles {
+				header.push(format!("{}_q{:.3}", name, q));
+				columns.push(self.quantile(values, *q));
+			}
+		}
+
+		sink.write_file("ensemble.data", &mut |file| {
+			writeln!(file, "{} (extinction probability {:.4})", header.join(" \t "), self.extinction_probability())?;
+			for day in 0..self.time.len() {
+				writeln!(file, "{}", columns.iter().map(|c| format!("{:.1$}", c[day], precision)).collect::<Vec<String>>().join(" \t "))?;
+			}
+			Ok(())
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::vaccination_model::solver::tests::small_solver;
+
+	#[test]
+	fn flows_without_rate_get_nobody() {
+		let mut rng = Rng::new(3);
+		for _ in 0..1000 {
+			let [first, second, last] = draw_outflows(&mut rng, 1000.0, [0.1, 0.2, 0.0], 10.0);
+			assert_eq!(last, 0.0);
+			assert!(first + second <= 1000.0);
+			let [first, _, last] = draw_outflows(&mut rng, 100.0, [0.0, 50.0, 0.0], 1.0);
+			assert_eq!((first, last), (0.0, 0.0));
+		}
+	}
+
+	#[test]
+	fn runs_keep_the_population() {
+		let mut solver = small_solver(1e4);
+		solver.initialize().unwrap();
+		solver.stochastic_run(60.0, 1.2, &mut Rng::new(5)).unwrap();
+
+		// All compartments except the helper variable h
+		let population = |j: usize| -> f64 {
+			solver.states[j].iter().map(|s| s.S.iter().chain(&s.V).chain(&s.E).chain(&s.I).chain(&s.ICU).chain(&s.R).sum::<f64>() + s.D).sum()
+		};
+		let initial = population(1);
+		assert!((1..solver.index+1).all(|j| population(j) == initial));
+	}
+
+	#[test]
+	fn ensemble_is_reproducible() {
+		let mut solver = small_solver(1e4);
+		solver.initialize().unwrap();
+
+		let ensemble = solver.stochastic_ensemble(10.0, 1.2, 4, 17).unwrap();
+		let again = solver.stochastic_ensemble(10.0, 1.2, 4, 17).unwrap();
+		assert_eq!((&again.N, &again.N_obs, &again.ICU, &again.D, &again.active), (&ensemble.N, &ensemble.N_obs, &ensemble.ICU, &ensemble.D, &ensemble.active));
+		assert_eq!(again.time, ensemble.time);
+
+		// Different runs of an ensemble and ensembles with another seed differ
+		assert_ne!(ensemble.active[0], ensemble.active[1]);
+		assert_ne!(solver.stochastic_ensemble(10.0, 1.2, 4, 18).unwrap().active, ensemble.active);
+	}
+}
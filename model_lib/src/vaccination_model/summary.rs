@@ -0,0 +1,157 @@
+//! Summary metrics of finished runs.
+//!
+//! _Solver::summary_ condenses the results of a run into one record: the deaths (in total and per age group), the peak and the integrated ICU
+//! occupancy, the time with the ICUs above their capacity, the infections (in total and per age group) and the freedom, i.e. the time average
+//! of the test-trace-and-isolate corrected $R_t$ allowed by the control. All of them are computed from the results after the initial values up
+//! to the current index (the whole run after _controlled\_run_), rates are integrated with the step size of the solver. Deaths, ICU days and
+//! freedom are the same as the corresponding outputs of the _sensitivity_ analyses.
+//!
+//! _Summary::table_ turns a summary into a table with a single row, _Summary::runs\_table_ collects the summaries of several runs in one table
+//! with one row per run. Both can be written in any format (see _output_).
+
+use crate::vaccination_model::delay_system::DelaySystem;
+use crate::vaccination_model::output::{Table, TableKind};
+use crate::vaccination_model::sensitivity::Output;
+use crate::vaccination_model::solver::Solver;
+
+/// Summary metrics of a run, see the module documentation
+#[derive(Clone, Debug, PartialEq)]
+pub struct Summary {
+	/// Names of the blocks (age groups)
+	pub names: Vec<String>,
+	/// Deaths during the run
+	pub deaths: f64,
+	/// Deaths during the run in every block
+	pub deaths_per_age_group: Vec<f64>,
+	/// Maximal ICU occupancy
+	pub peak_ICU: f64,
+	/// Time of the maximal ICU occupancy
+	pub peak_ICU_time: f64,
+	/// ICU occupancy integrated over the run (patient days)
+	pub ICU_days: f64,
+	/// ICU capacity used for _days\_above\_capacity_
+	pub ICU_capacity: f64,
+	/// Time (in days) with the ICU occupancy above the capacity
+	pub days_above_capacity: f64,
+	/// New infections during the run (not delayed by the observation)
+	pub infections: f64,
+	/// New infections during the run in every block
+	pub infections_per_age_group: Vec<f64>,
+	/// Time average of the test-trace-and-isolate corrected $R_t$ during the run
+	pub freedom: f64
+}
+
+impl<M: DelaySystem> Solver<M> {
+	/// Summary metrics of the results so far (see the _summary_ submodule), with the ICU capacity _ICU\_capacity_ for the time above capacity
+	pub fn summary(&self, ICU_capacity: f64) -> Summary {
+		let results = 1..self.index+1;
+		let names = self.model.block_names();
+		let per_block = |observable: &dyn Fn(&[M::State], usize) -> f64| -> Vec<f64> {
+			(0..names.len()).map(|block| results.clone().map(|j| observable(&self.states[j], block)).sum::<f64>()*self.dt).collect()
+		};
+
+		let ICU: Vec<f64> = results.clone().map(|j| self.model.ICU_occupancy(&self.states[j])).collect();
+		let (peak, peak_ICU) = ICU.iter().enumerate().fold((0, f64::NAN), |(k, max), (j, x)| if *x > max || max.is_nan() { (j, *x) } else { (k, max) });
+		Summary {
+			deaths: Output::Deaths.evaluate(self),
+			deaths_per_age_group: per_block(&|state, block| self.model.deaths_block(state, block)),
+			peak_ICU,
+			peak_ICU_time: if ICU.is_empty() { f64::NAN } else { self.time[peak+1] },
+			ICU_days: Output::ICUDays.evaluate(self),
+			ICU_capacity,
+			days_above_capacity: (ICU.iter().filter(|x| **x > ICU_capacity).count() as f64)*self.dt,
+			infections: self.N[results.clone()].iter().sum::<f64>()*self.dt,
+			infections_per_age_group: per_block(&|state, block| self.model.N_block(state, block)),
+			freedom: Output::Freedom.evaluate(self),
+			names
+		}
+	}
+}
+
+impl Summary {
+	/// The summary as a table "summary" with a single row (see the module documentation for the columns)
+	pub fn table(&self) -> Table {
+		Summary::columns(Table::new("summary", TableKind::Parameters), std::slice::from_ref(self))
+	}
+
+	/// The summaries of several runs as a table _name_ with one row per run, the first column "run" contains the names of the runs.
+	/// All runs need the same blocks (age groups).
+	pub fn runs_table(name: &str, runs: &[String], summaries: &[Summary]) -> Table {
+		Summary::columns(Table::new(name, TableKind::Series).text("run", runs.to_vec()), summaries)
+	}
+
+	/// Adds the columns "deaths", "deaths\_{name}" for every block, "peak\_ICU", "peak\_ICU\_time", "ICU\_days", "ICU\_capacity",
+	/// "days\_above\_capacity", "infections", "infections\_{name}" for every block and "freedom"
+	fn columns(mut table: Table, summaries: &[Summary]) -> Table {
+		let column = |value: &dyn Fn(&Summary) -> f64| summaries.iter().map(value).collect::<Vec<f64>>();
+		let names = summaries.first().map_or(&[][..], |summary| &summary.names[..]);
+
+		table = table.float("deaths", column(&|summary| summary.deaths));
+		for (block, name) in names.iter().enumerate() {
+			table = table.float(&format!("deaths_{}", name), column(&|summary| summary.deaths_per_age_group[block]));
+		}
+		table = table.float("peak_ICU", column(&|summary| summary.peak_ICU))
+			.float("peak_ICU_time", column(&|summary| summary.peak_ICU_time))
+			.float("ICU_days", column(&|summary| summary.ICU_days))
+			.float("ICU_capacity", column(&|summary| summary.ICU_capacity))
+			.float("days_above_capacity", column(&|summary| summary.days_above_capacity))
+			.float("infections", column(&|summary| summary.infections));
+		for (block, name) in names.iter().enumerate() {
+			table = table.float(&format!("infections_{}", name), column(&|summary| summary.infections_per_age_group[block]));
+		}
+		table.float("freedom", column(&|summary| summary.freedom))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::vaccination_model::solver::tests::small_solver;
+
+	#[test]
+	fn summary_of_a_growing_wave() {
+		let mut solver = small_solver(1e6);
+		solver.initialize().unwrap();
+		solver.run_constant(60.0, 3.0).unwrap();
+		let ICU = |j: usize| solver.model.ICU_occupancy(&solver.states[j]);
+		let day = |day: f64| solver.time.iter().position(|t| (t - solver.t0 - day).abs() < 1e-6).unwrap();
+		let (start, end) = (solver.states[0].to_vec(), solver.states[solver.index].to_vec());
+		let day_40 = day(40.0);
+		assert!((day(20.0)..=solver.index).all(|j| ICU(j) > ICU(j-1)) && (0..day(20.0)).all(|j| ICU(j) < ICU(day_40)));
+
+		// The occupancy grows after the initial decline, so it peaks at the end and is above the occupancy at day 40 for the last 20 days
+		let summary = solver.summary(ICU(day_40));
+		assert_eq!(summary.peak_ICU, ICU(solver.index));
+		assert_eq!(summary.peak_ICU_time, solver.time[solver.index]);
+		assert!((summary.days_above_capacity - 20.0).abs() < 1e-6);
+		assert_eq!(solver.summary(0.0).days_above_capacity, (solver.index as f64)*solver.dt);
+		assert_eq!(solver.summary(f64::INFINITY).days_above_capacity, 0.0);
+
+		// The deaths are the increase of the compartment D (up to the quadrature of the death rate)
+		let deaths: Vec<f64> = start.iter().zip(end.iter()).map(|(start, end)| end.D - start.D).collect();
+		assert!(deaths.iter().all(|deaths| *deaths > 0.0));
+		for (summary, deaths) in summary.deaths_per_age_group.iter().zip(deaths.iter()) {
+			assert!((summary/deaths - 1.0).abs() < 1e-2, "{} vs {}", summary, deaths);
+		}
+		assert!((summary.deaths/deaths.iter().sum::<f64>() - 1.0).abs() < 1e-2);
+		assert!((summary.deaths - summary.deaths_per_age_group.iter().sum::<f64>()).abs() < 1e-9*summary.deaths);
+		assert_eq!(summary.names, vec!["60+", "0-59"]);
+	}
+
+	#[test]
+	fn peak_of_a_passing_wave() {
+		let mut solver = small_solver(1e6);
+		solver.initialize().unwrap();
+		solver.run_constant(40.0, 3.0).unwrap();
+		solver.run_constant(60.0, 0.5).unwrap();
+		let ICU: Vec<f64> = (0..=solver.index).map(|j| solver.model.ICU_occupancy(&solver.states[j])).collect();
+
+		let summary = solver.summary(f64::INFINITY);
+		let peak = solver.time.iter().position(|t| *t == summary.peak_ICU_time).unwrap();
+		assert!(peak > 0 && peak < solver.index);
+		assert_eq!(summary.peak_ICU, ICU[peak]);
+		assert!(ICU.iter().all(|x| *x <= ICU[peak]));
+		assert_eq!(summary.table().rows(), 1);
+		assert_eq!(Summary::runs_table("runs", &["a".to_string(), "b".to_string()], &[summary.clone(), summary]).rows(), 2);
+	}
+}
@@ -0,0 +1,205 @@
+//! Synthetic surveillance data, e.g. to test estimation pipelines on data with a known truth.
+//!
+//! The expected daily reports of a finished run are computed with the observation model of the solver (reporting delays, ascertainment,
+//! weekday effects and holidays, see _observation_). The reported numbers are then drawn as Poisson or negative binomial counts around them
+//! and optionally aggregated to weeks. The same seed always gives the same data.
+
+use crate::vaccination_model::delay_system::DelaySystem;
+use crate::vaccination_model::error::{read_file, Error, Result};
+use crate::vaccination_model::random::{quantile, Rng};
+use crate::vaccination_model::sink::{Directory, Sink};
+use crate::vaccination_model::solver::Solver;
+use crate::vaccination_model::target::Target;
+
+/// Distribution of the reported counts around the expected reports
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Noise {
+	/// The expected reports without noise
+	None,
+	/// Poisson distributed counts
+	Poisson,
+	/// Negative binomially distributed counts with dispersion $k$ (variance $\mu + \mu^2/k$)
+	NegativeBinomial { dispersion: f64 }
+}
+
+/// Time resolution of the data
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Aggregation {
+	/// Daily reports
+	Daily,
+	/// Sums over weeks (starting with the first day of the run). The ICU occupancy is averaged over the week.
+	Weekly
+}
+
+/// Accessor of one series of the data
+type Series = fn(&SurveillanceData) -> &Vec<f64>;
+
+/// Settings of the generator
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SurveillanceGenerator {
+	/// Distribution of the counts
+	pub noise: Noise,
+	/// Time resolution
+	pub aggregation: Aggregation,
+	/// Seed of the random number generator
+	pub seed: u64
+}
+
+/// Synthetic surveillance time series. Entry j belongs to the day (or week) starting at _t\[j\]_.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SurveillanceData {
+	/// Start of the day or week
+	pub t: Vec<f64>,
+	/// Reported cases
+	pub cases: Vec<f64>,
+	/// Reported ICU admissions
+	pub ICU_admissions: Vec<f64>,
+	/// Reported deaths
+	pub deaths: Vec<f64>,
+	/// Reported ICU occupancy
+	pub ICU: Vec<f64>
+}
+
+impl SurveillanceGenerator {
+	/// Generator for daily Poisson counts
+	pub fn new(seed: u64) -> SurveillanceGenerator {
+		SurveillanceGenerator { noise: Noise::Poisson, aggregation: Aggregation::Daily, seed }
+	}
+
+	/// Draws one count with the expected value _mean_
+	fn draw(&self, rng: &mut Rng, mean: f64) -> f64 {
+		match self.noise {
+			Noise::None => mean,
+			Noise::Poisson => rng.poisson(mean) as f64,
+			Noise::NegativeBinomial { dispersion } => rng.negative_binomial(mean, dispersion) as f64
+		}
+	}
+}
+
+impl SurveillanceData {
+	/// Reads observed series from a file in the format of _write\_to\_disk_: a header line with the column names and whitespace separated
+	/// numbers. Only the column "t" is required, missing series are filled with NaN (skipped by the estimation).
+	pub fn read_from_disk(filename: &str) -> Result<SurveillanceData> {
+		let invalid = |message: String| Error::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, message));
+		let content = read_file(filename)?;
+		let mut lines = content.lines().filter(|line| !line.trim().is_empty());
+		let header: Vec<&str> = lines.next().ok_or_else(|| invalid(format!("{} is empty", filename)))?.split_whitespace().collect();
+		let column = |name: &str| header.iter().position(|h| *h == name);
+		let t_column = column("t").ok_or_else(|| invalid(format!("{} has no column t", filename)))?;
+		let columns = [column("cases"), column("ICU_admissions"), column("deaths"), column("ICU")];
+
+		let mut data = SurveillanceData::default();
+		for (number, line) in lines.enumerate() {
+			let values = line.split_whitespace().map(|x| x.parse::<f64>()).collect::<Result<Vec<f64>, _>>()
+				.map_err(|e| invalid(format!("{} line {}: {}", filename, number+2, e)))?;
+			if values.len() != header.len() {
+				return Err(invalid(format!("{} line {}: {} values for {} columns", filename, number+2, values.len(), header.len())));
+			}
+			let value = |column: Option<usize>| column.map(|i| values[i]).unwrap_or(f64::NAN);
+			data.t.push(values[t_column]);
+			data.cases.push(value(columns[0]));
+			data.ICU_admissions.push(value(columns[1]));
+			data.deaths.push(value(columns[2]));
+			data.ICU.push(value(columns[3]));
+		}
+		Ok(data)
+	}
+
+	/// Writes the data to the file "./data/foldername/surveillance.data" in the format of _Solver::write\_to\_disk_
+	/// (tab separated columns with a header line).
+	pub fn write_to_disk(&self, foldername: &str) -> Result<()> {
+		self.write_to(&mut Directory::data(foldername))
+	}
+
+	/// Same as _write\_to\_disk_, but writes "surveillance.data" to the _sink_ (see the _sink_ submodule)
+	pub fn write_to(&self, sink: &mut dyn Sink) -> Result<()> {
+		let precision = 6;
+		sink.write_file("surveillance.data", &mut |file| {
+			writeln!(file, "t \t cases \t ICU_admissions \t deaths \t ICU")?;
+			for j in 0..self.t.len() {
+				writeln!(file, "{1:.0$} \t {2:.0$} \t {3:.0$} \t {4:.0$} \t {5:.0$}", precision, self.t[j], self.cases[j], self.ICU_admissions[j], self.deaths[j], self.ICU[j])?;
+			}
+			Ok(())
+		})
+	}
+
+	/// Pointwise quantile _q_ (between 0 and 1) of several runs on the same days (e.g. posterior predictive runs)
+	pub fn quantile(runs: &[SurveillanceData], q: f64) -> SurveillanceData {
+		let days = runs.iter().map(|run| run.t.len()).min().unwrap_or(0);
+		let series = |values: Series| -> Vec<f64> {
+			(0..days).map(|j| quantile(&mut runs.iter().map(|run| values(run)[j]).collect::<Vec<f64>>(), q)).collect()
+		};
+		SurveillanceData { t: runs.first().map(|run| run.t[..days].to_vec()).unwrap_or_default(), cases: series(|run| &run.cases),
+						   ICU_admissions: series(|run| &run.ICU_admissions), deaths: series(|run| &run.deaths), ICU: series(|run| &run.ICU) }
+	}
+
+	/// Writes the pointwise _quantiles_ of several runs to the file "./data/foldername/predictive.data": the column "cases\_50" is e.g.
+	/// the median of the reported cases.
+	pub fn write_quantiles(runs: &[SurveillanceData], foldername: &str, quantiles: &[f64]) -> Result<()> {
+		Self::write_quantiles_to(runs, &mut Directory::data(foldername), quantiles)
+	}
+
+	/// Same as _write\_quantiles_, but writes "predictive.data" to the _sink_ (see the _sink_ submodule)
+	pub fn write_quantiles_to(runs: &[SurveillanceData], sink: &mut dyn Sink, quantiles: &[f64]) -> Result<()> {
+		let precision = 6;
+		let bands: Vec<SurveillanceData> = quantiles.iter().map(|q| SurveillanceData::quantile(runs, *q)).collect();
+		let columns: [(&str, Series); 4] = [("cases", |data| &data.cases), ("ICU_admissions", |data| &data.ICU_admissions),
+											("deaths", |data| &data.deaths), ("ICU", |data| &data.ICU)];
+		sink.write_file("predictive.data", &mut |file| {
+			let mut header = vec!["t".to_string()];
+			for (name, _) in columns.iter() {
+				header.extend(quantiles.iter().map(|q| format!("{}_{}", name, (100.0*q).round())));
+			}
+			writeln!(file, "{}", header.join(" \t "))?;
+			for j in 0..bands.first().map_or(0, |band| band.t.len()) {
+				let mut line = vec![format!("{:.1$}", bands[0].t[j], precision)];
+				for (_, values) in columns.iter() {
+					line.extend(bands.iter().map(|band| format!("{:.1$}", values(band)[j], precision)));
+				}
+				writeln!(file, "{}", line.join(" \t "))?;
+			}
+			Ok(())
+		})
+	}
+}
+
+impl<M: DelaySystem> Solver<M> {
+	/// Generates synthetic surveillance data from the results of the run so far (see the module documentation).
+	pub fn synthetic_surveillance(&self, generator: &SurveillanceGenerator) -> SurveillanceData {
+		let mut rng = Rng::new(generator.seed);
+		let targets = [Target::ObservedCases, Target::ICUAdmissions, Target::Deaths, Target::ICUOccupancy];
+
+		// Daily counts of every series, each with its own stream of random numbers
+		let t: Vec<f64> = self.observed_daily(&Target::ObservedCases).iter().map(|(t, _)| *t).collect();
+		let mut daily: Vec<Vec<f64>> = targets.iter().map(|target| {
+			let mut series_rng = rng.split();
+			self.observed_daily(target).iter().map(|(_, mean)| generator.draw(&mut series_rng, *mean)).collect()
+		}).collect();
+
+		let t = match generator.aggregation {
+			Aggregation::Daily => t,
+			Aggregation::Weekly => {
+				let weeks = t.len()/7;
+				for (series, target) in daily.iter_mut().zip(targets.iter()) {
+					let mut weekly: Vec<f64> = series.chunks_exact(7).map(|week| week.iter().sum()).collect();
+					if target.is_stock() {
+						weekly.iter_mut().for_each(|x| *x /= 7.0);
+					}
+					*series = weekly;
+				}
+				t.iter().step_by(7).take(weeks).copied().collect()
+			}
+		};
+
+		let ICU = daily.pop().unwrap_or_default();
+		let deaths = daily.pop().unwrap_or_default();
+		let ICU_admissions = daily.pop().unwrap_or_default();
+		let cases = daily.pop().unwrap_or_default();
+		SurveillanceData { t, cases, ICU_admissions, deaths, ICU }
+	}
+
+	/// The expected reports of the run so far (the synthetic data without noise), e.g. to compare the model with observed data
+	pub fn expected_surveillance(&self, aggregation: Aggregation) -> SurveillanceData {
+		self.synthetic_surveillance(&SurveillanceGenerator { noise: Noise::None, aggregation, seed: 0 })
+	}
+}
@@ -0,0 +1,117 @@
+//! Parallel parameter sweeps.
+//!
+//! A sweep evaluates a task (usually setting up and running an own solver) for every input of a list, e.g. all combinations of efficacies and
+//! uptakes, on all CPU cores. The inputs are handed out one by one to the worker threads, so long and short runs balance out. The results are
+//! returned in the order of the inputs, independent of the number of threads and the scheduling, so a sweep gives the same results as a
+//! sequential loop. The progress can be reported after every finished task.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Function called with the number of finished and of all tasks
+type Progress<'a> = Box<dyn Fn(usize, usize) + Send + Sync + 'a>;
+
+/// Settings of a parallel sweep
+pub struct Sweep<'a> {
+	/// Number of worker threads
+	pub threads: usize,
+	/// Called after every finished task (one call at a time, with increasing numbers)
+	progress: Option<Progress<'a>>
+}
+
+impl<'a> Default for Sweep<'a> {
+	fn default() -> Sweep<'a> {
+		Sweep::new()
+	}
+}
+
+impl<'a> Sweep<'a> {
+	/// Sweep using all available CPU cores without progress reporting
+	pub fn new() -> Sweep<'a> {
+		Sweep { threads: std::thread::available_parallelism().map_or(1, |n| n.get()), progress: None }
+	}
+
+	/// Uses the given number of worker threads (at least one)
+	pub fn threads(mut self, threads: usize) -> Sweep<'a> {
+		self.threads = threads.max(1);
+		self
+	}
+
+	/// Calls _progress_ with the number of finished and of all tasks after every finished task
+	pub fn progress(mut self, progress: impl Fn(usize, usize) + Send + Sync + 'a) -> Sweep<'a> {
+		self.progress = Some(Box::new(progress));
+		self
+	}
+
+	/// Reports the progress as "label finished/all" on the standard error output (overwriting the line)
+	pub fn print_progress(self, label: &'a str) -> Sweep<'a> {
+		self.progress(move |done, total| {
+			eprint!("\r{} {}/{}", label, done, total);
+			if done == total {
+				eprintln!();
+			}
+		})
+	}
+
+	/// Runs _task_ for every input and returns the results in the order of the inputs
+	pub fn run<I: Sync, T: Send>(&self, inputs: &[I], task: impl Fn(&I) -> T + Sync) -> Vec<T> {
+		let next = AtomicUsize::new(0);
+		let done = Mutex::new(0);
+		let threads = self.threads.min(inputs.len()).max(1);
+
+		let results: Vec<Option<T>> = std::thread::scope(|scope| {
+			let workers: Vec<_> = (0..threads).map(|_| scope.spawn(|| {
+				let mut results = Vec::new();
+				loop {
+					let i = next.fetch_add(1, Ordering::Relaxed);
+					if i >= inputs.len() {
+						break;
+					}
+					results.push((i, task(&inputs[i])));
+					if let Some(progress) = &self.progress {
+						let mut done = done.lock().unwrap_or_else(|e| e.into_inner());
+						*done += 1;
+						progress(*done, inputs.len());
+					}
+				}
+				results
+			})).collect();
+
+			let mut ordered: Vec<Option<T>> = (0..inputs.len()).map(|_| None).collect();
+			for worker in workers {
+				match worker.join() {
+					Ok(results) => for (i, result) in results {
+						ordered[i] = Some(result);
+					},
+					Err(panic) => std::panic::resume_unwind(panic)
+				}
+			}
+			ordered
+		});
+		results.into_iter().map(|result| result.expect("every input is evaluated once")).collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::time::Duration;
+
+	#[test]
+	fn results_are_in_the_order_of_the_inputs() {
+		// Early inputs take longest, so they finish after later ones
+		let inputs: Vec<u64> = (0..20).collect();
+		let reports = Mutex::new(Vec::new());
+		let sweep = Sweep::new().threads(4).progress(|done, total| reports.lock().unwrap().push((done, total)));
+		let results = sweep.run(&inputs, |&i| {
+			std::thread::sleep(Duration::from_millis(2*(20 - i)));
+			i*i
+		});
+		drop(sweep);
+
+		assert_eq!(results, inputs.iter().map(|i| i*i).collect::<Vec<u64>>());
+		let reports = reports.into_inner().unwrap();
+		assert_eq!(reports.len(), 20);
+		assert!(reports.iter().enumerate().all(|(k, &(done, total))| done == k+1 && total == 20));
+	}
+}
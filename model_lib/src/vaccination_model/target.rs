@@ -0,0 +1,175 @@
+//! Quantities the control system can aim at.
+//!
+//! Every target is observed with a delay: the observation delay model of a target is a kernel $w_d$ of daily weights. For rates (infections,
+//! admissions, deaths) the observed value at time $t$ is $f(t) \sum_d w_d \int_{t-(d+1)}^{t-d} x(s) ds$, i.e. the fraction $w_d$ of the events from
+//! $d$ days ago is reported today, modulated by the reporting factor $f(t)$ of the day (weekday effects and holidays). Infections are counted only
+//! with their age-specific ascertainment. For the ICU occupancy (a stock, not a rate) it is $\sum_d w_d x(t-d)$.
+//! Each target takes its default kernel from the observation model of the solver (see _Target::observation\_delay_ and _observation_),
+//! which can be replaced with _Target::with\_delay_.
+
+use crate::vaccination_model::age_group_state_vector::States;
+use crate::vaccination_model::delay_system::DelaySystem;
+use crate::vaccination_model::observation::ObservationModel;
+
+/// The quantity the control system aims to keep at the set point
+#[derive(Clone, Debug, PartialEq)]
+pub enum Target {
+	/// Daily observed infections (ascertained daily infections convolved with the reporting delay)
+	ObservedCases,
+	/// ICU occupancy
+	ICUOccupancy,
+	/// Daily observed ICU admissions
+	ICUAdmissions,
+	/// Daily observed deaths
+	Deaths,
+	/// Fraction of positive tests, assuming a fixed number of tests per day and that every observed case had one positive test
+	/// (the reporting factors do not apply, they affect cases and tests alike)
+	TestPositivity { tests_per_day: f64 },
+	/// Daily observed infections in the given age groups (e.g. the 60+ year olds)
+	AgeIncidence { age_groups: Vec<usize> },
+	/// Weighted sum of targets $\sum_k w_k x_k$, every component observed with its own delay.
+	/// Choose e.g. the weights $w_k = 1/x_k^{aim}$ and the set point $\sum_k w_k x_k^{aim}$ to weight relative deviations.
+	Weighted(Vec<(f64, Target)>),
+	/// A target observed with another delay kernel (see _with\_delay_)
+	Delayed { target: Box<Target>, kernel: Vec<f64> }
+}
+
+impl Target {
+	/// Observation delay kernel of the target in the observation model _observation_ (empty for weighted sums, their components have their own)
+	pub fn observation_delay<'a>(&'a self, observation: &'a ObservationModel) -> &'a [f64] {
+		match self {
+			Target::ObservedCases | Target::TestPositivity { .. } | Target::AgeIncidence { .. } => &observation.case_delay,
+			Target::ICUOccupancy => &[1.0],
+			Target::ICUAdmissions => &observation.admission_delay,
+			Target::Deaths => &observation.death_delay,
+			Target::Weighted(_) => &[],
+			Target::Delayed { kernel, .. } => kernel
+		}
+	}
+
+	/// The same target observed with the delay kernel _kernel_ (weights for 0, 1, 2, ... days ago).
+	/// For a weighted sum the kernel is used for all components.
+	pub fn with_delay(self, kernel: Vec<f64>) -> Target {
+		match self {
+			Target::Delayed { target, .. } => Target::Delayed { target, kernel },
+			target => Target::Delayed { target: Box::new(target), kernel }
+		}
+	}
+
+	/// Whether the target is a stock like the ICU occupancy (rather than a daily rate)
+	pub fn is_stock(&self) -> bool {
+		match self {
+			Target::ICUOccupancy => true,
+			Target::Delayed { target, .. } => target.is_stock(),
+			_ => false
+		}
+	}
+
+	/// Checks the parameters of the target (positive tests per day, non-empty kernels and sums, finite weights)
+	pub fn is_valid(&self) -> bool {
+		match self {
+			Target::TestPositivity { tests_per_day } => *tests_per_day > 0.0 && tests_per_day.is_finite(),
+			Target::AgeIncidence { age_groups } => !age_groups.is_empty(),
+			Target::Weighted(components) => !components.is_empty() && components.iter().all(|(weight, target)| weight.is_finite() && target.is_valid()),
+			Target::Delayed { target, kernel } => !kernel.is_empty() && kernel.iter().all(|w| w.is_finite()) && target.is_valid(),
+			_ => true
+		}
+	}
+
+	/// Observed value of the target at index k of _series_ (see the module documentation)
+	pub(crate) fn observe<M: DelaySystem>(&self, model: &M, observation: &ObservationModel, series: &Series<M::State>, k: usize) -> f64 {
+		match self {
+			Target::Weighted(components) => components.iter().map(|(weight, target)| weight*target.observe(model, observation, series, k)).sum(),
+			Target::Delayed { target, kernel } => target.observe_with(kernel, model, observation, series, k),
+			target => target.observe_with(target.observation_delay(observation), model, observation, series, k)
+		}
+	}
+
+	/// Observed value of the target at index k of _series_ with the delay kernel _kernel_
+	fn observe_with<M: DelaySystem>(&self, kernel: &[f64], model: &M, observation: &ObservationModel, series: &Series<M::State>, k: usize) -> f64 {
+		let one_day = (1./series.dt) as usize;
+		match self {
+			Target::Weighted(components) => components.iter().map(|(weight, target)| weight*target.observe_with(kernel, model, observation, series, k)).sum(),
+			Target::Delayed { target, .. } => target.observe_with(kernel, model, observation, series, k),
+			Target::ICUOccupancy => {
+				kernel.iter().enumerate().map(|(day, weight)| weight*model.ICU_occupancy(series.state(k.saturating_sub(one_day*day)))).sum()
+			},
+			target => {
+				let mut observed = 0.0;
+				for (day, weight) in kernel.iter().enumerate() {
+					if k >= one_day*(day+1) {
+						observed += (k-one_day*(day+1)..k-one_day*day).map(|i| target.rate(model, observation, series, i)).sum::<f64>()*series.dt * weight;
+					} else {
+						observed += target.rate(model, observation, series, 0) * weight;	// before the start of the simulation
+					}
+				}
+				if let Target::TestPositivity { .. } = target {
+					observed
+				} else {
+					// Reported during the day ending at t
+					observed * observation.reporting_factor(series.time(k) - 0.5*series.dt)
+				}
+			}
+		}
+	}
+
+	/// Instantaneous daily rate of a rate target at index k of _series_. Without age-specific ascertainment the daily infections are taken from the
+	/// solver results, so that e.g. the stochastic runs observe their realized infections.
+	fn rate<M: DelaySystem>(&self, model: &M, observation: &ObservationModel, series: &Series<M::State>, k: usize) -> f64 {
+		let ascertained_cases = || if observation.ascertainment.is_empty() {
+			series.N(k)
+		} else {
+			(0..observation.ascertainment.len()).map(|block| observation.ascertained(block)*model.N_block(series.state(k), block)).sum()
+		};
+		match self {
+			Target::ObservedCases => ascertained_cases(),
+			Target::ICUAdmissions => model.ICU_admissions(series.state(k)),
+			Target::Deaths => model.deaths(series.state(k)),
+			Target::TestPositivity { tests_per_day } => ascertained_cases()/tests_per_day,
+			Target::AgeIncidence { age_groups } => age_groups.iter().map(|&block| observation.ascertained(block)*model.N_block(series.state(k), block)).sum(),
+			Target::ICUOccupancy | Target::Weighted(_) | Target::Delayed { .. } => unreachable!("not a rate")
+		}
+	}
+}
+
+/// The results of the solver up to index _end_ continued by a preview (if any), seen as one series on the grid dt.
+///
+/// Observables of the past and of the preview are computed from this view, so that the delay kernels reaching before the preview
+/// see the real history.
+pub(crate) struct Series<'a, S> {
+	/// States of the results
+	pub(crate) states: &'a States<S>,
+	/// Daily infections of the results
+	pub(crate) N: &'a [f64],
+	/// Last index of the results that belongs to the series
+	pub(crate) end: usize,
+	/// States and daily infections of the preview starting after _end_
+	pub(crate) preview: Option<(&'a States<S>, &'a [f64])>,
+	/// Time at index 0
+	pub(crate) t0: f64,
+	/// Time step
+	pub(crate) dt: f64
+}
+
+impl<'a, S: Copy> Series<'a, S> {
+	/// System state at index k (indices after _end_ are in the preview)
+	pub(crate) fn state(&self, k: usize) -> &'a [S] {
+		match self.preview {
+			Some((states, _)) if k > self.end => &states[k-self.end-1],
+			_ => &self.states[k]
+		}
+	}
+
+	/// Time at index k
+	pub(crate) fn time(&self, k: usize) -> f64 {
+		self.t0 + (k as f64)*self.dt
+	}
+
+	/// Daily infections at index k as stored by the solver (indices after _end_ are in the preview)
+	pub(crate) fn N(&self, k: usize) -> f64 {
+		match self.preview {
+			Some((_, N)) if k > self.end => N[k-self.end-1],
+			_ => self.N[k]
+		}
+	}
+}
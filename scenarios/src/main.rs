@@ -12,6 +12,37 @@ fn main() {
 	//println!("Generating data...");
 	//// Retrieve the parameters
 	let args: Vec<String> = env::args().collect();
+
+	// Rerun (optional): "rerun", the manifest written with a run and the folder for the results
+	if args[1] == "rerun" {
+		let manifest = vm::Manifest::read_from_disk(&args[2]).expect("Reading the manifest failed");
+		let solver = manifest.rerun().expect("Running the scenario failed");
+		solver.write_to_disk(&args[3], (1./solver.dt) as usize /5).expect("Writing Failed");
+		manifest.write_to_disk(&args[3]).expect("Writing Failed");
+		if let Some(ICU_capacity) = manifest.ICU_capacity {
+			vm::write_tables(&mut vm::Directory::data(&args[3]), &[solver.summary(ICU_capacity).table()], &vm::Tsv::default()).expect("Writing Failed");
+		}
+		return;
+	}
+
+	// Uncertainty runs (optional), followed by the scenario arguments below:
+	// - "posterior", the posterior samples of eta, kappa, sigma, seroprevalence, total_uptake and/or TTI_factor written by Posterior::write_to_disk
+	//   and the number of draws,
+	// - "ensemble" and the number of runs.
+	let (uncertainty, args) = if args[1] == "posterior" {
+		let posterior = vm::Posterior::read_from_disk(&args[2]).expect("Reading the posterior failed");
+		if let Some(name) = posterior.names.iter().find(|name| !UNCERTAIN_INPUTS.contains(&name.as_str())) {
+			eprintln!("Unknown parameter {} in the posterior (supported: {})", name, UNCERTAIN_INPUTS.join(", "));
+			std::process::exit(1);
+		}
+		let draws = args[3].parse::<usize>().unwrap();
+		(Some(Uncertainty::Posterior(posterior, draws)), &args[3..])
+	} else if args[1] == "ensemble" {
+		let runs = args[2].parse::<usize>().unwrap();
+		(Some(Uncertainty::Ensemble(runs)), &args[2..])
+	} else {
+		(None, &args[..])
+	};
 	
 	// Model parameters
 	let scenario: &String = &args[1];	// ["I", "II", "III", "IV", "V", "IV*", "V*"]
@@ -46,7 +77,120 @@ fn main() {
 	let R_max_capped = args[15].parse::<f64>().unwrap();			//2.5
 	let R_capped = args[16].parse::<f64>().unwrap();				//1.5
 
+	let inputs = Inputs { seroprevalence, influx, kappa, eta, sigma, total_uptake, TTI_factor };
+	let t0: f64 = 9.*7.;			// t0=0 corresponds to the beginning of the vaccination programs (last week of December for Europe)
+	let T:  f64 = 350.0;			// total integration time
+
+
+	// Phases of the scenarios
+	let cases = |until: f64, max_Rt: f64, max_slope: f64, aim: f64| vm::Phase::new(vm::Target::ObservedCases, aim).until_time(until).Rt_range(0.8, max_Rt).max_slope(max_slope);
+	let ICU = |min_Rt: f64, max_Rt: f64, max_slope: f64| vm::Phase::new(vm::Target::ICUOccupancy, ICU_capacity).Rt_range(min_Rt, max_Rt).max_slope(max_slope);
+
+	// Run selected scenario
+	let phases = if scenario == "I" {	// Scenario 1: Full ICU occupancy until pop. immunity
+		vec![ICU(0.8, R_max, 1.0)]
+	} else if scenario == "II" {	// Scenario 2: Medium case numbers (early lift)
+		vec![cases(126., R_max, 0.02, mod_case_numbers), ICU(0.7, R_max, 0.07)]
+	} else if scenario == "III" {	// Scenario 3: Medium case numbers (medium late lift)
+		vec![cases(189., R_max, 0.02, mod_case_numbers), ICU(0.7, R_max, 0.05)]
+	} else if scenario == "IV" {	// Scenario 5: Low case numbers forever
+		vec![cases(237., R_max, 0.02, mod_case_numbers), ICU(0.7, R_max, 0.03)]
+	} else if scenario == "V" {	// Scenario 5: Low case numbers forever
+		vec![vm::Phase::new(vm::Target::ObservedCases, low_case_numbers).Rt_range(0.8, R_max).max_slope(1.0)]
+	} else if scenario == "IV*" {	// Scenario 4*: Medium case numbers (late lift) (capped at Rt=2.5)
+		vec![cases(237., R_max_capped, 0.02, mod_case_numbers), ICU(0.7, R_max_capped, 0.03)]
+	} else if scenario == "V*" {	// Scenario 5*: use vaccinations first to lift restrictions and retrieve some normality. Then keep contacts constant to bring down case numbers.
+		vec![cases(189., R_capped, 0.02, mod_case_numbers), cases(236., R_max_capped, 0.01, mod_case_numbers), ICU(0.8, R_max, 0.05)]
+	} else {
+		Vec::new()
+	};
+	let schedule = if phases.is_empty() { None } else { Some(vm::Schedule::new(phases).expect("Invalid scenario schedule")) };
+	let run = |inputs: &Inputs| {
+		let mut solver = build_solver(country, contacts, &M_per_country, inputs, t0, T);
+		if let Some(schedule) = &schedule {
+			solver.controlled_run(T, schedule).expect("Running the scenario failed");
+		}
+		solver
+	};
+	let folder = format!("{}_{}_{}_sero{:.2}_influx{:.1}_kappa{:.2}_eta{:.2}_sigma{:.2}_uptake{:.2}_ICU{:.1}_TTI{:.1}", scenario, country, contacts, seroprevalence, influx/M_per_country[0]*1e6, kappa, eta, sigma, total_uptake, ICU_capacity/M_per_country[0]*1e6, TTI_factor);
+
+	// Uncertainty ensemble: the seroprevalence, the efficacies and the uptake are sampled with a Latin hypercube from the ranges of the
+	// sensitivity analysis, the quantile bands of all time series are written to the data folder.
+	if let Some(Uncertainty::Ensemble(runs)) = uncertainty {
+		let factors = [vm::Factor::new("seroprevalence", 0.05, 0.15), vm::Factor::new("kappa", 0.83, 0.97), vm::Factor::new("eta", 0.60, 0.90),
+					   vm::Factor::new("sigma", 0.25, 1.00), vm::Factor::new("total_uptake", 0.60, 0.80)];
+		let names: Vec<String> = factors.iter().map(|factor| factor.name.clone()).collect();
+		let sweep = vm::Sweep::new().print_progress("Ensemble runs");
+		let ensemble = vm::EnsembleDesign::new(runs, 0).run(&factors, &sweep, |values| run(&inputs.with(&names, values)));
+		ensemble.write_to_disk(&folder, &vm::QUANTILES).expect("Writing Failed");
+		return;
+	}
+
+	// Posterior predictive runs: the inputs not in the posterior keep the values given above
+	if let Some(Uncertainty::Posterior(posterior, draws)) = uncertainty {
+		let sweep = vm::Sweep::new().print_progress("Posterior predictive runs");
+		let runs = posterior.predictive(draws, 0, &sweep, |sample| run(&inputs.with(&posterior.names, sample)).expected_surveillance(vm::Aggregation::Daily))
+			.expect("Posterior predictive runs failed");
+		vm::SurveillanceData::write_quantiles(&runs, &folder, &[0.05, 0.25, 0.5, 0.75, 0.95]).expect("Writing Failed");
+		return;
+	}
+
+	// Write results into data folder, together with the manifest to repeat the run and the summary metrics
+	let solver = run(&inputs);
+	solver.write_to_disk(folder.as_str(), (1./solver.dt) as usize /5).expect("Writing Failed");
+	let names = ["scenario", "country", "contacts", "seroprevalence", "influx", "kappa", "eta", "sigma", "total_uptake", "ICU_capacity", "TTI_factor",
+				 "low_case_numbers", "mod_case_numbers", "R_max", "R_max_capped", "R_capped"];
+	let manifest = names.iter().zip(args[1..].iter()).fold(vm::Manifest::new(&solver, schedule.as_ref(), T).expect("Recording the run failed"),
+														   |manifest, (name, value)| manifest.metadata(name, value)).ICU_capacity(ICU_capacity);
+	manifest.write_to_disk(folder.as_str()).expect("Writing Failed");
+	vm::write_tables(&mut vm::Directory::data(&folder), &[solver.summary(ICU_capacity).table()], &vm::Tsv::default()).expect("Writing Failed");
+}
+
+/// Inputs that can be drawn from a posterior or sampled for an ensemble (see Inputs::with)
+const UNCERTAIN_INPUTS: [&str; 6] = ["eta", "kappa", "sigma", "seroprevalence", "total_uptake", "TTI_factor"];
+
+/// Runs over the uncertain inputs (see main)
+enum Uncertainty {
+	/// Posterior predictive runs with the number of draws
+	Posterior(vm::Posterior, usize),
+	/// Ensemble with the number of runs
+	Ensemble(usize)
+}
+
+/// Uncertain inputs of the scenarios (can be drawn from a posterior or sampled for an ensemble, see main)
+#[derive(Clone, Copy)]
+struct Inputs {
+	seroprevalence: f64,
+	influx: f64,
+	kappa: f64,
+	eta: f64,
+	sigma: f64,
+	total_uptake: f64,
+	TTI_factor: f64
+}
+
+impl Inputs {
+	/// The inputs with the named ones (eta, kappa, sigma, seroprevalence, total_uptake, TTI_factor) replaced by _values_
+	fn with(&self, names: &[String], values: &[f64]) -> Inputs {
+		let mut inputs = *self;
+		for (name, value) in names.iter().zip(values) {
+			match name.as_str() {
+				"eta" => inputs.eta = *value,
+				"kappa" => inputs.kappa = *value,
+				"sigma" => inputs.sigma = *value,
+				"seroprevalence" => inputs.seroprevalence = *value,
+				"total_uptake" => inputs.total_uptake = *value,
+				"TTI_factor" => inputs.TTI_factor = *value,
+				_ => panic!("Unknown input {}", name)
+			}
+		}
+		inputs
+	}
+}
 
+/// Creates the model for the country and contact structure with the given inputs and the initialized solver starting at t0 (for runs up to t0+T)
+fn build_solver(country: &str, contacts: &str, M_per_country: &[f64; 7], inputs: &Inputs, t0: f64, T: f64) -> vm::Solver {
+	let Inputs { seroprevalence, influx, kappa, eta, sigma, total_uptake, TTI_factor } = *inputs;
 
 	//// Define the model
 	let mut model = vm::Model {
@@ -260,18 +404,16 @@ fn main() {
 		age_distribution_EI[i] = model.age_groups[i].M/model.M;
 	}
 
-	let t0: f64 = 9.*7.;			// t0=0 corresponds to the beginning of the vaccination programs (last week of December for Europe)
-	let T:  f64 = 350.0;			// total integration time
 
 	
-	model.initialize();		// adds the subpopulations
-	model.prepare_vaccination_rates(((t0+T)/7.0).ceil() as usize +10, total_uptake);	// prepares the vaccination rates for 50 weeks in advance
+	model.initialize().expect("Invalid model parameters");		// adds the subpopulations
+	model.prepare_vaccination_rates(((t0+T)/7.0).ceil() as usize +10, total_uptake).expect("Preparing the vaccination rates failed");	// prepares the vaccination rates for 50 weeks in advance
 
 	let mut initials = Vec::<vm::AgeGroupStateVector>::with_capacity(6);
 
 	for i in 0..6 {
-		let (vaccinated, vaccinated2) = model.vaccinated_between(0.0, t0, i);
-		let (in_V1, in_V2) = model.vaccinated_between(t0-model.tau, t0, i);
+		let (vaccinated, vaccinated2) = model.vaccinated_between(0.0, t0, i).expect("Vaccination rates not prepared");
+		let (in_V1, in_V2) = model.vaccinated_between(t0-model.tau, t0, i).expect("Vaccination rates not prepared");
 		
 		initials.push(vm::AgeGroupStateVector::create_initial(model.age_groups[i].M, seroprevalence, vaccinated, (vaccinated2/vaccinated).max(0.0), model.eta0, in_V1, in_V2, in_EI*age_distribution_EI[i], in_ICU*age_distribution_ICU[i]));
 	}
@@ -283,36 +425,21 @@ fn main() {
 		model: model,
 		t0: t0,
 		dt: 1e-2,
+		integrator: vm::Integrator::RK4,
+		observation: vm::ObservationModel::default(),
 		initials: initials,
 		time: Vec::with_capacity(N),
 		Rt: Vec::with_capacity(N),
-		states: Vec::with_capacity(N),
+		states: vm::States::with_capacity(6, N),
 		N: Vec::with_capacity(N),
 		N_obs: Vec::with_capacity(N),
-		index: 0
+		index: 0,
+		events: Vec::new(),
+		event_log: Vec::new()
 	};
 	
 
 	// Initialize solver
-	solver.initialize();
-
-	// Run selected scenario
-	if scenario == "I" {	// Scenario 1: Full ICU occupancy until pop. immunity
-		solver.controlled_run(T, &[(1025., 0.8, R_max, 1.0, 1, ICU_capacity)]);
-	} else if scenario == "II" {	// Scenario 2: Medium case numbers (early lift)
-		solver.controlled_run(T, &[(126., 0.8, R_max, 0.02, 0, mod_case_numbers), (t0+T+280., 0.7, R_max, 0.07, 1, ICU_capacity)]);
-	} else if scenario == "III" {	// Scenario 3: Medium case numbers (medium late lift)
-		solver.controlled_run(T, &[(189., 0.8, R_max, 0.02, 0, mod_case_numbers), (t0+T+280., 0.7, R_max, 0.05, 1, ICU_capacity)]);
-	} else if scenario == "IV" {	// Scenario 5: Low case numbers forever
-		solver.controlled_run(T, &[(237., 0.8, R_max, 0.02, 0, mod_case_numbers), (t0+T+280., 0.7, R_max, 0.03, 1, ICU_capacity)]);
-	} else if scenario == "V" {	// Scenario 5: Low case numbers forever
-		solver.controlled_run(T, &[(1025., 0.8, R_max, 1.0, 0, low_case_numbers)]);
-	} else if scenario == "IV*" {	// Scenario 4*: Medium case numbers (late lift) (capped at Rt=2.5)
-		solver.controlled_run(T, &[(237., 0.8, R_max_capped, 0.02, 0, mod_case_numbers), (t0+T+280., 0.7, R_max_capped, 0.03, 1, ICU_capacity)]);
-	} else if scenario == "V*" {	// Scenario 5*: use vaccinations first to lift restrictions and retrieve some normality. Then keep contacts constant to bring down case numbers.
-		solver.controlled_run(T, &[(189., 0.8, R_capped, 0.02, 0, mod_case_numbers), (236., 0.8, R_max_capped, 0.01, 0, mod_case_numbers), (t0+T+280., 0.8, R_max, 0.05, 1, ICU_capacity)]);
-	}
-
-	// Write results into data folder
-	solver.write_to_disk(format!("{}_{}_{}_sero{:.2}_influx{:.1}_kappa{:.2}_eta{:.2}_sigma{:.2}_uptake{:.2}_ICU{:.1}_TTI{:.1}", scenario, country, contacts, seroprevalence, influx/M_per_country[0]*1e6, kappa, eta, sigma, total_uptake, ICU_capacity/M_per_country[0]*1e6, TTI_factor).as_str(), (1./solver.dt) as usize /5).expect("Writing Failed");
+	solver.initialize().expect("Initializing the solver failed");
+	solver
 }